@@ -0,0 +1,189 @@
+//! Burst sequences.
+//!
+//! The frames of a burst are linked by an identifier where the camera writes
+//! one, like the burst UUID in Apple MakerNotes. Otherwise at least
+//! [`MIN_BURST_LENGTH`] consecutive frames from the same camera and directory,
+//! each taken within the burst threshold of the previous one, form a burst.
+
+use crate::{group::Group, maker_note};
+use chrono::{NaiveDateTime, TimeDelta};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+/// Apple MakerNote tag of the burst UUID.
+const BURST_IDENTIFIER_TAG: u16 = 0x000b;
+
+/// Minimum number of frames of a burst detected by capture time.
+const MIN_BURST_LENGTH: usize = 3;
+
+/// Returns the burst identifier from an Apple MakerNote.
+pub fn burst_identifier(meta_data: &exif::Exif) -> Option<String> {
+    maker_note::apple_string(meta_data, BURST_IDENTIFIER_TAG)
+}
+
+/// Marks the items of all groups that are frames of a burst, see `{burst}`.
+///
+/// A burst is named after the file stem of its first frame.
+pub(crate) fn mark_bursts(groups: &mut [Group], threshold: TimeDelta) {
+    let mut by_identifier: HashMap<&str, Vec<(NaiveDateTime, usize)>> =
+        HashMap::new();
+    let mut by_camera: BTreeMap<_, Vec<(NaiveDateTime, usize)>> =
+        BTreeMap::new();
+
+    for (index, group) in groups.iter().enumerate() {
+        let Some(metadata) = group.metadata() else {
+            continue;
+        };
+        let frame = (metadata.time_stamp, index);
+
+        match metadata.burst_identifier.as_deref() {
+            Some(identifier) => {
+                by_identifier.entry(identifier).or_default().push(frame)
+            }
+            None => by_camera
+                .entry((
+                    group.items[0].path.parent().unwrap_or(Path::new("")),
+                    metadata.camera_make.as_deref(),
+                    metadata.camera_model.as_deref(),
+                ))
+                .or_default()
+                .push(frame),
+        }
+    }
+
+    let mut bursts: Vec<Vec<usize>> = Vec::new();
+
+    for mut frames in by_identifier.into_values() {
+        if frames.len() > 1 {
+            frames.sort();
+            bursts.push(frames.into_iter().map(|(_, index)| index).collect());
+        }
+    }
+
+    for mut frames in by_camera.into_values() {
+        frames.sort();
+
+        let mut run: Vec<usize> = Vec::new();
+        let mut previous: Option<NaiveDateTime> = None;
+        for (time_stamp, index) in frames {
+            if previous
+                .is_some_and(|previous| time_stamp - previous > threshold)
+            {
+                bursts.push(std::mem::take(&mut run));
+            }
+            run.push(index);
+            previous = Some(time_stamp);
+        }
+        bursts.push(run);
+    }
+
+    for burst in bursts {
+        if burst.len() < MIN_BURST_LENGTH
+            && groups[burst[0]]
+                .metadata()
+                .is_none_or(|m| m.burst_identifier.is_none())
+        {
+            continue;
+        }
+
+        let name = groups[burst[0]].items[0]
+            .path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        for index in burst {
+            for item in &mut groups[index].items {
+                item.burst = Some(name.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{group::Item, metadata::Metadata};
+    use anyhow::anyhow;
+    use chrono::NaiveDate;
+    use std::path::PathBuf;
+
+    fn frame(path: &str, millisecond: u32, burst: Option<&str>) -> Group {
+        Group {
+            items: vec![Item {
+                path: PathBuf::from(path),
+                sidecars: Vec::new(),
+                metadata: Ok(Metadata {
+                    time_stamp: NaiveDate::from_ymd_opt(2023, 8, 15)
+                        .unwrap()
+                        .and_hms_milli_opt(14, 30, 0, 0)
+                        .unwrap()
+                        + TimeDelta::milliseconds(millisecond as i64),
+                    camera_model: Some("Z9".into()),
+                    burst_identifier: burst.map(String::from),
+                    ..Default::default()
+                }),
+                pair_role: None,
+                live: false,
+                burst: None,
+            }],
+        }
+    }
+
+    fn bursts(groups: &[Group]) -> Vec<Option<&str>> {
+        groups
+            .iter()
+            .map(|group| group.items[0].burst.as_deref())
+            .collect()
+    }
+
+    #[test]
+    fn bursts_by_time() {
+        let mut groups = vec![
+            frame("/card/DSC_0102.NEF", 200, None),
+            frame("/card/DSC_0100.NEF", 0, None),
+            frame("/card/DSC_0101.NEF", 100, None),
+            // Too far from the previous frame.
+            frame("/card/DSC_0103.NEF", 2000, None),
+            // Too short.
+            frame("/card/DSC_0104.NEF", 5000, None),
+            frame("/card/DSC_0105.NEF", 5100, None),
+        ];
+        mark_bursts(&mut groups, TimeDelta::milliseconds(500));
+
+        assert_eq!(
+            bursts(&groups),
+            [
+                Some("DSC_0100"),
+                Some("DSC_0100"),
+                Some("DSC_0100"),
+                None,
+                None,
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn bursts_by_identifier() {
+        let mut groups = vec![
+            frame("/dcim/IMG_0001.HEIC", 0, Some("A")),
+            frame("/dcim/IMG_0002.HEIC", 3000, Some("A")),
+            frame("/dcim/IMG_0003.HEIC", 3100, Some("B")),
+        ];
+        groups.push(Group {
+            items: vec![Item {
+                metadata: Err(anyhow!("No metadata.")),
+                ..frame("/dcim/IMG_0004.HEIC", 0, None).items.remove(0)
+            }],
+        });
+        mark_bursts(&mut groups, TimeDelta::milliseconds(500));
+
+        assert_eq!(
+            bursts(&groups),
+            [Some("IMG_0001"), Some("IMG_0001"), None, None]
+        );
+    }
+}
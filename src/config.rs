@@ -1,14 +1,47 @@
 //! Configuration file loading and management.
 
-use anyhow::Result;
+use crate::{filename_date::FilenameDates, sidecar};
+use anyhow::{Result, anyhow};
+use chrono::TimeDelta;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::{fmt, path::PathBuf, str::FromStr, sync::OnceLock};
 
 /// Default format string for destination paths.
 pub const DEFAULT_FORMAT: &str = "{year}/{month}/{day}/{filename}.{extension}";
 
+/// Default order in which capture date sources are tried.
+pub const DEFAULT_DATE_SOURCES: &[DateSource] = &[
+    DateSource::Xmp,
+    DateSource::Exif,
+    DateSource::Takeout,
+    DateSource::Filename,
+];
+
+/// Default sidecar patterns, see [`sidecar`].
+pub const DEFAULT_SIDECARS: &[&str] = &[
+    // Darktable & co.
+    "{name}.xmp",
+    // Lightroom, Capture One.
+    "{stem}.xmp",
+    // RawTherapee.
+    "{name}.pp3",
+    // DxO.
+    "{name}.dop",
+    // Video thumbnails.
+    "{stem}.thm",
+    // Apple edits.
+    "{stem}.aae",
+    // Google Photos Takeout.
+    "{name}.json",
+    // GoPro low-res proxies.
+    "{stem}.lrv",
+];
+
+/// Default maximum time between two frames of a burst, in seconds.
+pub const DEFAULT_BURST_THRESHOLD: f64 = 1.0;
+
 /// Application name for confy.
-const APP_NAME: &str = "exifmv";
+pub(crate) const APP_NAME: &str = "exifmv";
 
 /// Configuration loaded from TOML file.
 #[derive(Debug, Default, Deserialize, Serialize)]
@@ -16,6 +49,8 @@ const APP_NAME: &str = "exifmv";
 pub struct Config {
     /// Path format template.
     pub format: Option<String>,
+    /// Path format template for files without metadata.
+    pub unsorted: Option<String>,
     /// Change filename & extension to lowercase.
     pub make_lowercase: Option<bool>,
     /// Recurse subdirectories.
@@ -30,23 +65,236 @@ pub struct Config {
     pub dereference: Option<bool>,
     /// Use checksum for duplicate detection instead of size.
     pub checksum: Option<bool>,
+    /// Keep an index of the destination by content to find duplicates
+    /// anywhere in it.
+    pub index: Option<bool>,
+    /// Sources for the capture date, in order of precedence.
+    pub date_sources: Option<Vec<DateSource>>,
+    /// Additional regular expressions matching dates in file names.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub filename_patterns: Vec<String>,
+    /// Sidecar patterns, see [`DEFAULT_SIDECARS`].
+    pub sidecars: Option<Vec<String>>,
+    /// Append a numeric suffix instead of skipping when a different file
+    /// exists at the destination.
+    pub rename_conflicts: Option<bool>,
+    /// Set the modification time of moved files to their capture time.
+    pub set_mtime: Option<bool>,
+    /// Files read or moved at once per device, see [`device`](crate::device).
+    pub device_threads: Option<usize>,
+    /// What to do with the JPEG of a RAW+JPEG pair.
+    pub jpeg_with_raw: Option<JpegWithRaw>,
+    /// Maximum time between two frames of a burst, in seconds.
+    pub burst_threshold: Option<f64>,
+    /// Value of `{place}` if no place matches or GPS data is missing.
+    pub place_fallback: Option<String>,
+    /// Named geofences resolved by the `{place}` template variable.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub place: Vec<Place>,
+    /// Compiled `filename_patterns`.
+    #[serde(skip)]
+    pub(crate) filename_dates: OnceLock<FilenameDates>,
+}
+
+/// Where the capture date of a file is taken from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DateSource {
+    /// Capture date from an XMP sidecar, e.g. as corrected in an editor.
+    Xmp,
+    /// EXIF `DateTimeOriginal` embedded in the file.
+    Exif,
+    /// `photoTakenTime` from a Google Photos Takeout JSON sidecar.
+    Takeout,
+    /// A date in the file name, see `filename-patterns`.
+    Filename,
+}
+
+impl DateSource {
+    /// Names accepted on the command line.
+    pub const NAMES: &[&str] = &["xmp", "exif", "takeout", "filename"];
+}
+
+impl FromStr for DateSource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "xmp" => Ok(Self::Xmp),
+            "exif" => Ok(Self::Exif),
+            "takeout" => Ok(Self::Takeout),
+            "filename" => Ok(Self::Filename),
+            _ => Err(anyhow!("Unknown date source '{}'.", s)),
+        }
+    }
+}
+
+impl fmt::Display for DateSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Xmp => "xmp",
+            Self::Exif => "exif",
+            Self::Takeout => "takeout",
+            Self::Filename => "filename",
+        })
+    }
+}
+
+/// What to do with the JPEG of a RAW+JPEG pair.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum JpegWithRaw {
+    /// Move the JPEG next to its RAW.
+    #[default]
+    Keep,
+    /// Delete the JPEG once its RAW was moved.
+    Remove,
+    /// Move the JPEG to the trash once its RAW was moved.
+    Trash,
+}
+
+impl JpegWithRaw {
+    /// Names accepted on the command line.
+    pub const NAMES: &[&str] = &["keep", "remove", "trash"];
+}
+
+impl FromStr for JpegWithRaw {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "keep" => Ok(Self::Keep),
+            "remove" => Ok(Self::Remove),
+            "trash" => Ok(Self::Trash),
+            _ => Err(anyhow!("Unknown JPEG action '{}'.", s)),
+        }
+    }
+}
+
+impl fmt::Display for JpegWithRaw {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Keep => "keep",
+            Self::Remove => "remove",
+            Self::Trash => "trash",
+        })
+    }
+}
+
+/// A named geofence, configured via `[[place]]` tables.
+///
+/// Either `center` and `radius` or `polygon` must be given.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Place {
+    /// Name substituted for `{place}`.
+    pub name: String,
+    /// Center as `[latitude, longitude]` in decimal degrees.
+    pub center: Option<[f64; 2]>,
+    /// Radius around `center` in meters.
+    pub radius: Option<f64>,
+    /// Outline as a list of `[latitude, longitude]` vertices.
+    pub polygon: Option<Vec<[f64; 2]>>,
 }
 
 impl Config {
     /// Load config from the given path, or the default path if `None`.
     /// Returns default config if file doesn't exist.
     pub fn load(path: Option<&PathBuf>) -> Result<Self> {
-        if let Some(path) = path {
-            Ok(confy::load_path(path)?)
+        let config: Self = if let Some(path) = path {
+            confy::load_path(path)?
         } else {
-            Ok(confy::load(APP_NAME, "config")?)
+            confy::load(APP_NAME, "config")?
+        };
+
+        if let Some(place) = config.place.iter().find(|p| !p.is_valid()) {
+            return Err(anyhow!(
+                "Place '{}' needs either `center` and `radius` or a \
+                 `polygon` with at least three vertices.",
+                place.name
+            ));
         }
+
+        config.sidecar_patterns()?;
+
+        if config
+            .burst_threshold
+            .is_some_and(|t| !t.is_finite() || t < 0.0)
+        {
+            return Err(anyhow!("`burst-threshold` must not be negative."));
+        }
+
+        // Surface invalid filename patterns early.
+        let filename_dates = FilenameDates::new(&config.filename_patterns)?;
+        config.filename_dates.set(filename_dates).unwrap();
+
+        Ok(config)
     }
 
     /// Returns the format string, using default if not specified.
     pub fn format(&self) -> &str {
         self.format.as_deref().unwrap_or(DEFAULT_FORMAT)
     }
+
+    /// Returns the date sources in order of precedence.
+    pub fn date_sources(&self) -> &[DateSource] {
+        self.date_sources.as_deref().unwrap_or(DEFAULT_DATE_SOURCES)
+    }
+
+    /// Returns the maximum time between two frames of a burst.
+    pub fn burst_threshold(&self) -> TimeDelta {
+        TimeDelta::microseconds(
+            (self.burst_threshold.unwrap_or(DEFAULT_BURST_THRESHOLD) * 1e6)
+                as i64,
+        )
+    }
+
+    /// Returns the compiled filename date patterns.
+    ///
+    /// Invalid user patterns are reported by [`Config::load()`]; if the config
+    /// was created otherwise they are ignored.
+    pub fn filename_dates(&self) -> &FilenameDates {
+        self.filename_dates.get_or_init(|| {
+            FilenameDates::new(&self.filename_patterns).unwrap_or_default()
+        })
+    }
+
+    /// Returns the parsed sidecar patterns.
+    pub fn sidecar_patterns(&self) -> Result<Vec<sidecar::Pattern>> {
+        match &self.sidecars {
+            Some(sidecars) => sidecars
+                .iter()
+                .map(|p| sidecar::Pattern::parse(p))
+                .collect(),
+            None => DEFAULT_SIDECARS
+                .iter()
+                .map(|p| sidecar::Pattern::parse(p))
+                .collect(),
+        }
+    }
+
+    /// Adds sidecar `patterns` to the configured ones.
+    pub fn add_sidecars(&mut self, patterns: &[&str]) {
+        self.sidecars
+            .get_or_insert_with(|| {
+                DEFAULT_SIDECARS.iter().map(|p| p.to_string()).collect()
+            })
+            .extend(patterns.iter().map(|p| p.to_string()));
+    }
+
+    /// Returns the place name for `{place}` at the given coordinates.
+    ///
+    /// Falls back to `place-fallback` if no `[[place]]` matches or there are
+    /// no coordinates.
+    pub fn place_name(&self, gps: Option<(f64, f64)>) -> Option<String> {
+        gps.and_then(|(latitude, longitude)| {
+            crate::place::find_place(&self.place, latitude, longitude)
+        })
+        .map(|place| place.name.clone())
+        .or_else(|| self.place_fallback.clone())
+    }
 }
 
 #[cfg(test)]
@@ -60,6 +308,8 @@ format = "{year}-{month}-{day}/{filename}.{extension}"
 make-lowercase = true
 day-wrap = "04:00"
 verbose = false
+jpeg-with-raw = "trash"
+burst-threshold = 0.25
 "#;
         let config: Config = toml::from_str(toml).unwrap();
         assert_eq!(
@@ -69,6 +319,76 @@ verbose = false
         assert_eq!(config.make_lowercase, Some(true));
         assert_eq!(config.day_wrap.as_deref(), Some("04:00"));
         assert_eq!(config.verbose, Some(false));
+        assert_eq!(config.jpeg_with_raw, Some(JpegWithRaw::Trash));
+        assert_eq!(config.burst_threshold(), TimeDelta::milliseconds(250));
+    }
+
+    #[test]
+    fn parse_places() {
+        let toml = r#"
+place-fallback = "elsewhere"
+
+[[place]]
+name = "home"
+center = [52.52, 13.405]
+radius = 150.0
+
+[[place]]
+name = "studio"
+polygon = [[0.0, 0.0], [0.0, 1.0], [1.0, 1.0]]
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+        assert_eq!(config.place.len(), 2);
+        assert_eq!(config.place[0].name, "home");
+        assert_eq!(config.place[1].polygon.as_ref().unwrap().len(), 3);
+        assert_eq!(
+            config.place_name(Some((52.52, 13.405))).as_deref(),
+            Some("home")
+        );
+        assert_eq!(
+            config.place_name(Some((40.0, -70.0))).as_deref(),
+            Some("elsewhere")
+        );
+        assert_eq!(config.place_name(None).as_deref(), Some("elsewhere"));
+    }
+
+    #[test]
+    fn parse_date_sources() {
+        let toml = r#"
+date-sources = ["filename", "exif"]
+filename-patterns = ['^holiday_(?<year>\d{4})(?<month>\d{2})(?<day>\d{2})']
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+        assert_eq!(
+            config.date_sources(),
+            &[DateSource::Filename, DateSource::Exif]
+        );
+        assert!(
+            config
+                .filename_dates()
+                .parse("holiday_20230815.jpg")
+                .is_some()
+        );
+
+        let config = Config::default();
+        assert_eq!(config.date_sources(), DEFAULT_DATE_SOURCES);
+    }
+
+    #[test]
+    fn parse_sidecars() {
+        let config: Config =
+            toml::from_str(r#"sidecars = ["{stem}.xmp", "{name}.pp3"]"#)
+                .unwrap();
+        assert_eq!(config.sidecar_patterns().unwrap().len(), 2);
+
+        let config: Config = toml::from_str(r#"sidecars = ["*.xmp"]"#).unwrap();
+        assert!(config.sidecar_patterns().is_err());
+
+        let config = Config::default();
+        assert_eq!(
+            config.sidecar_patterns().unwrap().len(),
+            DEFAULT_SIDECARS.len()
+        );
     }
 
     #[test]
@@ -0,0 +1,283 @@
+//! Storage devices: free space and throttling.
+//!
+//! Moves within a file system are renames; only moves to another one (and
+//! `import --copy`) need space at the destination. That space is checked
+//! before anything is moved.
+//!
+//! Reading many files at once from a spinning disk or a memory card makes it
+//! seek back and forth and slows everything down. So on Linux, files are read
+//! from and moved from or to such devices one at a time; `--device-threads`
+//! sets the limit for all devices.
+
+use crate::group::Group;
+use anyhow::{Result, anyhow};
+use indicatif::HumanBytes;
+use log::{info, warn};
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Condvar, Mutex},
+};
+
+/// Returns the device the file system of `path` is on, or of its closest
+/// existing ancestor.
+pub(crate) fn device(path: &Path) -> Option<u64> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        path.ancestors()
+            .map(|path| {
+                if path.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    path
+                }
+            })
+            .find_map(|path| path.metadata().ok())
+            .map(|metadata| metadata.dev())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+/// Returns the space available to us on the file system of `path`, or of
+/// its closest existing ancestor.
+pub(crate) fn free_space(path: &Path) -> Option<u64> {
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    {
+        let path = path.ancestors().find(|path| path.exists())?;
+        let stat = rustix::fs::statvfs(path).ok()?;
+        #[allow(clippy::unnecessary_cast)]
+        Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+/// Fails if moving `groups` to `dest_dir` needs more space than is free
+/// there. With `warn_only`, only warns.
+///
+/// Files on the file system of `dest_dir` are renamed and need none, unless
+/// `copy` is set.
+pub(crate) fn check_free_space(
+    groups: &[Group],
+    dest_dir: &Path,
+    copy: bool,
+    warn_only: bool,
+) -> Result<()> {
+    let (Some(dest_device), Some(available)) =
+        (device(dest_dir), free_space(dest_dir))
+    else {
+        return Ok(());
+    };
+
+    let needed: u64 = groups
+        .iter()
+        .flat_map(|group| &group.items)
+        .filter(|item| copy || device(&item.path) != Some(dest_device))
+        .flat_map(|item| {
+            std::iter::once(&item.path)
+                .chain(item.sidecars.iter().map(|sidecar| &sidecar.path))
+        })
+        .filter_map(|path| path.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum();
+    if needed == 0 {
+        return Ok(());
+    }
+
+    let message = format!(
+        "Copying needs up to {} in {}, {} are free.",
+        HumanBytes(needed),
+        dest_dir.display(),
+        HumanBytes(available)
+    );
+    if needed <= available {
+        info!("{}", message);
+        Ok(())
+    } else if warn_only {
+        warn!("{}", message);
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "{} Use --ignore-free-space to start anyway.",
+            message
+        ))
+    }
+}
+
+/// Limits how many files are read or moved at once per device.
+#[derive(Debug)]
+pub(crate) struct Throttle {
+    /// Applies to all devices; otherwise slow ones get one at a time.
+    limit: Option<usize>,
+    /// Limit and files in progress per device.
+    devices: Mutex<HashMap<u64, (Option<usize>, usize)>>,
+    finished: Condvar,
+}
+
+impl Throttle {
+    pub fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            devices: Mutex::new(HashMap::new()),
+            finished: Condvar::new(),
+        }
+    }
+
+    /// Runs `f`, which works on `paths`, once the devices of all of them have
+    /// a free slot.
+    pub fn run<T>(&self, paths: &[&Path], f: impl FnOnce() -> T) -> T {
+        let mut ids: Vec<_> =
+            paths.iter().filter_map(|path| device(path)).collect();
+        ids.sort_unstable();
+        ids.dedup();
+
+        // All slots are taken at once, so two callers never wait for each
+        // other's.
+        {
+            let mut devices = self.devices.lock().unwrap();
+            loop {
+                for id in &ids {
+                    devices.entry(*id).or_insert_with(|| {
+                        (self.limit.or_else(|| is_slow(*id).then_some(1)), 0)
+                    });
+                }
+                if ids.iter().all(|id| {
+                    let (limit, running) = devices[id];
+                    limit.is_none_or(|limit| running < limit)
+                }) {
+                    for id in &ids {
+                        devices.get_mut(id).unwrap().1 += 1;
+                    }
+                    break;
+                }
+                devices = self.finished.wait(devices).unwrap();
+            }
+        }
+
+        let result = f();
+
+        {
+            let mut devices = self.devices.lock().unwrap();
+            for id in &ids {
+                devices.get_mut(id).unwrap().1 -= 1;
+            }
+        }
+        self.finished.notify_all();
+
+        result
+    }
+}
+
+/// Returns `true` if `device` is a spinning disk or removable, e.g. a memory
+/// card.
+fn is_slow(device: u64) -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        let (major, minor) = (libc::major(device), libc::minor(device));
+        let Ok(path) =
+            std::fs::canonicalize(format!("/sys/dev/block/{major}:{minor}"))
+        else {
+            return false;
+        };
+
+        // Partitions have the attributes in their disk's folder.
+        let slow = path.ancestors().take(2).any(|path| {
+            ["queue/rotational", "removable"].iter().any(|attribute| {
+                std::fs::read_to_string(path.join(attribute))
+                    .is_ok_and(|value| value.trim() == "1")
+            })
+        }) || path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with("mmcblk"));
+        if slow {
+            info!(
+                "Accessing {} one file at a time.",
+                path.file_name().unwrap_or_default().to_string_lossy()
+            );
+        }
+
+        slow
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = device;
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::group::Item;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+        time::Duration,
+    };
+    use tempfile::TempDir;
+
+    #[test]
+    fn throttle_limits_files_per_device() {
+        let tmp = TempDir::new().unwrap();
+        let throttle = Throttle::new(Some(2));
+        let running = AtomicUsize::new(0);
+        let most = AtomicUsize::new(0);
+
+        thread::scope(|scope| {
+            for _ in 0..6 {
+                scope.spawn(|| {
+                    throttle.run(&[tmp.path(), tmp.path()], || {
+                        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        most.fetch_max(now, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(20));
+                        running.fetch_sub(1, Ordering::SeqCst);
+                    })
+                });
+            }
+        });
+
+        if device(tmp.path()).is_some() {
+            assert_eq!(most.load(Ordering::SeqCst), 2);
+        }
+    }
+
+    #[test]
+    fn free_space_is_needed_for_copies_only() {
+        let tmp = TempDir::new().unwrap();
+        let Some(available) = free_space(tmp.path()) else {
+            return;
+        };
+        // Sparse, so it takes no space itself.
+        let path = tmp.path().join("large.jpg");
+        let file = std::fs::File::create(&path).unwrap();
+        if file.set_len(available + 1).is_err() {
+            return;
+        }
+        let groups = [Group {
+            items: vec![Item {
+                path,
+                sidecars: Vec::new(),
+                metadata: Err(anyhow!("Not read.")),
+                pair_role: None,
+                live: false,
+                burst: None,
+            }],
+        }];
+        let dest_dir = tmp.path().join("dest");
+
+        assert!(check_free_space(&groups, &dest_dir, false, false).is_ok());
+        let error =
+            check_free_space(&groups, &dest_dir, true, false).unwrap_err();
+        assert!(error.to_string().contains("--ignore-free-space"));
+        assert!(check_free_space(&groups, &dest_dir, true, true).is_ok());
+    }
+}
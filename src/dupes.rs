@@ -0,0 +1,382 @@
+//! Finding duplicates in a library.
+//!
+//! Files are compared by size first, then by the XXH3 hash of their first and
+//! last chunk and only then by the hash of their full contents, so most files
+//! are never read completely.
+
+use crate::{
+    device::Throttle,
+    sidecar::{self, Pattern},
+    util::{file_hash, file_kind},
+};
+use anyhow::{Context, Result, anyhow};
+use log::{info, warn};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs,
+    hash::Hash,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use xxhash_rust::xxh3::Xxh3;
+
+/// Size of the chunks read from the start and the end of a file for the
+/// partial hash.
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Which file of a set of duplicates to keep.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Keep {
+    /// The file modified the longest time ago.
+    #[default]
+    Oldest,
+    /// The file with the shortest path.
+    Shortest,
+}
+
+impl Keep {
+    /// Names accepted on the command line.
+    pub const NAMES: &[&str] = &["oldest", "shortest"];
+}
+
+impl FromStr for Keep {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "oldest" => Ok(Self::Oldest),
+            "shortest" => Ok(Self::Shortest),
+            _ => Err(anyhow!("Unknown keep rule '{}'.", s)),
+        }
+    }
+}
+
+impl fmt::Display for Keep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Oldest => "oldest",
+            Self::Shortest => "shortest",
+        })
+    }
+}
+
+/// Files with the same content.
+#[derive(Debug, Serialize)]
+pub(crate) struct Duplicates {
+    pub size: u64,
+    /// XXH3 hash of the contents.
+    pub hash: String,
+    /// The file to keep comes first.
+    pub files: Vec<PathBuf>,
+}
+
+/// Finds the files in `files` with the same content. Files that cannot be
+/// read are skipped with a warning.
+pub(crate) fn find(
+    files: &[PathBuf],
+    keep: Keep,
+    throttle: &Throttle,
+) -> Vec<Duplicates> {
+    let mut sized: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    for path in files {
+        match path.metadata() {
+            Ok(metadata) => {
+                sized.entry(metadata.len()).or_default().push(path.clone())
+            }
+            Err(e) => {
+                warn!("Unable to read size of '{}': {}", path.display(), e)
+            }
+        }
+    }
+
+    let partial = refine(sized, throttle, |size, path| {
+        Ok((size, partial_hash(path, size)?))
+    });
+    let full = refine(partial, throttle, |(size, _), path| {
+        Ok((size, file_hash(path, size)?))
+    });
+
+    let mut duplicates: Vec<_> = full
+        .into_iter()
+        .map(|((size, hash), mut files)| {
+            sort_keep_first(&mut files, keep);
+            Duplicates {
+                size,
+                hash: format!("{hash:016x}"),
+                files,
+            }
+        })
+        .collect();
+    duplicates.sort_by(|a, b| a.files.cmp(&b.files));
+
+    duplicates
+}
+
+/// Splits the `groups` of files with more than one file by a finer key,
+/// dropping files whose key cannot be computed.
+fn refine<K, L>(
+    groups: impl IntoIterator<Item = (K, Vec<PathBuf>)>,
+    throttle: &Throttle,
+    key: impl Fn(K, &Path) -> Result<L> + Sync,
+) -> Vec<(L, Vec<PathBuf>)>
+where
+    K: Copy + Send + Sync,
+    L: Copy + Eq + Hash + Send,
+{
+    let keyed = groups
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .flat_map(|(k, paths)| paths.into_iter().map(move |p| (k, p)))
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .filter_map(|(k, path)| {
+            match throttle.run(&[&path], || key(k, &path)) {
+                Ok(l) => Some((l, path)),
+                Err(e) => {
+                    warn!("{:#}", e);
+                    None
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut groups: HashMap<L, Vec<PathBuf>> = HashMap::new();
+    for (l, path) in keyed {
+        groups.entry(l).or_default().push(path);
+    }
+
+    groups
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .collect()
+}
+
+/// XXH3 hash of the first and the last chunk of a file.
+fn partial_hash(path: &Path, size: u64) -> Result<u64> {
+    let mut file = fs::File::open(path).with_context(|| {
+        format!("Unable to open '{}' for hashing.", path.display())
+    })?;
+
+    let mut hasher = Xxh3::new();
+    let mut buffer = Vec::with_capacity(CHUNK_SIZE as usize);
+    (&mut file)
+        .take(CHUNK_SIZE)
+        .read_to_end(&mut buffer)
+        .and_then(|_| {
+            hasher.update(&buffer);
+            buffer.clear();
+            if size > CHUNK_SIZE {
+                file.seek(SeekFrom::Start(
+                    size.saturating_sub(CHUNK_SIZE).max(CHUNK_SIZE),
+                ))?;
+                file.read_to_end(&mut buffer)?;
+                hasher.update(&buffer);
+            }
+            Ok(())
+        })
+        .with_context(|| {
+            format!("Unable to read '{}' for hashing.", path.display())
+        })?;
+
+    Ok(hasher.digest())
+}
+
+fn sort_keep_first(files: &mut [PathBuf], keep: Keep) {
+    match keep {
+        Keep::Oldest => files.sort_by_cached_key(|path| {
+            (
+                path.metadata().and_then(|m| m.modified()).ok(),
+                path.clone(),
+            )
+        }),
+        Keep::Shortest => files
+            .sort_by_cached_key(|path| (path.as_os_str().len(), path.clone())),
+    }
+}
+
+/// Prints `duplicates` as text.
+pub(crate) fn print(duplicates: &[Duplicates], root: &Path) {
+    for set in duplicates {
+        println!("{} copies of {} bytes:", set.files.len(), set.size);
+        for (i, path) in set.files.iter().enumerate() {
+            println!(
+                "  {} {}",
+                if i == 0 { "keep" } else { "    " },
+                path.strip_prefix(root).unwrap_or(path).display()
+            );
+        }
+    }
+
+    let redundant: usize = duplicates.iter().map(|s| s.files.len() - 1).sum();
+    let bytes: u64 = duplicates
+        .iter()
+        .map(|s| s.size * (s.files.len() as u64 - 1))
+        .sum();
+    println!(
+        "{} redundant file(s) in {} set(s), {} bytes.",
+        redundant,
+        duplicates.len(),
+        bytes
+    );
+}
+
+/// Trashes all files of `duplicates` but the first of each set, with their
+/// sidecars, see [`redundant_files()`].
+pub(crate) fn trash_redundant(
+    duplicates: &[Duplicates],
+    patterns: &[Pattern],
+    dry_run: bool,
+) -> Result<()> {
+    for path in redundant_files(duplicates, patterns) {
+        if dry_run {
+            info!("Would trash {}.", path.display());
+        } else {
+            trash::delete(&path).with_context(|| {
+                format!("Failed to trash {}.", path.display())
+            })?;
+            info!("Trashed {}.", path.display());
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns all files of `duplicates` but the first of each set, each
+/// followed by its sidecars matching `patterns`.
+///
+/// Sidecars of the files kept are left out, as are `{stem}` sidecars another
+/// media file in the same folder still uses, e.g. `IMG_1.xmp` of the RAW
+/// next to a redundant `IMG_1.jpg`.
+fn redundant_files(
+    duplicates: &[Duplicates],
+    patterns: &[Pattern],
+) -> Vec<PathBuf> {
+    let redundant: HashSet<_> =
+        duplicates.iter().flat_map(|set| &set.files[1..]).collect();
+    let kept: HashSet<_> = duplicates
+        .iter()
+        .flat_map(|set| sidecar::find(&set.files[0], patterns))
+        .map(|sidecar| sidecar.path)
+        .collect();
+
+    let mut files = Vec::new();
+    for path in duplicates.iter().flat_map(|set| &set.files[1..]) {
+        files.push(path.clone());
+        files.extend(
+            sidecar::find(path, patterns)
+                .into_iter()
+                .filter(|sidecar| {
+                    !sidecar.by_stem() || !stem_in_use(path, &redundant)
+                })
+                .map(|sidecar| sidecar.path)
+                .filter(|sidecar| !kept.contains(sidecar)),
+        );
+    }
+
+    files
+}
+
+/// Returns `true` if a media file other than those in `redundant` has the
+/// stem of `path` in its folder.
+fn stem_in_use(path: &Path, redundant: &HashSet<&PathBuf>) -> bool {
+    let Some(folder) = path.parent() else {
+        return false;
+    };
+    let Ok(entries) = fs::read_dir(folder) else {
+        // Better keep a sidecar than lose one.
+        return true;
+    };
+
+    entries.flatten().map(|entry| entry.path()).any(|other| {
+        other != path
+            && other.file_stem() == path.file_stem()
+            && file_kind(&other).is_some()
+            && !redundant.contains(&other)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn finds_duplicates() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("2023/08")).unwrap();
+
+        // Large enough for the partial hash to skip the middle.
+        let mut photo = vec![7u8; 3 * CHUNK_SIZE as usize];
+        fs::write(root.join("2023/08/IMG_1.jpg"), &photo).unwrap();
+        fs::write(root.join("copy.jpg"), &photo).unwrap();
+        // Same size, start and end.
+        photo[CHUNK_SIZE as usize + 1] = 8;
+        fs::write(root.join("edited.jpg"), &photo).unwrap();
+        fs::write(root.join("other.jpg"), b"other").unwrap();
+
+        let files: Vec<_> =
+            ["2023/08/IMG_1.jpg", "copy.jpg", "edited.jpg", "other.jpg"]
+                .iter()
+                .map(|p| root.join(p))
+                .collect();
+        let duplicates = find(&files, Keep::Shortest, &Throttle::new(None));
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(
+            duplicates[0].files,
+            [root.join("copy.jpg"), root.join("2023/08/IMG_1.jpg")]
+        );
+    }
+
+    #[test]
+    fn sidecars_go_with_duplicates() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        fs::write(root.join("a.jpg"), b"photo").unwrap();
+        fs::write(root.join("b.jpg"), b"photo").unwrap();
+        fs::write(root.join("b.jpg.xmp"), b"<x:xmpmeta/>").unwrap();
+
+        let files = [root.join("a.jpg"), root.join("b.jpg")];
+        let duplicates = find(&files, Keep::Shortest, &Throttle::new(None));
+        let patterns = [Pattern::parse("{name}.xmp").unwrap()];
+        assert_eq!(
+            redundant_files(&duplicates, &patterns),
+            [root.join("b.jpg"), root.join("b.jpg.xmp")]
+        );
+    }
+
+    #[test]
+    fn shared_sidecars_stay_with_partners() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("card")).unwrap();
+        fs::create_dir_all(root.join("copy")).unwrap();
+        fs::write(root.join("DSC_0001.JPG"), b"photo").unwrap();
+        // A redundant JPEG whose RAW partner is no duplicate.
+        fs::write(root.join("card/DSC_0001.JPG"), b"photo").unwrap();
+        fs::write(root.join("card/DSC_0001.NEF"), b"raw").unwrap();
+        fs::write(root.join("card/DSC_0001.xmp"), b"<x:xmpmeta/>").unwrap();
+        // Without a partner, the sidecar goes with the copy.
+        fs::write(root.join("copy/DSC_0001.JPG"), b"photo").unwrap();
+        fs::write(root.join("copy/DSC_0001.xmp"), b"<x:xmpmeta/>").unwrap();
+
+        let files = [
+            root.join("DSC_0001.JPG"),
+            root.join("card/DSC_0001.JPG"),
+            root.join("copy/DSC_0001.JPG"),
+        ];
+        let duplicates = find(&files, Keep::Shortest, &Throttle::new(None));
+        let patterns = [Pattern::parse("{stem}.xmp").unwrap()];
+        assert_eq!(
+            redundant_files(&duplicates, &patterns),
+            [
+                root.join("card/DSC_0001.JPG"),
+                root.join("copy/DSC_0001.JPG"),
+                root.join("copy/DSC_0001.xmp"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,312 @@
+//! Errors by class and the exit codes they lead to.
+//!
+//! Errors are still passed around as [`anyhow::Error`]; the class of one is
+//! that of the [`Error`] in its chain or, failing that, of the
+//! [`io::Error`] there. `--fail-on` picks the classes that make a run fail.
+
+use std::{
+    collections::BTreeMap,
+    fmt, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Kinds of errors, from least to most severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum ErrorClass {
+    /// A different file is at the destination.
+    Conflict,
+    /// No date source has a capture time.
+    Metadata,
+    /// A file cannot be parsed.
+    Unreadable,
+    Io,
+    Permission,
+    /// The path format template is invalid.
+    Template,
+    /// Anything else; always fails a run.
+    Other,
+}
+
+impl ErrorClass {
+    /// Names accepted by `--fail-on`.
+    pub const NAMES: &[&str] = &[
+        "conflict",
+        "metadata",
+        "unreadable",
+        "io",
+        "permission",
+        "template",
+    ];
+
+    /// What `--fail-on` defaults to with `--halt-on-errors`: everything but
+    /// conflicts. Otherwise no class fails a run.
+    pub const DEFAULT: &[Self] = &[
+        Self::Metadata,
+        Self::Unreadable,
+        Self::Io,
+        Self::Permission,
+        Self::Template,
+    ];
+
+    /// The exit code of a run failing with this class.
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Self::Other => 1,
+            Self::Metadata => 3,
+            Self::Unreadable => 4,
+            Self::Io => 5,
+            Self::Permission => 6,
+            Self::Conflict => 7,
+            Self::Template => 8,
+        }
+    }
+}
+
+impl FromStr for ErrorClass {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "conflict" => Ok(Self::Conflict),
+            "metadata" => Ok(Self::Metadata),
+            "unreadable" => Ok(Self::Unreadable),
+            "io" => Ok(Self::Io),
+            "permission" => Ok(Self::Permission),
+            "template" => Ok(Self::Template),
+            _ => Err(anyhow::anyhow!("Unknown error class '{}'.", s)),
+        }
+    }
+}
+
+impl fmt::Display for ErrorClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Conflict => "conflict",
+            Self::Metadata => "metadata",
+            Self::Unreadable => "unreadable",
+            Self::Io => "io",
+            Self::Permission => "permission",
+            Self::Template => "template",
+            Self::Other => "other",
+        })
+    }
+}
+
+/// An error of a known class.
+#[derive(Debug)]
+pub(crate) enum Error {
+    /// No date source has a capture time for the file.
+    MetadataMissing(PathBuf),
+    /// The EXIF data of the file cannot be parsed.
+    Unreadable {
+        path: PathBuf,
+        source: exif::Error,
+    },
+    Io {
+        message: String,
+        source: io::Error,
+    },
+    /// Like `Io`, but for lack of permission.
+    Permission {
+        message: String,
+        source: io::Error,
+    },
+    /// A different file is at `existing`.
+    Conflict {
+        path: PathBuf,
+        existing: PathBuf,
+    },
+    Template(String),
+    /// Files failed with errors of classes in `--fail-on`.
+    Failed(BTreeMap<ErrorClass, usize>),
+}
+
+impl Error {
+    /// An I/O error, with `message` saying what failed.
+    pub fn io(source: io::Error, message: String) -> Self {
+        if source.kind() == io::ErrorKind::PermissionDenied {
+            Self::Permission { message, source }
+        } else {
+            Self::Io { message, source }
+        }
+    }
+
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            Self::MetadataMissing(_) => ErrorClass::Metadata,
+            Self::Unreadable { .. } => ErrorClass::Unreadable,
+            Self::Io { .. } => ErrorClass::Io,
+            Self::Permission { .. } => ErrorClass::Permission,
+            Self::Conflict { .. } => ErrorClass::Conflict,
+            Self::Template(_) => ErrorClass::Template,
+            Self::Failed(counts) => {
+                counts.keys().max().copied().unwrap_or(ErrorClass::Other)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MetadataMissing(path) => {
+                write!(f, "Timestamp metadata missing in '{}'.", path.display())
+            }
+            Self::Unreadable { path, .. } => write!(
+                f,
+                "Unable to read EXIF metadata of '{}'.",
+                path.display()
+            ),
+            Self::Io { message, .. } | Self::Permission { message, .. } => {
+                f.write_str(message)
+            }
+            Self::Conflict { path, existing } => write!(
+                f,
+                "{} exists with different content; not moving {}.",
+                existing.display(),
+                path.display()
+            ),
+            Self::Template(message) => f.write_str(message),
+            Self::Failed(counts) => {
+                write!(
+                    f,
+                    "{} error(s) encountered:",
+                    counts.values().sum::<usize>()
+                )?;
+                for (i, (class, count)) in counts.iter().rev().enumerate() {
+                    let separator = if i == 0 { "" } else { "," };
+                    write!(f, "{} {} {}", separator, count, class)?;
+                }
+                f.write_str(".")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Unreadable { source, .. } => Some(source),
+            Self::Io { source, .. } | Self::Permission { source, .. } => {
+                Some(source)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Adds a message to I/O errors, turning them into an [`Error`].
+pub(crate) trait IoContext<T> {
+    fn io_context(self, message: impl FnOnce() -> String) -> Result<T, Error>;
+}
+
+impl<T> IoContext<T> for io::Result<T> {
+    fn io_context(self, message: impl FnOnce() -> String) -> Result<T, Error> {
+        self.map_err(|e| Error::io(e, message()))
+    }
+}
+
+/// Returns the class of `error`.
+pub(crate) fn class(error: &anyhow::Error) -> ErrorClass {
+    error
+        .chain()
+        .find_map(|e| e.downcast_ref::<Error>().map(Error::class))
+        .or_else(|| {
+            error.chain().find_map(|e| {
+                e.downcast_ref::<io::Error>().map(|e| {
+                    if e.kind() == io::ErrorKind::PermissionDenied {
+                        ErrorClass::Permission
+                    } else {
+                        ErrorClass::Io
+                    }
+                })
+            })
+        })
+        .unwrap_or(ErrorClass::Other)
+}
+
+/// Counts the errors of a run and the files that conflicted, by class.
+#[derive(Debug, Default)]
+pub(crate) struct Failures {
+    counts: BTreeMap<ErrorClass, usize>,
+}
+
+impl Failures {
+    pub fn add(&mut self, class: ErrorClass) {
+        *self.counts.entry(class).or_default() += 1;
+    }
+
+    /// Returns an error if any class in `fail_on`, or of no class, was
+    /// added.
+    pub fn check(&self, fail_on: &[ErrorClass]) -> Result<(), Error> {
+        let failed: BTreeMap<_, _> = self
+            .counts
+            .iter()
+            .filter(|(class, _)| {
+                **class == ErrorClass::Other || fail_on.contains(class)
+            })
+            .map(|(class, count)| (*class, *count))
+            .collect();
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Failed(failed))
+        }
+    }
+}
+
+/// Returns the exit code for `error`, see [`ErrorClass::exit_code()`].
+pub(crate) fn exit_code(error: &anyhow::Error) -> u8 {
+    class(error).exit_code()
+}
+
+/// Returns the [`Error`] for a conflict at `existing`.
+pub(crate) fn conflict(path: &Path, existing: &Path) -> Error {
+    Error::Conflict {
+        path: path.to_path_buf(),
+        existing: existing.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn classes_of_errors() {
+        let permission =
+            Err::<(), _>(io::Error::from(io::ErrorKind::PermissionDenied))
+                .context("Unable to remove 'a.jpg'.")
+                .unwrap_err();
+        assert_eq!(class(&permission), ErrorClass::Permission);
+
+        let missing =
+            anyhow::Error::from(Error::MetadataMissing("a.jpg".into()))
+                .context("Unable to move 'a.jpg'.");
+        assert_eq!(class(&missing), ErrorClass::Metadata);
+        assert_eq!(exit_code(&missing), 3);
+
+        assert_eq!(class(&anyhow::anyhow!("Oops.")), ErrorClass::Other);
+    }
+
+    #[test]
+    fn fail_on_picks_classes() {
+        let mut failures = Failures::default();
+        failures.add(ErrorClass::Conflict);
+        assert!(failures.check(ErrorClass::DEFAULT).is_ok());
+
+        failures.add(ErrorClass::Metadata);
+        failures.add(ErrorClass::Metadata);
+        let error = failures
+            .check(&[ErrorClass::Conflict, ErrorClass::Metadata])
+            .unwrap_err();
+        assert_eq!(error.class(), ErrorClass::Metadata);
+        assert_eq!(
+            error.to_string(),
+            "3 error(s) encountered: 2 metadata, 1 conflict."
+        );
+        assert!(failures.check(&[]).is_ok());
+    }
+}
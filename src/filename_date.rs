@@ -0,0 +1,178 @@
+//! Capture dates embedded in file names.
+//!
+//! Messengers and screenshot tools strip EXIF but put a timestamp in the file
+//! name. Patterns are regular expressions with the named groups `year`,
+//! `month` and `day` and the optional groups `hour`, `minute` and `second`.
+
+use anyhow::{Result, anyhow};
+use chrono::{NaiveDate, NaiveDateTime};
+use regex::{Captures, Regex};
+
+/// Built-in patterns for common phone, messenger and screenshot file names.
+const BUILTIN_PATTERNS: &[&str] = &[
+    // Pixel: PXL_20230815_143000123.jpg
+    r"(?i)^PXL_(?<year>\d{4})(?<month>\d{2})(?<day>\d{2})_(?<hour>\d{2})(?<minute>\d{2})(?<second>\d{2})",
+    // WhatsApp: IMG-20230815-WA0003.jpg, VID-20230815-WA0003.mp4
+    r"(?i)^(?:IMG|VID)-(?<year>\d{4})(?<month>\d{2})(?<day>\d{2})-WA\d+",
+    // Android: Screenshot_2023-08-15-14-30-00.png
+    // macOS: Screenshot 2023-08-15 at 14.30.00.png
+    r"(?i)^Screen ?shot[_ ](?<year>\d{4})-(?<month>\d{2})-(?<day>\d{2})(?:[-_ ](?:at )?(?<hour>\d{2})[-.](?<minute>\d{2})[-.](?<second>\d{2}))?",
+    // Android: Screenshot_20230815-143000.png
+    r"(?i)^Screenshot_(?<year>\d{4})(?<month>\d{2})(?<day>\d{2})-(?<hour>\d{2})(?<minute>\d{2})(?<second>\d{2})",
+    // Signal: signal-2023-08-15-143000.jpg, signal-2023-08-15-14-30-00-123.jpg
+    r"(?i)^signal-(?<year>\d{4})-(?<month>\d{2})-(?<day>\d{2})-(?<hour>\d{2})-?(?<minute>\d{2})-?(?<second>\d{2})",
+    // Android cameras: IMG_20230815_143000.jpg, VID_20230815_143000.mp4
+    r"(?:^|\D)(?<year>(?:19|20)\d{2})(?<month>\d{2})(?<day>\d{2})[_-](?<hour>\d{2})(?<minute>\d{2})(?<second>\d{2})",
+    // Dropbox & co.: 2023-08-15 14.30.00.jpg
+    r"(?:^|\D)(?<year>(?:19|20)\d{2})-(?<month>\d{2})-(?<day>\d{2})(?:[ _T-](?<hour>\d{2})[.:-](?<minute>\d{2})[.:-](?<second>\d{2}))?",
+];
+
+/// Compiled filename date patterns.
+#[derive(Debug)]
+pub struct FilenameDates {
+    patterns: Vec<Regex>,
+}
+
+impl FilenameDates {
+    /// Compiles the user patterns followed by the built-in ones.
+    ///
+    /// User patterns are tried first so they can override the built-ins.
+    pub fn new(user_patterns: &[String]) -> Result<Self> {
+        let mut patterns = Vec::new();
+
+        for pattern in user_patterns {
+            let regex = Regex::new(pattern).map_err(|e| {
+                anyhow!("Invalid filename pattern '{}': {}", pattern, e)
+            })?;
+
+            let names: Vec<_> = regex.capture_names().flatten().collect();
+            if let Some(group) = ["year", "month", "day"]
+                .into_iter()
+                .find(|group| !names.contains(group))
+            {
+                return Err(anyhow!(
+                    "Filename pattern '{}' lacks the named group '{}'.",
+                    pattern,
+                    group
+                ));
+            }
+
+            patterns.push(regex);
+        }
+
+        patterns.extend(
+            BUILTIN_PATTERNS
+                .iter()
+                .map(|pattern| Regex::new(pattern).unwrap()),
+        );
+
+        Ok(Self { patterns })
+    }
+
+    /// Returns the timestamp of the first pattern matching `file_name` that
+    /// yields a valid date and time.
+    pub fn parse(&self, file_name: &str) -> Option<NaiveDateTime> {
+        self.patterns.iter().find_map(|pattern| {
+            pattern
+                .captures(file_name)
+                .and_then(|captures| date_time(&captures))
+        })
+    }
+}
+
+impl Default for FilenameDates {
+    fn default() -> Self {
+        Self::new(&[]).unwrap()
+    }
+}
+
+/// Builds a timestamp from the named groups of a match, validating it.
+fn date_time(captures: &Captures) -> Option<NaiveDateTime> {
+    let group = |name: &str| -> Option<u32> {
+        match captures.name(name) {
+            Some(m) => m.as_str().parse().ok(),
+            // Missing time components default to midnight.
+            None => Some(0),
+        }
+    };
+
+    NaiveDate::from_ymd_opt(
+        group("year")? as i32,
+        group("month")?,
+        group("day")?,
+    )?
+    .and_hms_opt(group("hour")?, group("minute")?, group("second")?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Datelike, Timelike};
+
+    fn ymdhms(file_name: &str) -> Option<(i32, u32, u32, u32, u32, u32)> {
+        FilenameDates::default().parse(file_name).map(|t| {
+            (
+                t.year(),
+                t.month(),
+                t.day(),
+                t.hour(),
+                t.minute(),
+                t.second(),
+            )
+        })
+    }
+
+    #[test]
+    fn builtin_patterns() {
+        assert_eq!(
+            ymdhms("IMG-20230815-WA0003.jpg"),
+            Some((2023, 8, 15, 0, 0, 0))
+        );
+        assert_eq!(
+            ymdhms("Screenshot_2023-08-15-14-30-00.png"),
+            Some((2023, 8, 15, 14, 30, 0))
+        );
+        assert_eq!(
+            ymdhms("Screenshot 2023-08-15 at 14.30.00.png"),
+            Some((2023, 8, 15, 14, 30, 0))
+        );
+        assert_eq!(
+            ymdhms("PXL_20230815_143000123.jpg"),
+            Some((2023, 8, 15, 14, 30, 0))
+        );
+        assert_eq!(
+            ymdhms("signal-2023-08-15-143005.jpg"),
+            Some((2023, 8, 15, 14, 30, 5))
+        );
+        assert_eq!(
+            ymdhms("IMG_20230815_143000.jpg"),
+            Some((2023, 8, 15, 14, 30, 0))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_dates() {
+        assert_eq!(ymdhms("IMG_1234.jpg"), None);
+        assert_eq!(ymdhms("IMG-20231345-WA0003.jpg"), None);
+    }
+
+    #[test]
+    fn user_patterns_take_precedence() {
+        let dates = FilenameDates::new(&[
+            r"^holiday_(?<day>\d{2})\.(?<month>\d{2})\.(?<year>\d{4})".into(),
+        ])
+        .unwrap();
+        assert_eq!(
+            dates.parse("holiday_15.08.2023.jpg"),
+            NaiveDate::from_ymd_opt(2023, 8, 15)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn user_pattern_requires_date_groups() {
+        assert!(FilenameDates::new(&[r"^(?<year>\d{4})".into()]).is_err());
+        assert!(FilenameDates::new(&["(".into()]).is_err());
+    }
+}
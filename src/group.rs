@@ -0,0 +1,344 @@
+//! Grouping of files that belong together.
+//!
+//! Cameras shooting RAW+JPEG write e.g. `DSC_0001.NEF` and `DSC_0001.JPG`.
+//! Such pairs share a directory and stem and were captured within the same
+//! second.
+//!
+//! Apple Live Photos are a still and a movie, e.g. `IMG_1234.HEIC` and
+//! `IMG_1234.MOV`, linked by a content identifier. Without one they are
+//! matched by stem and a capture time difference of a few seconds; the movie
+//! starts before the still.
+//!
+//! Pairs are moved as one group so they always end up next to each other
+//! under the same name.
+
+use crate::{
+    config::Config as AppConfig,
+    metadata::{self, Metadata},
+    sidecar::{self, Sidecar},
+    util::{FileKind, file_kind, is_jpeg},
+};
+use anyhow::Result;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    path::{Path, PathBuf},
+};
+
+/// Maximum capture time difference between a RAW and its JPEG, in seconds.
+const MAX_PAIR_TIME_DELTA: i64 = 1;
+
+/// Maximum capture time difference between the still and the movie of a Live
+/// Photo without content identifiers, in seconds.
+const MAX_LIVE_PHOTO_TIME_DELTA: i64 = 3;
+
+/// The role of a file in a RAW+JPEG pair, see `{pair_role}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PairRole {
+    Raw,
+    Jpeg,
+}
+
+impl fmt::Display for PairRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Raw => "raw",
+            Self::Jpeg => "jpeg",
+        })
+    }
+}
+
+/// A file to move, with its sidecars and metadata.
+#[derive(Debug)]
+pub(crate) struct Item {
+    pub path: PathBuf,
+    pub sidecars: Vec<Sidecar>,
+    pub metadata: Result<Metadata>,
+    pub pair_role: Option<PairRole>,
+    /// Part of a Live Photo, see `{live}`.
+    pub live: bool,
+    /// Name of the burst this is a frame of, see `{burst}`.
+    pub burst: Option<String>,
+}
+
+impl Item {
+    /// Finds the sidecars of `path` and reads its metadata.
+    pub fn read(path: &Path, config: &AppConfig) -> Result<Self> {
+        let sidecars = sidecar::find(path, &config.sidecar_patterns()?);
+        let metadata = metadata::read(path, &sidecars, config);
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            sidecars,
+            metadata,
+            pair_role: None,
+            live: false,
+            burst: None,
+        })
+    }
+
+    fn time_stamp(&self) -> Option<chrono::NaiveDateTime> {
+        self.metadata.as_ref().ok().map(|m| m.time_stamp)
+    }
+
+    fn content_identifier(&self) -> Option<&str> {
+        self.metadata.as_ref().ok()?.content_identifier.as_deref()
+    }
+
+    /// Sort key putting RAWs first, then stills, then movies.
+    fn rank(&self) -> u8 {
+        match file_kind(&self.path) {
+            Some(FileKind::Raw) => 0,
+            Some(FileKind::Movie) => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// Files that are moved together.
+///
+/// The first item with metadata determines the destination of all items.
+#[derive(Debug)]
+pub(crate) struct Group {
+    pub items: Vec<Item>,
+}
+
+impl Group {
+    /// Returns the metadata that determines the destination of the group.
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.items
+            .iter()
+            .find_map(|item| item.metadata.as_ref().ok())
+    }
+}
+
+/// Groups `items` into RAW+JPEG pairs, Live Photos and single files.
+///
+/// The RAW or the still comes first in its group.
+pub(crate) fn group(items: Vec<Item>) -> Vec<Group> {
+    let mut directories: BTreeMap<PathBuf, Vec<Item>> = BTreeMap::new();
+    for item in items {
+        directories
+            .entry(item.path.parent().unwrap_or(Path::new("")).to_path_buf())
+            .or_default()
+            .push(item);
+    }
+
+    let mut groups = Vec::new();
+    for (_, items) in directories {
+        let (live_photos, items) = link_live_photos(items);
+        groups.extend(live_photos);
+
+        let mut buckets: BTreeMap<String, Vec<Item>> = BTreeMap::new();
+        for item in items {
+            buckets
+                .entry(
+                    item.path
+                        .file_stem()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .to_lowercase(),
+                )
+                .or_default()
+                .push(item);
+        }
+
+        for (_, mut bucket) in buckets {
+            // RAWs first so they claim shared `{stem}` sidecars.
+            bucket.sort_by_key(Item::rank);
+            release_shared_sidecars(&mut bucket);
+
+            if bucket.len() == 2 && is_raw_jpeg_pair(&bucket[0], &bucket[1]) {
+                bucket[0].pair_role = Some(PairRole::Raw);
+                bucket[1].pair_role = Some(PairRole::Jpeg);
+                groups.push(Group { items: bucket });
+            } else if bucket.len() == 2 && is_live_photo(&bucket[0], &bucket[1])
+            {
+                bucket.iter_mut().for_each(|item| item.live = true);
+                groups.push(Group { items: bucket });
+            } else {
+                groups.extend(
+                    bucket.into_iter().map(|item| Group { items: vec![item] }),
+                );
+            }
+        }
+    }
+
+    groups
+}
+
+/// Splits off the Live Photos whose still and movie share a content
+/// identifier, whatever their names.
+fn link_live_photos(items: Vec<Item>) -> (Vec<Group>, Vec<Item>) {
+    let mut counts: HashMap<String, (usize, usize)> = HashMap::new();
+    for item in &items {
+        if let Some(id) = item.content_identifier() {
+            let (stills, movies) = counts.entry(id.to_string()).or_default();
+            if file_kind(&item.path) == Some(FileKind::Movie) {
+                *movies += 1;
+            } else if is_jpeg(&item.path) {
+                *stills += 1;
+            }
+        }
+    }
+
+    let (linked, rest): (Vec<_>, Vec<_>) =
+        items.into_iter().partition(|item| {
+            item.content_identifier()
+                .is_some_and(|id| counts.get(id) == Some(&(1, 1)))
+        });
+
+    let mut pairs: BTreeMap<String, Vec<Item>> = BTreeMap::new();
+    for mut item in linked {
+        item.live = true;
+        pairs
+            .entry(item.content_identifier().unwrap().to_string())
+            .or_default()
+            .push(item);
+    }
+
+    let groups = pairs
+        .into_values()
+        .map(|mut items| {
+            items.sort_by_key(Item::rank);
+            release_shared_sidecars(&mut items);
+            Group { items }
+        })
+        .collect();
+
+    (groups, rest)
+}
+
+/// `a` and `b` must be sorted by [`Item::rank()`].
+fn is_raw_jpeg_pair(a: &Item, b: &Item) -> bool {
+    file_kind(&a.path) == Some(FileKind::Raw)
+        && is_jpeg(&b.path)
+        && within(a, b, MAX_PAIR_TIME_DELTA)
+}
+
+/// `a` and `b` must be sorted by [`Item::rank()`].
+fn is_live_photo(a: &Item, b: &Item) -> bool {
+    is_jpeg(&a.path)
+        && file_kind(&b.path) == Some(FileKind::Movie)
+        && match (a.content_identifier(), b.content_identifier()) {
+            (Some(a), Some(b)) => a == b,
+            _ => within(a, b, MAX_LIVE_PHOTO_TIME_DELTA),
+        }
+}
+
+/// Files without a capture time pair by name alone, as they will follow
+/// their partner's.
+fn within(a: &Item, b: &Item, seconds: i64) -> bool {
+    match (a.time_stamp(), b.time_stamp()) {
+        (Some(a), Some(b)) => (a - b).num_seconds().abs() <= seconds,
+        _ => true,
+    }
+}
+
+/// Ensures each sidecar is claimed by one item only, e.g. an `IMG_1234.xmp`
+/// shared by `IMG_1234.CR3` and `IMG_1234.JPG`.
+fn release_shared_sidecars(items: &mut [Item]) {
+    let mut claimed: Vec<PathBuf> = Vec::new();
+    for item in items {
+        item.sidecars.retain(|sidecar| {
+            if claimed.contains(&sidecar.path) {
+                false
+            } else {
+                claimed.push(sidecar.path.clone());
+                true
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use chrono::NaiveDate;
+
+    fn item(path: &str) -> Item {
+        Item {
+            path: PathBuf::from(path),
+            sidecars: Vec::new(),
+            metadata: Err(anyhow!("No metadata.")),
+            pair_role: None,
+            live: false,
+            burst: None,
+        }
+    }
+
+    fn item_with(
+        path: &str,
+        second: u32,
+        content_identifier: Option<&str>,
+    ) -> Item {
+        Item {
+            metadata: Ok(Metadata {
+                time_stamp: NaiveDate::from_ymd_opt(2023, 8, 15)
+                    .unwrap()
+                    .and_hms_opt(14, 30, second)
+                    .unwrap(),
+                content_identifier: content_identifier.map(String::from),
+                ..Default::default()
+            }),
+            ..item(path)
+        }
+    }
+
+    #[test]
+    fn pairs_raw_and_jpeg() {
+        let groups = group(vec![
+            item("/card/DSC_0001.JPG"),
+            item("/card/DSC_0001.NEF"),
+            item("/card/DSC_0002.JPG"),
+            item("/other/DSC_0001.JPG"),
+        ]);
+        assert_eq!(groups.len(), 3);
+
+        let pair = &groups[0].items;
+        assert_eq!(pair.len(), 2);
+        assert_eq!(pair[0].path, Path::new("/card/DSC_0001.NEF"));
+        assert_eq!(pair[0].pair_role, Some(PairRole::Raw));
+        assert_eq!(pair[1].pair_role, Some(PairRole::Jpeg));
+
+        assert_eq!(groups[1].items[0].pair_role, None);
+        assert_eq!(groups[2].items[0].pair_role, None);
+    }
+
+    #[test]
+    fn does_not_pair_two_jpegs() {
+        let groups =
+            group(vec![item("/card/IMG_1.jpg"), item("/card/IMG_1.heic")]);
+        assert_eq!(groups.len(), 2);
+    }
+
+    #[test]
+    fn pairs_live_photos() {
+        let groups = group(vec![
+            // Linked by content identifier despite different names.
+            item_with("/dcim/IMG_0001.MOV", 0, Some("A")),
+            item_with("/dcim/IMG_E0001.HEIC", 1, Some("A")),
+            // Linked by name and time.
+            item_with("/dcim/IMG_0002.MOV", 8, None),
+            item_with("/dcim/IMG_0002.HEIC", 10, None),
+            // Same name but too far apart.
+            item_with("/dcim/IMG_0003.MOV", 0, None),
+            item_with("/dcim/IMG_0003.HEIC", 30, None),
+            // Same name but different content identifiers.
+            item_with("/dcim/IMG_0004.MOV", 0, Some("B")),
+            item_with("/dcim/IMG_0004.HEIC", 0, Some("C")),
+        ]);
+        assert_eq!(groups.len(), 6);
+
+        let live: Vec<_> = groups
+            .iter()
+            .filter(|group| group.items.len() == 2)
+            .map(|group| {
+                assert!(group.items.iter().all(|item| item.live));
+                group.items[0].path.to_str().unwrap()
+            })
+            .collect();
+        assert_eq!(live, ["/dcim/IMG_E0001.HEIC", "/dcim/IMG_0002.HEIC"]);
+    }
+}
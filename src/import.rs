@@ -0,0 +1,411 @@
+//! Importing from camera cards.
+//!
+//! Cameras write to well-known folders: stills and most movies go to DCF
+//! folders like `DCIM/100MSDCF`, Sony movies to `PRIVATE/M4ROOT/CLIP` and AVCHD
+//! movies to `PRIVATE/AVCHD/BDMV/STREAM`. Only those folders are searched, so
+//! e.g. Sony's preview JPEGs in `PRIVATE/M4ROOT/THMBNL` are not imported as
+//! photos.
+//!
+//! The hashes of imported files are remembered per card, so a card that is
+//! inserted again only has its new shots imported.
+
+use crate::{
+    config::APP_NAME,
+    device::Throttle,
+    index::modified,
+    util::{file_hash, file_kind},
+};
+use anyhow::{Context, Result, anyhow};
+use log::{info, warn};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Name of the import history file, next to the configuration file.
+const HISTORY_NAME: &str = "imports";
+
+/// Where a kind of camera puts its files on a card.
+#[derive(Debug)]
+struct Layout {
+    name: &'static str,
+    /// Folder below the mount point; `*` matches DCF folder names.
+    folder: &'static str,
+    /// Sidecar patterns in addition to the configured ones.
+    sidecars: &'static [&'static str],
+}
+
+const LAYOUTS: &[Layout] = &[
+    Layout {
+        name: "DCF",
+        folder: "DCIM/*",
+        sidecars: &[],
+    },
+    Layout {
+        name: "Sony XAVC",
+        folder: "PRIVATE/M4ROOT/CLIP",
+        // Recording metadata, e.g. `C0001M01.XML` for `C0001.MP4`.
+        sidecars: &["{stem}M01.XML"],
+    },
+    Layout {
+        name: "AVCHD",
+        folder: "PRIVATE/AVCHD/BDMV/STREAM",
+        sidecars: &[],
+    },
+];
+
+/// The media files on a card.
+#[derive(Debug, Default)]
+pub(crate) struct Card {
+    /// Sorted by path.
+    pub files: Vec<PathBuf>,
+    /// Sidecar patterns of the layouts found.
+    pub sidecars: Vec<&'static str>,
+}
+
+/// Finds the media files on the card mounted at `mount`.
+pub(crate) fn discover(mount: &Path) -> Result<Card> {
+    let mut card = Card::default();
+    let mut found = false;
+
+    for layout in LAYOUTS {
+        let folders = find_folders(mount, layout.folder);
+        if folders.is_empty() {
+            continue;
+        }
+        found = true;
+        info!("Found {} folders on {}.", layout.name, mount.display());
+
+        card.sidecars.extend(layout.sidecars);
+        for folder in folders {
+            card.files.extend(media_files(&folder)?);
+        }
+    }
+
+    if !found {
+        return Err(anyhow!(
+            "No camera folders found on '{}'; expected DCIM or PRIVATE.",
+            mount.display()
+        ));
+    }
+
+    card.files.sort();
+    Ok(card)
+}
+
+/// Returns the folders matching `pattern` below `mount`, ignoring case as
+/// cards are usually FAT or exFAT formatted.
+fn find_folders(mount: &Path, pattern: &str) -> Vec<PathBuf> {
+    let mut folders = vec![mount.to_path_buf()];
+
+    for component in pattern.split('/') {
+        folders = folders
+            .iter()
+            .filter_map(|folder| fs::read_dir(folder).ok())
+            .flatten()
+            .flatten()
+            .filter(|entry| entry.path().is_dir())
+            .filter(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if component == "*" {
+                    is_dcf_folder(&name)
+                } else {
+                    name.eq_ignore_ascii_case(component)
+                }
+            })
+            .map(|entry| entry.path())
+            .collect();
+        folders.sort();
+    }
+
+    folders
+}
+
+/// DCF folder names are a number from 100 to 999 and five characters picked
+/// by the camera maker, e.g. `100MSDCF` or `101CANON`.
+fn is_dcf_folder(name: &str) -> bool {
+    name.len() == 8
+        && name[..3]
+            .parse::<u16>()
+            .is_ok_and(|number| (100..=999).contains(&number))
+        && name[3..]
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn media_files(folder: &Path) -> Result<Vec<PathBuf>> {
+    let entries = fs::read_dir(folder).with_context(|| {
+        format!("Unable to read folder '{}'.", folder.display())
+    })?;
+
+    Ok(entries
+        .flatten()
+        .filter(|entry| {
+            !entry.file_name().to_string_lossy().starts_with('.')
+                && entry.path().is_file()
+        })
+        .map(|entry| entry.path())
+        .filter(|path| file_kind(path).is_some())
+        .collect())
+}
+
+/// Returns an identifier of the file system mounted at `mount`.
+///
+/// This is its UUID where it can be found, the mount point otherwise.
+pub(crate) fn volume_id(mount: &Path) -> String {
+    volume_uuid(mount).unwrap_or_else(|| {
+        let mount =
+            mount.canonicalize().unwrap_or_else(|_| mount.to_path_buf());
+        warn!(
+            "Unable to find the volume UUID of {}; remembering imports by \
+             mount point.",
+            mount.display()
+        );
+        mount.to_string_lossy().into_owned()
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn volume_uuid(mount: &Path) -> Option<String> {
+    use std::os::unix::fs::MetadataExt;
+
+    let device = fs::metadata(mount).ok()?.dev();
+    fs::read_dir("/dev/disk/by-uuid")
+        .ok()?
+        .flatten()
+        .find(|entry| {
+            fs::metadata(entry.path()).is_ok_and(|m| m.rdev() == device)
+        })
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn volume_uuid(_mount: &Path) -> Option<String> {
+    None
+}
+
+/// Files imported so far.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct History {
+    /// XXH3 hashes of the imported files, by volume.
+    volumes: BTreeMap<String, BTreeSet<String>>,
+    /// The files on each volume when it was last imported from, by path
+    /// relative to where it was mounted.
+    seen: BTreeMap<String, BTreeMap<String, Seen>>,
+}
+
+/// A file on a card, so it need not be hashed again while it is unchanged.
+#[derive(Debug, Deserialize, Serialize)]
+struct Seen {
+    size: u64,
+    /// Modification time in nanoseconds since the Unix epoch.
+    modified: u64,
+    hash: String,
+}
+
+impl History {
+    pub fn load() -> Result<Self> {
+        Ok(confy::load(APP_NAME, HISTORY_NAME)?)
+    }
+
+    pub fn store(&self) -> Result<()> {
+        Ok(confy::store(APP_NAME, HISTORY_NAME, self)?)
+    }
+
+    fn contains(&self, volume: &str, hash: u64) -> bool {
+        self.volumes
+            .get(volume)
+            .is_some_and(|hashes| hashes.contains(&format!("{hash:016x}")))
+    }
+
+    pub fn insert(&mut self, volume: &str, hash: u64) {
+        self.volumes
+            .entry(volume.to_string())
+            .or_default()
+            .insert(format!("{hash:016x}"));
+    }
+
+    /// Returns the files not imported from `volume`, mounted at `mount`,
+    /// yet, with their hashes.
+    ///
+    /// Files seen there before with the same size and modification time are
+    /// not read again, so re-inserting a card is quick.
+    pub fn new_files(
+        &mut self,
+        volume: &str,
+        mount: &Path,
+        files: &[PathBuf],
+        throttle: &Throttle,
+    ) -> Result<Vec<(PathBuf, u64)>> {
+        let seen = self.seen.remove(volume).unwrap_or_default();
+        let hashed = files
+            .par_iter()
+            .map(|path| {
+                let metadata = path.metadata().with_context(|| {
+                    format!("Unable to read size of '{}'.", path.display())
+                })?;
+                let (size, modified) = (metadata.len(), modified(&metadata));
+                let name = path
+                    .strip_prefix(mount)
+                    .unwrap_or(path)
+                    .to_string_lossy()
+                    .into_owned();
+                let known = seen
+                    .get(&name)
+                    .filter(|seen| {
+                        seen.size == size && Some(seen.modified) == modified
+                    })
+                    .and_then(|seen| u64::from_str_radix(&seen.hash, 16).ok());
+                let hash = match known {
+                    Some(hash) => hash,
+                    None => throttle.run(&[path], || file_hash(path, size))?,
+                };
+                Ok((path.clone(), hash, name, size, modified))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // Files no longer on the card are forgotten.
+        let seen = hashed
+            .iter()
+            .filter_map(|(_, hash, name, size, modified)| {
+                let seen = Seen {
+                    size: *size,
+                    modified: (*modified)?,
+                    hash: format!("{hash:016x}"),
+                };
+                Some((name.clone(), seen))
+            })
+            .collect();
+        self.seen.insert(volume.to_string(), seen);
+
+        Ok(hashed
+            .into_iter()
+            .map(|(path, hash, ..)| (path, hash))
+            .filter(|(_, hash)| !self.contains(volume, *hash))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn discovers_card_layouts() {
+        let tmp = TempDir::new().unwrap();
+        let mount = tmp.path();
+        for folder in [
+            "DCIM/100MSDCF",
+            "DCIM/CANONMSC",
+            "PRIVATE/M4ROOT/CLIP",
+            "PRIVATE/M4ROOT/THMBNL",
+        ] {
+            fs::create_dir_all(mount.join(folder)).unwrap();
+        }
+        for file in [
+            "DCIM/100MSDCF/DSC00001.ARW",
+            "DCIM/100MSDCF/DSC00001.JPG",
+            "DCIM/100MSDCF/._DSC00001.JPG",
+            "DCIM/CANONMSC/M0001.CTG",
+            "DCIM/CANONMSC/IMG_0001.JPG",
+            "PRIVATE/M4ROOT/CLIP/C0001.MP4",
+            "PRIVATE/M4ROOT/CLIP/C0001M01.XML",
+            "PRIVATE/M4ROOT/THMBNL/C0001T01.JPG",
+        ] {
+            fs::write(mount.join(file), file).unwrap();
+        }
+
+        let card = discover(mount).unwrap();
+        let files: Vec<_> = card
+            .files
+            .iter()
+            .map(|path| path.strip_prefix(mount).unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(
+            files,
+            [
+                "DCIM/100MSDCF/DSC00001.ARW",
+                "DCIM/100MSDCF/DSC00001.JPG",
+                "PRIVATE/M4ROOT/CLIP/C0001.MP4",
+            ]
+        );
+        assert_eq!(card.sidecars, ["{stem}M01.XML"]);
+
+        assert!(discover(&mount.join("DCIM")).is_err());
+    }
+
+    #[test]
+    fn only_new_files() {
+        let tmp = TempDir::new().unwrap();
+        let old = tmp.path().join("DSC00001.JPG");
+        let new = tmp.path().join("DSC00002.JPG");
+        fs::write(&old, b"old").unwrap();
+        fs::write(&new, b"new").unwrap();
+
+        let mut history = History::default();
+        let files = [old, new.clone()];
+        for (_, hash) in history
+            .new_files("card", tmp.path(), &files, &Throttle::new(None))
+            .unwrap()
+        {
+            history.insert("card", hash);
+        }
+        assert!(
+            history
+                .new_files("card", tmp.path(), &files, &Throttle::new(None))
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            history
+                .new_files("other", tmp.path(), &files, &Throttle::new(None))
+                .unwrap()
+                .len(),
+            2
+        );
+
+        // The card was formatted and numbering started over.
+        fs::write(&new, b"newer").unwrap();
+        let mut history: History =
+            toml::from_str(&toml::to_string(&history).unwrap()).unwrap();
+        let new_files = history
+            .new_files("card", tmp.path(), &files, &Throttle::new(None))
+            .unwrap();
+        assert_eq!(new_files.len(), 1);
+        assert_eq!(new_files[0].0, new);
+    }
+
+    #[test]
+    fn unchanged_files_are_not_read_again() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("DCIM/100MSDCF/DSC00001.JPG");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, b"old").unwrap();
+        let files = [path.clone()];
+
+        let mut history = History::default();
+        let new_files = history
+            .new_files("card", tmp.path(), &files, &Throttle::new(None))
+            .unwrap();
+        history.insert("card", new_files[0].1);
+        let mut history: History =
+            toml::from_str(&toml::to_string(&history).unwrap()).unwrap();
+
+        // Same size and time, so the stored hash is used.
+        let modified = path.metadata().unwrap().modified().unwrap();
+        fs::write(&path, b"new").unwrap();
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(modified).unwrap();
+        assert!(
+            history
+                .new_files("card", tmp.path(), &files, &Throttle::new(None))
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
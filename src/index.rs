@@ -0,0 +1,346 @@
+//! Index of a destination library by content.
+//!
+//! Maps the size and XXH3 hash of every media file in the library to its
+//! path, so a source whose content is already in the library is found
+//! whatever its name or the template it was moved with.
+//!
+//! The index is stored in `.exifmv/index.json` in the library. It is brought
+//! up to date when opened, hashing only files that are new or whose size or
+//! modification time changed, and updated on every move.
+
+use crate::{
+    device::Throttle,
+    util::{file_hash, file_kind},
+};
+use anyhow::{Context, Result};
+use log::info;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::UNIX_EPOCH,
+};
+use walkdir::WalkDir;
+
+/// Folder in the destination root for `exifmv`'s own files.
+pub(crate) const STATE_DIR: &str = ".exifmv";
+
+const INDEX_FILE: &str = "index.json";
+
+/// Size and XXH3 hash of a file.
+pub(crate) type Key = (u64, u64);
+
+/// An entry of the index file.
+#[derive(Debug, Deserialize, Serialize)]
+struct Entry {
+    /// Relative to the library.
+    path: PathBuf,
+    size: u64,
+    hash: String,
+    /// Modification time in nanoseconds since the Unix epoch. Missing in
+    /// older index files, whose files are hashed again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    modified: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    /// Key and modification time by path.
+    by_path: HashMap<PathBuf, (Key, Option<u64>)>,
+    by_key: HashMap<Key, BTreeSet<PathBuf>>,
+}
+
+impl Entries {
+    fn insert(&mut self, path: PathBuf, key: Key, modified: Option<u64>) {
+        self.remove(&path);
+        self.by_key.entry(key).or_default().insert(path.clone());
+        self.by_path.insert(path, (key, modified));
+    }
+
+    fn remove(&mut self, path: &Path) {
+        if let Some((key, _)) = self.by_path.remove(path)
+            && let Some(paths) = self.by_key.get_mut(&key)
+        {
+            paths.remove(path);
+            if paths.is_empty() {
+                self.by_key.remove(&key);
+            }
+        }
+    }
+}
+
+/// The content index of a library.
+#[derive(Debug)]
+pub(crate) struct Index {
+    root: PathBuf,
+    entries: Mutex<Entries>,
+}
+
+impl Index {
+    /// Loads the index of the library at `root` and brings it up to date.
+    pub fn open(root: &Path, throttle: &Throttle) -> Result<Self> {
+        let file = root.join(STATE_DIR).join(INDEX_FILE);
+        let stored: Vec<Entry> = if file.exists() {
+            serde_json::from_slice(&fs::read(&file).with_context(|| {
+                format!("Unable to read index '{}'.", file.display())
+            })?)
+            .with_context(|| {
+                format!("Unable to parse index '{}'.", file.display())
+            })?
+        } else {
+            Vec::new()
+        };
+
+        let mut entries = Entries::default();
+        for entry in stored {
+            if let Ok(hash) = u64::from_str_radix(&entry.hash, 16) {
+                entries.insert(entry.path, (entry.size, hash), entry.modified);
+            }
+        }
+
+        // Files that are gone are dropped, new or changed ones hashed.
+        let files = library_files(root);
+        let stale: Vec<_> = files
+            .iter()
+            .filter(|(path, (size, modified))| {
+                entries.by_path.get(path).is_none_or(|(key, stored)| {
+                    key.0 != *size || modified.is_none() || stored != modified
+                })
+            })
+            .collect();
+        let hashed = stale
+            .par_iter()
+            .map(|(path, (size, modified))| {
+                let path_in_root = root.join(path);
+                let hash = throttle.run(&[&path_in_root], || {
+                    file_hash(&path_in_root, *size)
+                })?;
+                Ok((path.clone(), (*size, hash), *modified))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if !hashed.is_empty() {
+            info!("Indexed {} file(s) in {}.", hashed.len(), root.display());
+        }
+
+        let present: HashMap<_, _> = files.into_iter().collect();
+        let gone: Vec<_> = entries
+            .by_path
+            .keys()
+            .filter(|path| !present.contains_key(*path))
+            .cloned()
+            .collect();
+        for path in gone {
+            entries.remove(&path);
+        }
+        for (path, key, modified) in hashed {
+            entries.insert(path, key, modified);
+        }
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            entries: Mutex::new(entries),
+        })
+    }
+
+    /// Returns the key of `path` and a file in the library with the same
+    /// content, other than `path` itself.
+    pub fn find(&self, path: &Path) -> Result<(Key, Option<PathBuf>)> {
+        let size = path
+            .metadata()
+            .with_context(|| {
+                format!("Unable to read size of '{}'.", path.display())
+            })?
+            .len();
+        let key = (size, file_hash(path, size)?);
+
+        let this = path.canonicalize().ok();
+        let entries = self.entries.lock().unwrap();
+        let existing = entries.by_key.get(&key).and_then(|paths| {
+            paths.iter().find_map(|relative| {
+                let candidate = self.root.join(relative);
+                let metadata = candidate.metadata().ok()?;
+                if metadata.len() != size
+                    || candidate.canonicalize().ok() == this
+                {
+                    return None;
+                }
+                // Changed since indexed, so the hash may be stale.
+                let stored = entries.by_path.get(relative)?.1;
+                if stored.is_none() || modified(&metadata) != stored {
+                    let hash = file_hash(&candidate, size).ok()?;
+                    (hash == key.1).then_some(candidate)
+                } else {
+                    Some(candidate)
+                }
+            })
+        });
+
+        Ok((key, existing))
+    }
+
+    /// Records that the file with `key` was moved from `source` to
+    /// `destination`; either may be outside the library.
+    pub fn moved(&self, key: Key, source: &Path, destination: &Path) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(path) = self.relative(source) {
+            entries.remove(&path);
+        }
+        if let Some(path) = self.relative(destination) {
+            let modified =
+                destination.metadata().ok().and_then(|m| modified(&m));
+            entries.insert(path, key, modified);
+        }
+    }
+
+    /// Records that the file at `path` was removed.
+    pub fn removed(&self, path: &Path) {
+        if let Some(path) = self.relative(path) {
+            self.entries.lock().unwrap().remove(&path);
+        }
+    }
+
+    /// Writes the index to the library.
+    pub fn store(&self) -> Result<()> {
+        let dir = self.root.join(STATE_DIR);
+        fs::create_dir_all(&dir).with_context(|| {
+            format!("Unable to create folder '{}'.", dir.display())
+        })?;
+
+        let entries = self.entries.lock().unwrap();
+        let mut stored: Vec<_> = entries
+            .by_path
+            .iter()
+            .map(|(path, ((size, hash), modified))| Entry {
+                path: path.clone(),
+                size: *size,
+                hash: format!("{hash:016x}"),
+                modified: *modified,
+            })
+            .collect();
+        stored.sort_by(|a, b| a.path.cmp(&b.path));
+
+        // Write next to the index and rename, so it is never half written.
+        let file = dir.join(INDEX_FILE);
+        let temp = dir.join(format!("{INDEX_FILE}.tmp"));
+        fs::write(&temp, serde_json::to_vec(&stored)?)
+            .and_then(|()| fs::rename(&temp, &file))
+            .with_context(|| {
+                format!("Unable to write index '{}'.", file.display())
+            })
+    }
+
+    /// Returns `path` relative to the library, if it is inside.
+    fn relative(&self, path: &Path) -> Option<PathBuf> {
+        let root = self.root.canonicalize().ok()?;
+        let parent = path.parent()?.canonicalize().ok()?;
+        Some(parent.strip_prefix(root).ok()?.join(path.file_name()?))
+    }
+}
+
+/// Returns the modification time of a file in nanoseconds since the Unix
+/// epoch.
+pub(crate) fn modified(metadata: &fs::Metadata) -> Option<u64> {
+    let since_epoch = metadata.modified().ok()?.duration_since(UNIX_EPOCH);
+    u64::try_from(since_epoch.ok()?.as_nanos()).ok()
+}
+
+/// Returns the media files in the library at `root` with their sizes and
+/// modification times, relative to `root`.
+fn library_files(root: &Path) -> Vec<(PathBuf, (u64, Option<u64>))> {
+    WalkDir::new(root)
+        .into_iter()
+        .filter_entry(|e| {
+            e.depth() == 0 || !e.file_name().to_string_lossy().starts_with('.')
+        })
+        .flatten()
+        .filter(|e| e.file_type().is_file() && file_kind(e.path()).is_some())
+        .filter_map(|e| {
+            let metadata = e.metadata().ok()?;
+            Some((
+                e.path().strip_prefix(root).ok()?.to_path_buf(),
+                (metadata.len(), modified(&metadata)),
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn finds_content_anywhere_in_library() {
+        let tmp = TempDir::new().unwrap();
+        let library = tmp.path().join("library");
+        fs::create_dir_all(library.join("2023/08")).unwrap();
+        fs::write(library.join("2023/08/IMG_1234.JPG"), b"photo").unwrap();
+        fs::write(tmp.path().join("renamed.jpg"), b"photo").unwrap();
+        fs::write(tmp.path().join("other.jpg"), b"other").unwrap();
+
+        let index = Index::open(&library, &Throttle::new(None)).unwrap();
+        let (key, existing) =
+            index.find(&tmp.path().join("renamed.jpg")).unwrap();
+        assert_eq!(existing, Some(library.join("2023/08/IMG_1234.JPG")));
+
+        // A file in the library is no duplicate of itself.
+        let (_, existing) =
+            index.find(&library.join("2023/08/IMG_1234.JPG")).unwrap();
+        assert_eq!(existing, None);
+
+        let (other, existing) =
+            index.find(&tmp.path().join("other.jpg")).unwrap();
+        assert_eq!(existing, None);
+
+        // Moves are recorded and survive reopening.
+        fs::rename(tmp.path().join("other.jpg"), library.join("other.jpg"))
+            .unwrap();
+        index.moved(
+            other,
+            &tmp.path().join("other.jpg"),
+            &library.join("other.jpg"),
+        );
+        index.store().unwrap();
+
+        fs::write(tmp.path().join("copy.jpg"), b"other").unwrap();
+        let index = Index::open(&library, &Throttle::new(None)).unwrap();
+        let (_, existing) = index.find(&tmp.path().join("copy.jpg")).unwrap();
+        assert_eq!(existing, Some(library.join("other.jpg")));
+
+        // Files removed behind our back are dropped.
+        fs::remove_file(library.join("2023/08/IMG_1234.JPG")).unwrap();
+        let index = Index::open(&library, &Throttle::new(None)).unwrap();
+        let (found, existing) =
+            index.find(&tmp.path().join("renamed.jpg")).unwrap();
+        assert_eq!(found, key);
+        assert_eq!(existing, None);
+    }
+
+    #[test]
+    fn files_edited_in_place_are_hashed_again() {
+        let tmp = TempDir::new().unwrap();
+        let library = tmp.path().join("library");
+        fs::create_dir_all(&library).unwrap();
+        let photo = library.join("IMG_1234.JPG");
+        fs::write(&photo, b"photo").unwrap();
+        fs::write(tmp.path().join("old.jpg"), b"photo").unwrap();
+
+        let index = Index::open(&library, &Throttle::new(None)).unwrap();
+        index.store().unwrap();
+
+        // Same size, different content and modification time.
+        fs::write(&photo, b"PHOTO").unwrap();
+        let file = fs::File::options().write(true).open(&photo).unwrap();
+        file.set_modified(UNIX_EPOCH).unwrap();
+
+        let (_, existing) = index.find(&tmp.path().join("old.jpg")).unwrap();
+        assert_eq!(existing, None);
+
+        let index = Index::open(&library, &Throttle::new(None)).unwrap();
+        let (_, existing) = index.find(&tmp.path().join("old.jpg")).unwrap();
+        assert_eq!(existing, None);
+    }
+}
@@ -0,0 +1,78 @@
+//! Apple Live Photos and Google/Samsung motion photos.
+//!
+//! A Live Photo is a still, e.g. `IMG_1234.HEIC`, and a short movie,
+//! `IMG_1234.MOV`. Both carry the same content identifier: the still in its
+//! Apple MakerNote, the movie in its QuickTime metadata.
+//!
+//! Google and Samsung motion photos are a single JPEG with the movie appended
+//! and flagged in the embedded XMP.
+
+use crate::{maker_note, xmp};
+use std::{
+    fs,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+/// Apple MakerNote tag of the Live Photo content identifier.
+const CONTENT_IDENTIFIER_TAG: u16 = 0x0011;
+
+/// How much of the head of a file is searched for an XMP packet.
+const XMP_SEARCH_SIZE: u64 = 256 * 1024;
+
+/// Marker of the trailer Samsung appends to motion photos.
+const SAMSUNG_MOTION_PHOTO_MARKER: &[u8] = b"MotionPhoto_Data";
+
+/// How much of the tail of a file is searched for the Samsung marker.
+const TRAILER_SEARCH_SIZE: u64 = 64 * 1024;
+
+/// Returns the Live Photo content identifier from an Apple MakerNote.
+pub fn content_identifier(meta_data: &exif::Exif) -> Option<String> {
+    maker_note::apple_string(meta_data, CONTENT_IDENTIFIER_TAG)
+}
+
+/// Returns `true` if `path` is a Google or Samsung motion photo.
+pub fn is_motion_photo(path: &Path) -> bool {
+    let Ok(mut file) = fs::File::open(path) else {
+        return false;
+    };
+
+    let mut head = Vec::new();
+    if (&mut file)
+        .take(XMP_SEARCH_SIZE)
+        .read_to_end(&mut head)
+        .is_ok()
+        && xmp::find_packet(&head)
+            .is_some_and(|xml| xmp::parse(xml).motion_photo)
+    {
+        return true;
+    }
+
+    let mut tail = Vec::new();
+    file.seek(SeekFrom::End(-(TRAILER_SEARCH_SIZE as i64)))
+        .or_else(|_| file.seek(SeekFrom::Start(0)))
+        .and_then(|_| file.read_to_end(&mut tail))
+        .is_ok()
+        && tail
+            .windows(SAMSUNG_MOTION_PHOTO_MARKER.len())
+            .any(|w| w == SAMSUNG_MOTION_PHOTO_MARKER)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn samsung_motion_photo() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("20230815_143000.jpg");
+
+        fs::write(&path, b"\xff\xd8\xff\xd9").unwrap();
+        assert!(!is_motion_photo(&path));
+
+        fs::write(&path, b"\xff\xd8\xff\xd9ftypmp42...MotionPhoto_DataSEFT")
+            .unwrap();
+        assert!(is_motion_photo(&path));
+    }
+}
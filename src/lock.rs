@@ -0,0 +1,235 @@
+//! Locking a destination against concurrent runs.
+//!
+//! Two runs into the same destination could both find a path free and move
+//! different files there. So a run holds an advisory lock on
+//! `.exifmv/lock` in the destination, which also holds its PID.
+//!
+//! The lock file is removed when a run ends. If a run dies instead, the
+//! operating system releases its lock. Where file locks are not supported,
+//! e.g. on some network file systems, the PID is used instead: a lock file
+//! naming a process that is gone is stale and taken over.
+
+use crate::index::STATE_DIR;
+use anyhow::{Context, Result, anyhow};
+use log::{info, warn};
+use std::{
+    fs,
+    io::{ErrorKind, Read, Seek, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+const LOCK_FILE: &str = "lock";
+
+/// How often a lock without file locking support is checked when waiting.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A lock on a destination, held until dropped.
+#[derive(Debug)]
+pub(crate) struct RunLock {
+    path: PathBuf,
+    /// Holds the lock while open.
+    _file: fs::File,
+}
+
+impl RunLock {
+    /// Locks `dest_dir`. If another run holds the lock, waits for it with
+    /// `wait`, else fails.
+    pub fn acquire(dest_dir: &Path, wait: bool) -> Result<Self> {
+        let dir = dest_dir.join(STATE_DIR);
+        let path = dir.join(LOCK_FILE);
+
+        let mut file = loop {
+            // Also after waiting, as the other run may have removed it.
+            fs::create_dir_all(&dir).with_context(|| {
+                format!("Unable to create folder '{}'.", dir.display())
+            })?;
+            let mut file = match fs::File::options()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)
+            {
+                Ok(file) => file,
+                // The folder was removed in between.
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => {
+                    return Err(e).with_context(|| {
+                        format!(
+                            "Unable to open lock file '{}'.",
+                            path.display()
+                        )
+                    });
+                }
+            };
+
+            match file.try_lock() {
+                Ok(()) => (),
+                Err(fs::TryLockError::WouldBlock) => {
+                    let message = in_use(dest_dir, owner(&mut file));
+                    if !wait {
+                        return Err(anyhow!(
+                            "{} Use --wait-lock to wait for it.",
+                            message
+                        ));
+                    }
+                    info!("{} Waiting for it to finish.", message);
+                    file.lock().with_context(|| {
+                        format!("Unable to lock '{}'.", path.display())
+                    })?;
+                }
+                Err(fs::TryLockError::Error(e)) => {
+                    // No file locking here; go by the PID.
+                    match owner(&mut file) {
+                        Some(pid)
+                            if pid != std::process::id() && alive(pid) =>
+                        {
+                            let message = in_use(dest_dir, Some(pid));
+                            if !wait {
+                                return Err(anyhow!(
+                                    "{} Use --wait-lock to wait for it.",
+                                    message
+                                ));
+                            }
+                            thread::sleep(POLL_INTERVAL);
+                            continue;
+                        }
+                        Some(pid) => warn!(
+                            "Taking over the lock of {}, as exifmv (PID {}) is \
+                             gone ({}).",
+                            dest_dir.display(),
+                            pid,
+                            e
+                        ),
+                        None => (),
+                    }
+                }
+            }
+
+            // The run we waited for removed the file it locked; lock the one
+            // at `path` now.
+            if is_file_at(&file, &path) {
+                break file;
+            }
+        };
+
+        file.set_len(0)
+            .and_then(|()| file.rewind())
+            .and_then(|()| write!(file, "{}", std::process::id()))
+            .and_then(|()| file.sync_data())
+            .with_context(|| {
+                format!("Unable to write lock file '{}'.", path.display())
+            })?;
+
+        Ok(Self { path, _file: file })
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        // Removed while still locked, so runs waiting for it know to lock a
+        // new file, see `acquire()`.
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!(
+                "Unable to remove lock file '{}': {}",
+                self.path.display(),
+                e
+            );
+        }
+        if let Some(dir) = self.path.parent() {
+            let _ = fs::remove_dir(dir);
+        }
+    }
+}
+
+/// Returns `true` if `file` is the file at `path`.
+fn is_file_at(file: &fs::File, path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        match (file.metadata(), fs::metadata(path)) {
+            (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+            _ => false,
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = file;
+        path.exists()
+    }
+}
+
+fn in_use(dest_dir: &Path, pid: Option<u32>) -> String {
+    match pid {
+        Some(pid) => format!(
+            "{} is in use by another exifmv run (PID {}).",
+            dest_dir.display(),
+            pid
+        ),
+        None => {
+            format!("{} is in use by another exifmv run.", dest_dir.display())
+        }
+    }
+}
+
+/// Returns the PID in the lock file.
+fn owner(file: &mut fs::File) -> Option<u32> {
+    let mut contents = String::new();
+    file.rewind().ok()?;
+    file.read_to_string(&mut contents).ok()?;
+    contents.trim().parse().ok()
+}
+
+/// Returns `true` if the process `pid` exists.
+fn alive(pid: u32) -> bool {
+    #[cfg(unix)]
+    {
+        let Ok(pid) = libc::pid_t::try_from(pid) else {
+            return false;
+        };
+        // SAFETY: signal 0 only checks whether the process exists.
+        let exists = unsafe { libc::kill(pid, 0) } == 0;
+        exists
+            || std::io::Error::last_os_error().kind()
+                == ErrorKind::PermissionDenied
+    }
+    #[cfg(not(unix))]
+    {
+        // Without a way to tell, assume the lock is held.
+        let _ = pid;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn second_run_fails_fast() {
+        let tmp = TempDir::new().unwrap();
+        let lock = RunLock::acquire(tmp.path(), false).unwrap();
+        let error = RunLock::acquire(tmp.path(), false).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains(&format!("PID {}", std::process::id())),
+            "{error}"
+        );
+
+        drop(lock);
+        RunLock::acquire(tmp.path(), false).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dead_owner_is_stale() {
+        // PIDs are below 2^22 on Linux.
+        assert!(!alive(u32::MAX >> 1));
+        assert!(alive(std::process::id()));
+    }
+}
@@ -1,7 +1,8 @@
 #![recursion_limit = "1024"]
 //! Moves images into a folder hierarchy based on EXIF tags.
 //!
-//! XMP sidecar files are also moved, if present.
+//! Sidecar files (XMP, Google Photos Takeout JSON, video thumbnails, …) are
+//! also moved, if present.
 //!
 //! The folder hierarchy is configurable via a template string
 //! (`-f`/`--format`). The default template is:
@@ -9,8 +10,9 @@
 //! `{year}/{month}/{day}/{filename}.{extension}`
 //!
 //! Available template variables: `year`, `month`, `day`, `hour`, `minute`,
-//! `second`, `filename`, `extension`, `camera_make`, `camera_model`, `lens`,
-//! `iso`, `focal_length`.
+//! `second`, `filename`, `extension`, `pair_role`, `live`, `burst`,
+//! `camera_make`, `camera_model`, `lens`, `iso`, `focal_length`, `place`,
+//! `rating`, `label`, `keywords`.
 //!
 //! Run `exifmv --help` for full variable descriptions and examples.
 //!
@@ -40,9 +42,133 @@
 //! to the user’s trash folder from where they can be restored to their original
 //! location on most operating systems.
 //!
-//! Before doing any deletion or moving-to-trash `exifmv` checks that the file
-//! size matches. Use `--checksum` to verify file contents instead, eliminating
-//! false positives from same-size different-content files.
+//! Before doing any deletion or moving-to-trash `exifmv` checks that the
+//! existing file has the same content, by size and XXH3 hash. Without
+//! `--remove-source` or `--trash-source` only the size is compared, unless
+//! `--checksum` is given. `--trust-size` removes or trashes sources on a size
+//! match alone, which is faster but deletes a file that merely has the same
+//! size. `--byte-compare` compares contents byte by byte instead of hashing
+//! them, also for files found with `--index`.
+//!
+//! Moves to another file system and `import --copy` write to a hidden
+//! `.NAME.exifmv-tmp` file next to the destination first. It is synced to
+//! disk and its size (and with `--checksum` its contents) checked against the
+//! source before it is renamed into place. Only then is the source removed.
+//! On Linux, the contents are read back from disk for the check; elsewhere
+//! they may come from the operating system's cache.
+//! An interrupted copy never leaves a partial file under the final name.
+//!
+//! Copies keep the access and modification times, permissions, owner and
+//! user extended attributes of the source where the destination permits it;
+//! file systems like exFAT or SMB shares may refuse some of them. With
+//! `--set-mtime` (or `set-mtime = true`) the modification time of moved and
+//! copied files is set to their capture time instead.
+//!
+//! # Quarantine
+//!
+//! Where there is no trash, e.g. on a headless server or a NAS mount,
+//! `--quarantine DIR` moves sources aside instead of removing them. Sources
+//! that are duplicates of a file at the destination, that conflict with one
+//! or whose metadata cannot be read go to a folder for the day below `DIR`,
+//! e.g. `DIR/2024-05-01/DCIM/100CANON/IMG_1234.CR3`, keeping their path
+//! relative to SOURCE. Sidecars go with them, as do the other files of a
+//! group whose first file conflicts.
+//!
+//! Each file quarantined is listed in `manifest.jsonl` in that folder with
+//! its original path, the reason and, for duplicates and conflicts, the file
+//! at the destination. With `import --copy` files are copied into the
+//! quarantine.
+//!
+//! # Unsorted Files
+//!
+//! Files whose metadata cannot be read, e.g. without EXIF data, corrupt or in
+//! an unsupported container, are left in SOURCE with a warning. With
+//! `--unsorted TEMPLATE` (or `unsorted` in the config file) they are moved to
+//! where TEMPLATE puts them instead, so SOURCE can be emptied:
+//!
+//! ```text
+//! exifmv --unsorted 'unsorted/{source_relpath}' ~/Pictures/card ~/Pictures
+//! ```
+//!
+//! `{source_relpath}` is the path of a file below SOURCE, e.g.
+//! `DCIM/100CANON/IMG_1234.CR3`. Only file variables like `{filename}` have
+//! values; date and metadata variables are empty or `unknown`. Unsorted files
+//! are not quarantined, see above.
+//!
+//! # Reports
+//!
+//! At the end of a run, `exifmv` prints how many files were moved, copied,
+//! skipped as duplicates or conflicts, removed, trashed, quarantined or
+//! failed, and their size. `--report FILE` also writes a record of each file
+//! with its source, destination, action, size, date source and error, if any.
+//! The format follows the extension: `.json`, `.ndjson` (or `.jsonl`) or
+//! `.csv`.
+//!
+//! ```text
+//! exifmv --report run.csv ~/Pictures/card ~/Pictures
+//! ```
+//!
+//! # Exit Codes
+//!
+//! Errors fall into classes, each with its own exit code:
+//!
+//! | Class        | Exit code | Cause                                      |
+//! |--------------|-----------|--------------------------------------------|
+//! | `metadata`   | 3         | No date source has a capture time.         |
+//! | `unreadable` | 4         | The metadata of a file cannot be parsed.   |
+//! | `io`         | 5         | Reading or writing a file failed.          |
+//! | `permission` | 6         | Access to a file was denied.               |
+//! | `conflict`   | 7         | A different file is at the destination.    |
+//! | `template`   | 8         | The path format template is invalid.       |
+//!
+//! Any other error exits with 1. A run fails if a file fails with an error in
+//! a class of `--fail-on`. By default, files that fail are only logged and a
+//! run exits with 0 unless something else goes wrong. If files failed in
+//! several classes, the most severe decides the exit code: `template`, then
+//! `permission`, `io`, `unreadable`, `metadata` and `conflict`. So
+//! `--fail-on conflict,metadata` only fails on conflicts and missing dates.
+//!
+//! `--halt-on-errors` (or `halt-on-errors = true`) stops a run at the first
+//! such failure: files being moved are finished, but no more are started.
+//! Unless `--fail-on` is given, it fails on all classes but `conflict`;
+//! `--fail-on none` only fails on other errors. In watch mode, it stops
+//! watching after a batch with such a failure; otherwise watching goes on and
+//! the failure only shows in the exit code once it ends.
+//!
+//! # Free Space and Slow Devices
+//!
+//! Before moving anything, `exifmv` adds up the sizes of the files that will
+//! be copied, i.e. those on another file system than DESTINATION or all of
+//! them with `import --copy`. If DESTINATION lacks the space it stops; with
+//! `--ignore-free-space` (or `--dry-run`) it only warns.
+//!
+//! On Linux, files on spinning disks and removable devices like memory cards
+//! are read and moved one at a time, as accessing many at once makes them
+//! slower. The same goes for moves to such devices.
+//! `--device-threads N` (or `device-threads = N`) allows N files at once per
+//! device instead, for any kind of device.
+//!
+//! # Concurrent Runs
+//!
+//! Runs that write to DESTINATION, including `watch`, `import`,
+//! `reorganize` and `dupes --trash`, lock it via `DESTINATION/.exifmv/lock`,
+//! which holds the run's process ID. Another run into DESTINATION fails right
+//! away, or waits for the lock with `--wait-lock`, so e.g. two cron jobs never
+//! race for the same file names. The lock file is removed at the end of a
+//! run; if a run dies, its lock is released all the same. `--no-lock` skips
+//! locking.
+//!
+//! # Interrupted Runs
+//!
+//! Progress is recorded in `DESTINATION/.exifmv/run` as files are moved. On
+//! Unix, Ctrl-C lets the files being moved finish and then stops; press it
+//! again to exit immediately.
+//!
+//! `--resume` continues an interrupted run, or one that failed, e.g. on a
+//! file it could not read: the files it already moved or copied are
+//! skipped, which matters with `import --copy` where they are still on the
+//! card. Temp files of copies that were cut short, e.g. by a power loss, are
+//! removed at the start of the next run into DESTINATION.
 //!
 //! # Configuration File
 //!
@@ -58,10 +184,221 @@
 //! halt-on-errors = false
 //! dereference = false
 //! checksum = false
+//! index = false
+//! date-sources = ["xmp", "exif", "takeout", "filename"]
+//! rename-conflicts = false
+//! set-mtime = false
+//! jpeg-with-raw = "keep"
+//! burst-threshold = 1.0
+//! unsorted = "unsorted/{source_relpath}"
 //! ```
 //!
 //! CLI arguments override config file settings.
 //!
+//! ## Date Sources
+//!
+//! The capture date is taken from the first source in `date-sources` (or
+//! `--date-source`) that has one:
+//!
+//! - `xmp`: `exif:DateTimeOriginal`, `photoshop:DateCreated` or
+//!   `xmp:CreateDate` from an XMP sidecar. This picks up capture dates
+//!   corrected in Lightroom or darktable and dates for raws and videos
+//!   lacking EXIF.
+//!
+//! - `exif`: the EXIF `DateTimeOriginal` tag, or the QuickTime creation date
+//!   of movies.
+//!
+//! - `takeout`: `photoTakenTime` from a Google Photos Takeout sidecar, e.g.
+//!   `IMG_1234.jpg.json` or a truncated variant like `IMG_1234.j.json`.
+//!   Takeout strips EXIF dates from exports. The sidecar's `geoData` is also
+//!   used for `{place}` if the file has no GPS data.
+//!
+//! - `filename`: a date embedded in the file name, e.g.
+//!   `IMG-20230815-WA0003.jpg` (WhatsApp),
+//!   `Screenshot_2023-08-15-14-30-00.png` or `PXL_20230815_143000123.jpg`.
+//!
+//! Put `filename` first if your cameras' clocks can't be trusted. Additional
+//! patterns are regular expressions with the named groups `year`, `month`,
+//! `day` and optionally `hour`, `minute` and `second`. They are tried before
+//! the built-in ones:
+//!
+//! ```toml
+//! filename-patterns = [
+//!     '^holiday_(?<day>\d{2})\.(?<month>\d{2})\.(?<year>\d{4})',
+//! ]
+//! ```
+//!
+//! ## Sidecars
+//!
+//! Sidecars follow their primary file to its destination and naming,
+//! including `--make-lowercase` and `--rename-conflicts` suffixes. They are
+//! matched by patterns where `{name}` is the primary's file name and `{stem}`
+//! that name without its extension. Suffixes match as written, in lowercase
+//! or in uppercase. The default is:
+//!
+//! ```toml
+//! sidecars = [
+//!     "{name}.xmp", "{stem}.xmp", "{name}.pp3", "{name}.dop",
+//!     "{stem}.thm", "{stem}.aae", "{name}.json", "{stem}.lrv",
+//! ]
+//! ```
+//!
+//! ## RAW+JPEG Pairs
+//!
+//! A RAW and a JPEG (or HEIF) with the same name in the same folder, shot
+//! within a second of each other, e.g. `DSC_0001.NEF` and `DSC_0001.JPG`, are
+//! moved as a pair. Both go to the RAW's destination folder and get the same
+//! `--rename-conflicts` suffix. If the RAW stays because of a conflict, so
+//! does the JPEG.
+//!
+//! `{pair_role}` is `raw` or `jpeg` for the files of a pair and empty
+//! otherwise, so `{year}/{month}/{pair_role}/{filename}.{extension}` puts the
+//! JPEGs of pairs into a `jpeg` subfolder.
+//!
+//! With `jpeg-with-raw = "remove"` (or `--jpeg-with-raw remove`) the JPEG and
+//! its sidecars are deleted once the RAW was moved; `"trash"` moves them to
+//! the trash instead.
+//!
+//! ## Live Photos
+//!
+//! The still and the movie of an Apple Live Photo, e.g. `IMG_1234.HEIC` and
+//! `IMG_1234.MOV`, are moved as a pair. They are matched by the content
+//! identifier Apple stores in both or, lacking that, by name and a capture
+//! time difference of up to three seconds. The still's capture time decides
+//! where both go, so the movie never ends up a day apart.
+//!
+//! `{live}` is `live` for both files of a Live Photo and for Google and
+//! Samsung motion photos (JPEGs with an embedded movie), empty otherwise.
+//!
+//! ## Bursts
+//!
+//! `{burst}` is the file name (without extension) of the first frame of a
+//! burst for all its frames, and empty for other files. So
+//! `{year}/{month}/{day}/{burst}/{filename}.{extension}` moves each burst into
+//! its own subfolder of the day folder.
+//!
+//! Frames shot by an iPhone are linked by the burst identifier it stores. For
+//! other cameras, at least three consecutive frames from the same camera and
+//! folder, each shot within `burst-threshold` seconds (default: 1, including
+//! fractions of a second where the camera records them) of the previous one,
+//! form a burst.
+//!
+//! ## Places
+//!
+//! The `{place}` variable resolves a file's GPS coordinates to the first
+//! matching named place. Places are either a circle (center and radius in
+//! meters) or a polygon of `[latitude, longitude]` vertices:
+//!
+//! ```toml
+//! place-fallback = "elsewhere"
+//!
+//! [[place]]
+//! name = "home"
+//! center = [52.5200, 13.4050]
+//! radius = 150.0
+//!
+//! [[place]]
+//! name = "studio"
+//! polygon = [[52.50, 13.40], [52.50, 13.41], [52.51, 13.41], [52.51, 13.40]]
+//! ```
+//!
+//! Files without GPS data or outside all places get `place-fallback`, or
+//! `unknown` if that is not set.
+//!
+//! # Watch Mode
+//!
+//! `exifmv watch SOURCE DESTINATION` keeps running and moves files as they
+//! appear in an ingest folder, e.g. one a phone or a camera uploads to. Files
+//! already there are moved first.
+//!
+//! A file is moved once neither it nor a related file, i.e. one in the same
+//! folder whose name starts with the same stem like `IMG_1234.HEIC.xmp`, was
+//! written to or changed size for `--settle` seconds (default: 2). So uploads
+//! are complete and sidecars arriving shortly after their image still go
+//! with it. Hidden files, e.g. partial uploads, are ignored.
+//!
+//! All other options work like for a one-off run.
+//!
+//! # Card Import
+//!
+//! `exifmv import MOUNT_POINT DESTINATION` imports from a camera card. Only
+//! the folders cameras write to are searched: DCF folders like
+//! `DCIM/100MSDCF`, `PRIVATE/M4ROOT/CLIP` for Sony movies (with their
+//! `C0001M01.XML` metadata as sidecars) and `PRIVATE/AVCHD/BDMV/STREAM`.
+//! Thumbnails and other camera housekeeping files are left alone.
+//!
+//! With `--copy` files are copied instead of moved and the card is never
+//! touched. The hashes of imported files are remembered per card (by volume
+//! UUID) in `imports.toml` next to the configuration file. So a card can be
+//! imported again and again without ever formatting it and only new shots
+//! are imported each time. Files whose size and modification time are the
+//! same as at the last import are not read again.
+//!
+//! # Library Index
+//!
+//! Duplicates are normally only found at the exact destination path. With
+//! `--index` (or `index = true`) `exifmv` keeps an index of all media files in
+//! DESTINATION by size and XXH3 hash in `DESTINATION/.exifmv/index.json`. A
+//! source whose content is anywhere in the library is then treated as a
+//! duplicate of that file, e.g. skipped or, with `--trash-source`, trashed,
+//! even if it was moved there under another name or template. Its sidecars
+//! join the existing file.
+//!
+//! The index is updated on every move. Files added to or removed from the
+//! library by other means are picked up at the start of the next run; only
+//! new files and files whose size changed are hashed then.
+//!
+//! # Finding Duplicates
+//!
+//! `exifmv dupes LIBRARY` lists the media files in LIBRARY and its subfolders
+//! that have the same content. Files are compared by size, then by a hash of
+//! their first and last 64 KiB and only then by a hash of their full contents,
+//! so this is fast even for large libraries.
+//!
+//! The first file of each set is the one to keep: the oldest (by
+//! modification time) or, with `--keep shortest`, the one with the shortest
+//! path. `--trash` moves all other copies to the trash, along with their
+//! sidecars unless another file still uses them, like `IMG_1.xmp` of the RAW
+//! next to a redundant `IMG_1.jpg`. Combine it with `--dry-run` to see what
+//! would happen. `--json` prints the sets as JSON. Files that cannot be read
+//! are skipped with a warning.
+//!
+//! # Reorganizing a Library
+//!
+//! `exifmv reorganize LIBRARY --from OLD --to NEW` moves the files of a
+//! library organized with the template OLD to where the template NEW puts
+//! them, e.g. from `{year}-{month}-{day}/{filename}.{extension}` to
+//! `{year}/{month}/{day}/{filename}.{extension}`. Sidecars and pairs move
+//! with their files; `{filename}` and `{extension}` are taken from the
+//! current names. Folders left empty are removed.
+//!
+//! All moves are planned before any file is touched. Files whose path does
+//! not match OLD stay where they are, as do files (with their companions)
+//! that would overwrite another file. Files swapping places are moved via a
+//! temporary name. Use `--dry-run` to see the plan first.
+//!
+//! # Verifying a Library
+//!
+//! `exifmv verify LIBRARY` checks that every media file in LIBRARY is where
+//! the template (`--format` or the configuration's) puts it. It prints
+//!
+//! - misplaced files, with where they should be,
+//! - orphan sidecars, e.g. an XMP file whose image is gone, and
+//! - files whose metadata cannot be read
+//!
+//! and exits with a non-zero status if it found any, so it can run as a
+//! nightly check. Files renamed to resolve a conflict, e.g.
+//! `IMG_1234-1.jpg`, count as in place.
+//!
+//! # Statistics
+//!
+//! `exifmv stats PATH` reads the media files in PATH and its subfolders like
+//! when moving them and counts them and their sizes by capture month, camera,
+//! lens and kind (RAW, image or movie). It also counts the files missing a
+//! capture time, camera, lens or location. This helps choosing a template
+//! and spotting cameras with a wrong clock, e.g. files from 2000-01. `--json`
+//! prints the numbers as JSON.
+//!
 //! # Features
 //!
 //! - **color** (default): Enables colored CLI help output. Disable with
@@ -78,29 +415,73 @@
 //! you feel like fixing any of those or add some nice features, I look forward
 //! to merge your PRs. Beers!
 use anyhow::{Context, Result, anyhow};
-use chrono::{Datelike, Days, NaiveDate, NaiveTime, Timelike};
+use chrono::{Datelike, Days, NaiveDateTime, NaiveTime, Timelike};
 #[cfg(feature = "color")]
 use clap::builder::styling::{AnsiColor, Styles};
-use clap::{Arg, ArgAction, ArgMatches, arg, command};
-use exif::{DateTime, Tag, Value};
+use clap::{
+    Arg, ArgAction, ArgMatches, Command, arg, builder::PossibleValuesParser,
+    command,
+};
+use exif::DateTime;
 use indicatif::MultiProgress;
 use indicatif_log_bridge::LogWrapper;
 use log::{info, warn};
 use rayon::prelude::*;
 use simplelog::*;
 use std::{
+    cell::RefCell,
+    collections::HashMap,
+    ffi::OsString,
     path::{Path, PathBuf},
-    sync::Arc,
+    process::ExitCode,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
 };
 use walkdir::{DirEntry, WalkDir};
 
+mod burst;
 mod config;
+mod device;
+mod dupes;
+mod error;
+mod filename_date;
+mod group;
+mod import;
+mod index;
+mod live;
+mod lock;
+mod maker_note;
+mod metadata;
+mod place;
+mod quarantine;
+mod quicktime;
+mod reorganize;
+mod report;
+mod resume;
+mod sidecar;
+mod stats;
+mod takeout;
 mod template;
 #[cfg(test)]
 mod tests;
 mod util;
+mod verify;
+mod watch;
+mod xmp;
 
-use config::Config as AppConfig;
+use config::{Config as AppConfig, DateSource, JpegWithRaw};
+use device::Throttle;
+use error::{ErrorClass, Failures, IoContext};
+use group::{Group, Item, PairRole};
+use index::{Index, Key};
+use lock::RunLock;
+use metadata::Metadata;
+use quarantine::{Quarantine, Reason};
+use report::{Action, Record};
+use resume::RunState;
 use template::{Template, TemplateContext};
 use util::*;
 
@@ -114,7 +495,21 @@ const STYLES: Styles = Styles::styled()
     .invalid(AnsiColor::Red.on_default())
     .error(AnsiColor::Red.on_default().bold());
 
-fn main() -> Result<()> {
+fn main() -> ExitCode {
+    match run(std::env::args_os()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(error::exit_code(&e))
+        }
+    }
+}
+
+fn run<I, T>(args: I) -> Result<()>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
     // Get default config path for help text.
     let default_config_path =
         confy::get_configuration_file_path("exifmv", "config")
@@ -128,66 +523,166 @@ fn main() -> Result<()> {
     #[cfg(not(feature = "color"))]
     let cmd = command!();
 
-    let args = cmd
+    let mut args = cmd
         .author("Moritz Moeller <virtualritz@protonmail.com>")
         .about("Moves images into a folder hierarchy based on EXIF DateTime tags")
         .long_about("Moves images into a folder hierarchy based on EXIF DateTime tags.\nUse -f/--format to customize the destination path template. See -f for details.")
         .arg(
-            arg!(-v --verbose "Babble a lot").action(ArgAction::SetTrue),
+            arg!(-v --verbose "Babble a lot")
+                .action(ArgAction::SetTrue)
+                .global(true),
         )
         .arg(
             Arg::new("recursive")
                 .short('r')
                 .long("recursive")
                 .help("Recurse subdirectories")
-                .action(ArgAction::SetTrue),
+                .action(ArgAction::SetTrue)
+                .global(true),
         )
         .arg(
             Arg::new("trash-source")
                 .long("trash-source")
                 .conflicts_with("remove-source")
-                .help("Move any SOURCE file existing at DESTINATION and matching in size to the system's trash")
-                .action(ArgAction::SetTrue),
+                .help("Move any SOURCE file existing at DESTINATION with the same content to the system's trash")
+                .action(ArgAction::SetTrue)
+                .global(true),
         )
         .arg(
             Arg::new("remove-source")
                 .long("remove-source")
                 .conflicts_with("trash-source")
                 .help("Delete source files that already exist at the destination")
-                .action(ArgAction::SetTrue),
+                .action(ArgAction::SetTrue)
+                .global(true),
         )
         .arg(
             Arg::new("dry-run")
                 .long("dry-run")
                 .help("Do not move any files (forces --verbose)")
-                .action(ArgAction::SetTrue),
+                .action(ArgAction::SetTrue)
+                .global(true),
         )
         .arg(
             Arg::new("make-lowercase")
                 .short('l')
                 .long("make-lowercase")
                 .help("Change filename & extension to lowercase")
-                .action(ArgAction::SetTrue),
+                .action(ArgAction::SetTrue)
+                .global(true),
         )
         .arg(
             Arg::new("dereference-symlinks")
                 .short('L')
                 .long("dereference")
                 .help("Dereference symbolic links")
-                .action(ArgAction::SetTrue),
+                .action(ArgAction::SetTrue)
+                .global(true),
         )
         .arg(
             Arg::new("halt")
                 .short('H')
                 .long("halt-on-errors")
-                .help("Exit if any errors are encountered")
-                .action(ArgAction::SetTrue),
+                .help("Stop at the first error that fails the run")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("fail-on")
+                .long("fail-on")
+                .value_name("CLASS,…")
+                .value_delimiter(',')
+                .value_parser(PossibleValuesParser::new(
+                    ErrorClass::NAMES.iter().chain(&["none"]),
+                ))
+                .help("Error classes that make the run fail, or none")
+                .global(true),
         )
         .arg(
             Arg::new("checksum")
                 .long("checksum")
-                .help("Verify file contents for duplicate detection")
-                .action(ArgAction::SetTrue),
+                .help("Verify file contents for duplicate detection and copies")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("report")
+                .long("report")
+                .value_name("FILE")
+                .help("Write what was done with each file to FILE (.json, .ndjson or .csv)")
+                .global(true),
+        )
+        .arg(
+            Arg::new("unsorted")
+                .long("unsorted")
+                .value_name("TEMPLATE")
+                .help("Move files without metadata to where TEMPLATE puts them, e.g. 'unsorted/{source_relpath}'")
+                .global(true),
+        )
+        .arg(
+            Arg::new("quarantine")
+                .long("quarantine")
+                .value_name("DIR")
+                .conflicts_with_all(["remove-source", "trash-source"])
+                .help("Move duplicates, conflicting and unreadable files into DIR")
+                .global(true),
+        )
+        .arg(
+            Arg::new("trust-size")
+                .long("trust-size")
+                .help("Remove or trash sources whose destination only matches in size")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("byte-compare")
+                .long("byte-compare")
+                .help("Compare file contents byte by byte instead of by hash")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("index")
+                .long("index")
+                .help("Skip files whose content is anywhere in DESTINATION, using an index kept there")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("rename-conflicts")
+                .long("rename-conflicts")
+                .help("Append -1, -2, … to the name if a different file exists at the destination")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("resume")
+                .long("resume")
+                .help("Skip the files an interrupted run into DESTINATION moved or copied already")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("no-lock")
+                .long("no-lock")
+                .help("Do not lock DESTINATION against other runs")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("wait-lock")
+                .long("wait-lock")
+                .help("Wait for other runs into DESTINATION to finish instead of failing")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("no-lock")
+                .global(true),
+        )
+        .arg(
+            Arg::new("set-mtime")
+                .long("set-mtime")
+                .help("Set the modification time of moved files to their capture time")
+                .action(ArgAction::SetTrue)
+                .global(true),
         )
         /*.arg(
             Arg::new("cleanup")
@@ -199,7 +694,58 @@ fn main() -> Result<()> {
             Arg::new("day-wrap")
                 .long("day-wrap")
                 .value_name("H[H][:M[M]]")
-                .help("The time at which the date wraps to the next day"),
+                .help("The time at which the date wraps to the next day")
+                .global(true),
+        )
+        .arg(
+            Arg::new("date-source")
+                .long("date-source")
+                .value_name("SOURCE,…")
+                .value_delimiter(',')
+                .value_parser(PossibleValuesParser::new(DateSource::NAMES))
+                .help("Where to take the capture date from, in order of precedence [default: xmp,exif,takeout,filename]")
+                .global(true),
+        )
+        .arg(
+            Arg::new("jpeg-with-raw")
+                .long("jpeg-with-raw")
+                .value_name("ACTION")
+                .value_parser(PossibleValuesParser::new(JpegWithRaw::NAMES))
+                .help("What to do with the JPEG of a RAW+JPEG pair [default: keep]")
+                .global(true),
+        )
+        .arg(
+            Arg::new("burst-threshold")
+                .long("burst-threshold")
+                .value_name("SECONDS")
+                .value_parser(|s: &str| {
+                    s.parse::<f64>()
+                        .ok()
+                        .filter(|t| t.is_finite() && *t >= 0.0)
+                        .ok_or("expected a non-negative number of seconds")
+                })
+                .help("Maximum time between two frames of a burst [default: 1]")
+                .global(true),
+        )
+        .arg(
+            Arg::new("device-threads")
+                .long("device-threads")
+                .value_name("N")
+                .value_parser(|s: &str| {
+                    s.parse::<usize>()
+                        .ok()
+                        .filter(|n| *n > 0)
+                        .ok_or("expected a positive number")
+                })
+                .help("Read and move at most N files at once per device [default: 1 for spinning disks and memory cards, else unlimited]")
+                .global(true),
+        )
+        .arg(
+            Arg::new("ignore-free-space")
+                .long("ignore-free-space")
+                .help("Only warn if DESTINATION lacks the space for the files to copy")
+                .action(ArgAction::SetTrue)
+                .global(true),
         )
         .arg(
             Arg::new("format")
@@ -212,7 +758,7 @@ Path format template for the destination file hierarchy.\n\
 Variables are enclosed in braces. Literal braces: \\{ \\}\n\
 \n\
 Available variables:\n\
-  Date/time (from EXIF DateTimeOriginal):\n\
+  Date/time (from the first --date-source providing one):\n\
     {year}          ➞  2024\n\
     {month}         ➞  08       (zero-padded)\n\
     {day}           ➞  15       (zero-padded)\n\
@@ -222,12 +768,22 @@ Available variables:\n\
   File:\n\
     {filename}      ➞  IMG_1234 (stem, without extension)\n\
     {extension}     ➞  arw\n\
+    {pair_role}     ➞  raw      (raw/jpeg for RAW+JPEG pairs, else empty)\n\
+    {live}          ➞  live     (Live Photos & motion photos, else empty)\n\
+    {burst}         ➞  DSC_0100 (first frame of a burst, else empty)\n\
+    {source_relpath} ➞ DCIM/100/IMG_1234.arw (path below SOURCE)\n\
   Camera (from EXIF, 'unknown' if absent):\n\
     {camera_make}   ➞  Sony\n\
     {camera_model}  ➞  ILCE-7M3\n\
     {lens}          ➞  FE-35mm-F1.4-GM\n\
     {iso}           ➞  400\n\
     {focal_length}  ➞  35\n\
+  Location (from EXIF GPS and [[place]] in the config file):\n\
+    {place}         ➞  home     ('place-fallback' or 'unknown' if none)\n\
+  XMP sidecar ('unknown' if absent):\n\
+    {rating}        ➞  4\n\
+    {label}         ➞  Red\n\
+    {keywords}      ➞  Berlin,Street (comma-separated)\n\
 \n\
 Examples:\n\
   Default:\n\
@@ -238,14 +794,16 @@ Examples:\n\
     ➞  Sony/ILCE-7M3/2024-08-15/IMG_1234.arw\n\
   Flat with timestamp:\n\
     {year}{month}{day}_{hour}{minute}{second}_{filename}.{extension}\n\
-    ➞  20240815_143000_IMG_1234.arw"),
+    ➞  20240815_143000_IMG_1234.arw")
+                .global(true),
         )
         .arg(
             Arg::new("config")
                 .short('c')
                 .long("config")
                 .value_name("PATH")
-                .help(config_help),
+                .help(config_help)
+                .global(true),
         )
         .arg(
             Arg::new("SOURCE")
@@ -258,11 +816,143 @@ Examples:\n\
                 .default_value(".")
                 .help("Where to move the images"),
         )
-        .get_matches();
+        .subcommand_negates_reqs(true)
+        .args_conflicts_with_subcommands(true)
+        .subcommand(
+            Command::new("watch")
+                .about("Watch SOURCE and move new images as they arrive")
+                .arg(
+                    Arg::new("settle")
+                        .long("settle")
+                        .value_name("SECONDS")
+                        .value_parser(|s: &str| {
+                            s.parse::<f64>()
+                                .ok()
+                                .filter(|t| t.is_finite() && *t >= 0.0)
+                                .ok_or("expected a non-negative number of seconds")
+                        })
+                        .help(format!("How long a new file and its sidecars must be left alone before moving them [default: {}]", watch::DEFAULT_SETTLE)),
+                )
+                .arg(
+                    Arg::new("SOURCE")
+                        .required(true)
+                        .help("Folder to watch"),
+                )
+                .arg(
+                    Arg::new("DESTINATION")
+                        .required(false)
+                        .default_value(".")
+                        .help("Where to move the images"),
+                ),
+        )
+        .subcommand(
+            Command::new("dupes")
+                .about("Find files with the same content in a library")
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .help("Print the duplicates as JSON")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("keep")
+                        .long("keep")
+                        .value_name("RULE")
+                        .value_parser(PossibleValuesParser::new(dupes::Keep::NAMES))
+                        .help("Which copy to keep: the oldest file or the one with the shortest path [default: oldest]"),
+                )
+                .arg(
+                    Arg::new("trash")
+                        .long("trash")
+                        .help("Move all other copies to the system's trash")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("LIBRARY")
+                        .required(true)
+                        .help("Folder to search for duplicates"),
+                ),
+        )
+        .subcommand(
+            Command::new("import")
+                .about("Import new images and movies from a camera card")
+                .arg(
+                    Arg::new("copy")
+                        .long("copy")
+                        .conflicts_with_all(["remove-source", "trash-source"])
+                        .help("Copy files instead of moving them")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("SOURCE")
+                        .required(true)
+                        .help("Where the card is mounted"),
+                )
+                .arg(
+                    Arg::new("DESTINATION")
+                        .required(false)
+                        .default_value(".")
+                        .help("Where to move the images"),
+                ),
+        )
+        .subcommand(
+            Command::new("reorganize")
+                .about("Move the files of a library from one template to another")
+                .arg(
+                    Arg::new("from")
+                        .long("from")
+                        .value_name("TEMPLATE")
+                        .required(true)
+                        .help("Template the library was organized with"),
+                )
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .value_name("TEMPLATE")
+                        .required(true)
+                        .help("Template to organize the library with"),
+                )
+                .arg(
+                    Arg::new("LIBRARY")
+                        .required(true)
+                        .help("Folder to reorganize"),
+                ),
+        )
+        .subcommand(
+            Command::new("verify")
+                .about("Check that the files of a library are where the template puts them")
+                .arg(
+                    Arg::new("LIBRARY")
+                        .required(true)
+                        .help("Folder to check"),
+                ),
+        )
+        .subcommand(
+            Command::new("stats")
+                .about("Count the files of a library by date, camera, lens, kind and missing metadata")
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .help("Print the statistics as JSON")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("PATH")
+                        .required(true)
+                        .help("Folder to scan"),
+                ),
+        )
+        .get_matches_from(args);
+
+    // Options are global, so the matches of a subcommand have them all.
+    let (subcommand, args) = match args.remove_subcommand() {
+        Some((name, sub_args)) => (Some(name), sub_args),
+        None => (None, args),
+    };
 
     // Load config file.
     let config_path = args.get_one::<String>("config").map(PathBuf::from);
-    let app_config = AppConfig::load(config_path.as_ref())?;
+    let mut app_config = AppConfig::load(config_path.as_ref())?;
 
     // Merge CLI args with config (CLI wins).
     let verbose = args.get_flag("verbose")
@@ -278,6 +968,33 @@ Examples:\n\
         || app_config.dereference.unwrap_or(false);
     let checksum =
         args.get_flag("checksum") || app_config.checksum.unwrap_or(false);
+    if args.get_flag("rename-conflicts") {
+        app_config.rename_conflicts = Some(true);
+    }
+    if args.get_flag("set-mtime") {
+        app_config.set_mtime = Some(true);
+    }
+    if let Some(threads) = args.get_one::<usize>("device-threads") {
+        app_config.device_threads = Some(*threads);
+    }
+    if let Some(threshold) = args.get_one::<f64>("burst-threshold") {
+        app_config.burst_threshold = Some(*threshold);
+    }
+    if let Some(action) = args.get_one::<String>("jpeg-with-raw") {
+        app_config.jpeg_with_raw = Some(action.parse()?);
+    }
+    if let Some(sources) = args.get_many::<String>("date-source") {
+        app_config.date_sources =
+            Some(sources.map(|s| s.parse()).collect::<Result<_>>()?);
+    }
+    let fail_on: Vec<ErrorClass> = match args.get_many::<String>("fail-on") {
+        Some(classes) => classes
+            .filter(|class| *class != "none")
+            .map(|class| class.parse())
+            .collect::<Result<_>>()?,
+        None if halt => ErrorClass::DEFAULT.to_vec(),
+        None => Vec::new(),
+    };
 
     let multi = MultiProgress::new();
     let logger = TermLogger::new(
@@ -290,7 +1007,58 @@ Examples:\n\
         TerminalMode::Mixed,
         ColorChoice::Auto,
     );
-    LogWrapper::new(multi.clone(), logger).try_init().unwrap();
+    // A logger is set already if this is not the first run in the process.
+    let _ = LogWrapper::new(multi.clone(), logger).try_init();
+
+    if subcommand.as_deref() == Some("dupes") {
+        let library = PathBuf::from(args.get_one::<String>("LIBRARY").unwrap());
+        let keep = args
+            .get_one::<String>("keep")
+            .map(|keep| keep.parse())
+            .transpose()?
+            .unwrap_or_default();
+
+        // Nothing may move in the library while its duplicates are trashed.
+        let _lock = if args.get_flag("trash") {
+            lock(&library, &args)?
+        } else {
+            None
+        };
+        let files = find_files(&library, true, dereference);
+        let duplicates = dupes::find(
+            &files,
+            keep,
+            &Throttle::new(app_config.device_threads),
+        );
+        if args.get_flag("json") {
+            println!("{}", serde_json::to_string_pretty(&duplicates)?);
+        } else {
+            dupes::print(&duplicates, &library);
+        }
+        if args.get_flag("trash") {
+            dupes::trash_redundant(
+                &duplicates,
+                &app_config.sidecar_patterns()?,
+                args.get_flag("dry-run"),
+            )?;
+        }
+        return Ok(());
+    }
+
+    if subcommand.as_deref() == Some("stats") {
+        let path = PathBuf::from(args.get_one::<String>("PATH").unwrap());
+        let stats = stats::collect(
+            &find_files(&path, true, dereference),
+            &app_config,
+            &Throttle::new(app_config.device_threads),
+        )?;
+        if args.get_flag("json") {
+            println!("{}", serde_json::to_string_pretty(&stats)?);
+        } else {
+            stats::print(&stats);
+        }
+        return Ok(());
+    }
 
     // Parse day-wrap time.
     let day_wrap_str = args
@@ -314,11 +1082,278 @@ Examples:\n\
     let template = Template::parse(format_str)?;
     template.validate()?;
 
-    let source: &String = args.get_one("SOURCE").unwrap();
+    let unsorted = args
+        .get_one::<String>("unsorted")
+        .or(app_config.unsorted.as_ref())
+        .map(|unsorted| {
+            let unsorted = Template::parse(unsorted)?;
+            unsorted.validate()?;
+            Ok::<_, anyhow::Error>(unsorted)
+        })
+        .transpose()?;
+
+    if subcommand.as_deref() == Some("reorganize") {
+        let library = PathBuf::from(args.get_one::<String>("LIBRARY").unwrap());
+        let from = Template::parse(args.get_one::<String>("from").unwrap())?;
+        let to = Template::parse(args.get_one::<String>("to").unwrap())?;
+        to.validate()?;
+        let _lock = lock(&library, &args)?;
+
+        let groups = read_groups(
+            &find_files(&library, true, dereference),
+            &app_config,
+            &Throttle::new(app_config.device_threads),
+        )?;
+        let moves = reorganize::plan(
+            &library,
+            groups,
+            &from,
+            &to,
+            &time_offset,
+            make_lowercase,
+            &app_config,
+        );
+        return reorganize::execute(
+            &library,
+            moves.into_iter().flatten().collect(),
+            args.get_flag("dry-run"),
+        );
+    }
+
+    if subcommand.as_deref() == Some("verify") {
+        let library = PathBuf::from(args.get_one::<String>("LIBRARY").unwrap());
+        let groups = read_groups(
+            &find_files(&library, true, dereference),
+            &app_config,
+            &Throttle::new(app_config.device_threads),
+        )?;
+        let problems = verify::verify(
+            &library,
+            &groups,
+            &template,
+            &time_offset,
+            make_lowercase,
+            &app_config,
+        )?;
+        for problem in &problems {
+            println!("{}", problem);
+        }
+        return if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "{} problem(s) found in {}.",
+                problems.len(),
+                library.display()
+            ))
+        };
+    }
+
+    let source = PathBuf::from(args.get_one::<String>("SOURCE").unwrap());
     let dest_dir =
         PathBuf::from(args.get_one::<String>("DESTINATION").unwrap());
 
-    let files = WalkDir::new(source)
+    // Cards have a known layout, see `import`.
+    let (files, card) = if subcommand.as_deref() == Some("import") {
+        let card = import::discover(&source)?;
+        app_config.add_sidecars(&card.sidecars);
+        (card.files, Some(import::volume_id(&source)))
+    } else {
+        (find_files(&source, recursive, dereference), None)
+    };
+
+    let _lock = lock(&dest_dir, &args)?;
+    let index = if args.get_flag("index") || app_config.index.unwrap_or(false) {
+        Some(Index::open(
+            &dest_dir,
+            &Throttle::new(app_config.device_threads),
+        )?)
+    } else {
+        None
+    };
+
+    let report = args
+        .get_one::<String>("report")
+        .map(|path| {
+            let path = PathBuf::from(path);
+            report::Format::from_path(&path).map(|format| (path, format))
+        })
+        .transpose()?;
+
+    let quarantine = args.get_one::<String>("quarantine").map(|dir| {
+        Quarantine::new(
+            Path::new(dir),
+            &source,
+            copying(&args),
+            args.get_flag("dry-run"),
+        )
+    });
+
+    // Watch mode has nothing to resume: sources are gone once moved.
+    let state = if subcommand.as_deref() != Some("watch")
+        && !args.get_flag("dry-run")
+    {
+        Some(RunState::open(&dest_dir, args.get_flag("resume"))?)
+    } else {
+        None
+    };
+    resume::handle_interrupt();
+
+    let args = Arc::new(args);
+    let multi = Arc::new(multi);
+
+    let process = |files: &[PathBuf]| {
+        let files: Vec<_> = match &state {
+            Some(state) => files
+                .iter()
+                .filter(|file| !state.is_done(file))
+                .cloned()
+                .collect(),
+            None => files.to_vec(),
+        };
+        let summary = process(
+            &files,
+            &source,
+            &dest_dir,
+            &time_offset,
+            &template,
+            unsorted.as_ref(),
+            make_lowercase,
+            checksum,
+            &app_config,
+            index.as_ref(),
+            quarantine.as_ref(),
+            state.as_ref(),
+            halt.then_some(fail_on.as_slice()),
+            args.clone(),
+            multi.clone(),
+        )?;
+        if let Some(index) = &index
+            && !args.get_flag("dry-run")
+        {
+            index.store()?;
+        }
+        Ok::<_, anyhow::Error>(summary)
+    };
+    let records = RefCell::new(Vec::new());
+    let failures = RefCell::new(Failures::default());
+    let check = |summary: Summary| {
+        report::print_totals(&summary.records);
+        let mut failures = failures.borrow_mut();
+        for e in &summary.errors {
+            failures.add(error::class(e));
+        }
+        for record in &summary.records {
+            if record.action == Action::SkippedConflict {
+                failures.add(ErrorClass::Conflict);
+            }
+        }
+        records.borrow_mut().extend(summary.records);
+
+        // Otherwise the run only fails at the end, e.g. in watch mode.
+        if halt {
+            failures.check(&fail_on)?;
+        }
+        Ok(())
+    };
+
+    let result = match subcommand.as_deref() {
+        Some("watch") => {
+            let settle = args
+                .get_one::<f64>("settle")
+                .copied()
+                .unwrap_or(watch::DEFAULT_SETTLE);
+            watch::watch(
+                &source,
+                recursive,
+                Duration::from_secs_f64(settle),
+                files,
+                |files| check(process(&files)?),
+            )
+        }
+        Some("import") => {
+            let volume = card.unwrap();
+            let mut history = import::History::load()?;
+            let new_files: HashMap<_, _> = history
+                .new_files(
+                    &volume,
+                    &source,
+                    &files,
+                    &Throttle::new(app_config.device_threads),
+                )?
+                .into_iter()
+                .collect();
+            info!(
+                "{} of {} files are new on {}.",
+                new_files.len(),
+                files.len(),
+                source.display()
+            );
+
+            let mut paths: Vec<_> = new_files.keys().cloned().collect();
+            paths.sort();
+            let summary = process(&paths)?;
+
+            if !args.get_flag("dry-run") {
+                for record in &summary.records {
+                    if !matches!(
+                        record.action,
+                        Action::SkippedConflict | Action::Failed
+                    ) {
+                        history.insert(&volume, new_files[&record.source]);
+                    }
+                }
+                history.store()?;
+            }
+            check(summary)
+        }
+        _ => check(process(&files)?),
+    };
+
+    if let Some((path, format)) = &report {
+        report::write(path, *format, &records.into_inner())?;
+    }
+
+    let result =
+        result.and_then(|()| Ok(failures.into_inner().check(&fail_on)?));
+    if let Some(state) = state {
+        if resume::interrupted() {
+            return Err(anyhow!(
+                "Interrupted; use --resume to continue where this run stopped."
+            ));
+        }
+        // Keep the state of a failed run, so its files can be retried.
+        match result {
+            Ok(()) => state.finish()?,
+            Err(e) => {
+                return Err(e.context(
+                    "The run did not complete; use --resume to retry the \
+                     files it did not move.",
+                ));
+            }
+        }
+    }
+
+    result
+}
+
+/// Locks `dest_dir` against other runs, unless nothing will be written or
+/// `--no-lock` is given.
+fn lock(dest_dir: &Path, args: &ArgMatches) -> Result<Option<RunLock>> {
+    if args.get_flag("dry-run") || args.get_flag("no-lock") {
+        Ok(None)
+    } else {
+        RunLock::acquire(dest_dir, args.get_flag("wait-lock")).map(Some)
+    }
+}
+
+/// Returns the images in `source`, sorted by name.
+fn find_files(
+    source: &Path,
+    recursive: bool,
+    dereference: bool,
+) -> Vec<PathBuf> {
+    WalkDir::new(source)
         .contents_first(true)
         .max_depth(if recursive { usize::MAX } else { 1 })
         .follow_links(dereference)
@@ -329,40 +1364,148 @@ Examples:\n\
             e.ok()
                 .filter(|e| e.file_type().is_file() && has_image_extension(e))
         })
-        .collect::<Vec<_>>();
+        .map(|e| e.into_path())
+        .collect()
+}
 
-    let args = Arc::new(args);
-    let template = Arc::new(template);
-    let multi = Arc::new(multi);
+/// What happened to the files of a run.
+#[derive(Debug, Default)]
+struct Summary {
+    /// What was done with each file, not counting sidecars.
+    records: Vec<Record>,
+    errors: Vec<anyhow::Error>,
+}
 
-    let errors: Vec<_> = files
-        .par_iter()
-        .filter_map(|file| {
-            let result = move_image(
-                file.path(),
-                &dest_dir,
-                &time_offset,
-                &template,
-                make_lowercase,
-                checksum,
-                args.clone(),
-                multi.clone(),
-            );
-            match result {
-                Ok(()) => None,
-                Err(e) => {
-                    warn!("{}", e);
-                    Some(e)
+/// Moves `files`.
+///
+/// All metadata is read first so files that belong together can be moved
+/// together. With `halt`, no more groups are started once one fails with an
+/// error in one of its classes.
+#[allow(clippy::too_many_arguments)]
+fn process(
+    files: &[PathBuf],
+    source_dir: &Path,
+    dest_dir: &Path,
+    time_offset: &NaiveTime,
+    template: &Template,
+    unsorted: Option<&Template>,
+    make_lowercase: bool,
+    checksum: bool,
+    config: &AppConfig,
+    index: Option<&Index>,
+    quarantine: Option<&Quarantine>,
+    state: Option<&RunState>,
+    halt: Option<&[ErrorClass]>,
+    args: Arc<ArgMatches>,
+    multi: Arc<MultiProgress>,
+) -> Result<Summary> {
+    let throttle = Throttle::new(config.device_threads);
+    let groups = read_groups(files, config, &throttle)?;
+    device::check_free_space(
+        &groups,
+        dest_dir,
+        copying(&args),
+        args.get_flag("ignore-free-space") || args.get_flag("dry-run"),
+    )?;
+
+    let halted = AtomicBool::new(false);
+    let results: Vec<_> = groups
+        .into_par_iter()
+        // Once interrupted or halted, only the groups being moved are
+        // finished.
+        .filter(|_| !resume::interrupted() && !halted.load(Ordering::Relaxed))
+        .map(|group| {
+            let paths: Vec<_> =
+                group.items.iter().map(|item| item.path.clone()).collect();
+            let first = group.items[0].path.clone();
+            // What the files are reported as if moving them fails.
+            let failed: Vec<_> =
+                group.items.iter().map(Record::failed).collect();
+            let mut records = Vec::new();
+            let result = throttle.run(&[&first, dest_dir], || {
+                move_group(
+                    group,
+                    source_dir,
+                    dest_dir,
+                    time_offset,
+                    template,
+                    unsorted,
+                    make_lowercase,
+                    checksum,
+                    config,
+                    index,
+                    quarantine,
+                    args.clone(),
+                    multi.clone(),
+                    &mut records,
+                )
+            });
+            let result = result.and_then(|()| {
+                if let Some(state) = state {
+                    state.record(paths.iter().map(PathBuf::as_path))?;
+                }
+                Ok(())
+            });
+
+            if let Some(fail_on) = halt {
+                let mut failures = Failures::default();
+                if let Err(e) = &result {
+                    failures.add(error::class(e));
+                }
+                for record in &records {
+                    if record.action == Action::SkippedConflict {
+                        failures.add(ErrorClass::Conflict);
+                    }
+                }
+                if failures.check(fail_on).is_err()
+                    && !halted.swap(true, Ordering::Relaxed)
+                {
+                    warn!("Halting on the first error.");
                 }
             }
+            (failed, records, result)
         })
         .collect();
 
-    if halt && !errors.is_empty() {
-        Err(anyhow!("{} error(s) encountered.", errors.len()))
-    } else {
-        Ok(())
+    let mut summary = Summary::default();
+    for (failed, records, result) in results {
+        if let Err(e) = result {
+            warn!("{}", e);
+            // Files dealt with before the error keep their own record.
+            summary.records.extend(
+                failed
+                    .into_iter()
+                    .filter(|record| {
+                        !records.iter().any(|done| done.source == record.source)
+                    })
+                    .map(|record| Record {
+                        error: Some(e.to_string()),
+                        ..record
+                    }),
+            );
+            summary.errors.push(e);
+        }
+        summary.records.extend(records);
     }
+
+    Ok(summary)
+}
+
+/// Reads `files` and groups them, see [`group`] and [`burst`].
+fn read_groups(
+    files: &[PathBuf],
+    config: &AppConfig,
+    throttle: &Throttle,
+) -> Result<Vec<Group>> {
+    let items = files
+        .par_iter()
+        .map(|file| throttle.run(&[file], || Item::read(file, config)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut groups = group::group(items);
+    burst::mark_bursts(&mut groups, config.burst_threshold());
+
+    Ok(groups)
 }
 
 fn is_not_hidden(entry: &DirEntry) -> bool {
@@ -373,70 +1516,357 @@ fn is_not_hidden(entry: &DirEntry) -> bool {
         .unwrap_or(false)
 }
 
+/// Moves a group of files, see [`group`].
+///
+/// Each file goes where the template puts it, which for a `{pair_role}`
+/// template differs between a RAW and its JPEG. Companions share the
+/// outcome and conflict suffix of the first file, so pairs keep matching
+/// names; they stay where they are if the first file does.
+///
+/// With an `index`, files whose content is anywhere in the library are
+/// treated as duplicates of that file.
+///
+/// A group without metadata goes where `unsorted` puts it, if given.
+///
+/// Adds what was done with each file, not counting sidecars, to `records`.
+/// On an error, files already dealt with are recorded, with the error if it
+/// was one of theirs, e.g. moving a sidecar; the rest are not.
 #[allow(clippy::too_many_arguments)]
-pub(crate) fn move_image(
-    source_file: &Path,
+pub(crate) fn move_group(
+    group: Group,
+    source_dir: &Path,
     dest_dir: &Path,
     time_offset: &NaiveTime,
     template: &Template,
+    unsorted: Option<&Template>,
     make_lowercase: bool,
     checksum: bool,
+    config: &AppConfig,
+    index: Option<&Index>,
+    quarantine: Option<&Quarantine>,
     args: Arc<ArgMatches>,
     multi: Arc<MultiProgress>,
+    records: &mut Vec<Record>,
 ) -> Result<()> {
-    let source_file_handle =
-        std::fs::File::open(source_file).with_context(|| {
-            format!("Unable to open '{}'.", source_file.display())
-        })?;
+    let to_action = |outcome| Action::new(outcome, copying(&args));
+    let (template, metadata) = match (group.metadata(), unsorted, quarantine) {
+        (Some(metadata), ..) => (template, Some(metadata.clone())),
+        (None, Some(unsorted), _) => {
+            for item in &group.items {
+                if let Err(e) = &item.metadata {
+                    warn!("{} Moving it to the unsorted files.", e);
+                }
+            }
+            (unsorted, None)
+        }
+        (None, None, Some(quarantine)) => {
+            for item in &group.items {
+                if let Err(e) = &item.metadata {
+                    warn!("{}", e);
+                }
+                let bytes = item.path.metadata().map_or(0, |m| m.len());
+                let target =
+                    quarantine.put(item, Reason::Unreadable, None, &multi)?;
+                records.push(Record::new(
+                    item,
+                    bytes,
+                    Action::Quarantined,
+                    Some(&target),
+                ));
+            }
+            return Ok(());
+        }
+        (None, None, None) => {
+            return Err(group
+                .items
+                .into_iter()
+                .next()
+                .unwrap()
+                .metadata
+                .unwrap_err());
+        }
+    };
 
-    let exif_reader = exif::Reader::new();
-    let meta_data = exif_reader
-        .read_from_container(&mut std::io::BufReader::new(&source_file_handle))
-        .with_context(|| {
-            format!(
-                "Unable to read EXIF metadata of '{}'.",
-                source_file.display()
-            )
-        })?;
+    // Outcome and conflict suffix of the first file.
+    let mut primary: Option<(Outcome, Option<usize>)> = None;
 
-    let time_stamp = meta_data
-        .get_field(Tag::DateTimeOriginal, exif::In::PRIMARY)
-        .and_then(|f| match f.value {
-            Value::Ascii(ref vec) if !vec.is_empty() => {
-                DateTime::from_ascii(&vec[0]).ok()
+    for item in &group.items {
+        let bytes = item.path.metadata().map_or(0, |m| m.len());
+        let ctx = match &metadata {
+            Some(metadata) => template_context(
+                item,
+                source_dir,
+                metadata,
+                time_offset,
+                make_lowercase,
+                config,
+            )?,
+            None => file_context(item, source_dir, make_lowercase),
+        };
+
+        // Expand template to get relative path. Empty variables like an
+        // unpaired `{pair_role}` must not leave empty path components.
+        let dest_file: PathBuf =
+            dest_dir.join(template.expand(&ctx)).components().collect();
+
+        let (dest_file, outcome, indexed) = match primary {
+            None => {
+                let indexed = find_indexed(index, &item.path, &args)?;
+                let (dest_file, outcome, number) =
+                    if let Some((_, Some(existing))) = &indexed {
+                        let outcome = handle_duplicate(
+                            &item.path, existing, "content", &args,
+                        )?;
+                        (existing.clone(), outcome, 0)
+                    } else {
+                        create_parent(&dest_file, &args)?;
+
+                        let mut dest_file = dest_file;
+                        let mut outcome = move_file(
+                            &item.path,
+                            &dest_file,
+                            checksum,
+                            args.clone(),
+                            &multi,
+                        )?;
+
+                        let original_dest_file = dest_file.clone();
+                        let mut number = 0;
+                        if config.rename_conflicts.unwrap_or(false) {
+                            while outcome == Outcome::Conflict {
+                                number += 1;
+                                dest_file = with_conflict_suffix(
+                                    &original_dest_file,
+                                    number,
+                                );
+                                outcome = move_file(
+                                    &item.path,
+                                    &dest_file,
+                                    checksum,
+                                    args.clone(),
+                                    &multi,
+                                )?;
+                            }
+                        }
+                        (dest_file, outcome, number)
+                    };
+
+                primary = Some((outcome, (number > 0).then_some(number)));
+                (dest_file, outcome, indexed)
             }
-            _ => None,
-        })
-        .with_context(|| {
-            format!(
-                "Timestamp metadata missing in '{}'.",
-                source_file.display()
-            )
-        })?;
+            Some((Outcome::Conflict, _)) => {
+                info!(
+                    "Not moving {} as {} was not moved.",
+                    item.path.display(),
+                    group.items[0].path.display()
+                );
+                // Kept with the first file.
+                records.push(match quarantine {
+                    Some(quarantine) => {
+                        let target = quarantine.put(
+                            item,
+                            Reason::Conflict,
+                            None,
+                            &multi,
+                        )?;
+                        Record::new(
+                            item,
+                            bytes,
+                            Action::Quarantined,
+                            Some(&target),
+                        )
+                    }
+                    None => {
+                        Record::new(item, bytes, Action::SkippedConflict, None)
+                    }
+                });
+                continue;
+            }
+            Some((_, number)) => {
+                if item.pair_role == Some(PairRole::Jpeg) {
+                    let action = config.jpeg_with_raw.unwrap_or_default();
+                    if action != JpegWithRaw::Keep {
+                        if let Some(outcome) = discard(item, action, &args)? {
+                            records.push(Record::new(
+                                item,
+                                bytes,
+                                to_action(outcome),
+                                None,
+                            ));
+                        }
+                        continue;
+                    }
+                }
 
-    let date = NaiveDate::from_ymd_opt(
-        time_stamp.year as i32,
-        time_stamp.month as u32,
-        time_stamp.day as u32,
-    )
-    .with_context(|| {
-        format!(
-            "Invalid date {}-{}-{} in '{}'.",
-            time_stamp.year,
-            time_stamp.month,
-            time_stamp.day,
-            source_file.display()
-        )
-    })?;
+                let indexed = find_indexed(index, &item.path, &args)?;
+                if let Some((_, Some(existing))) = &indexed {
+                    let outcome = handle_duplicate(
+                        &item.path, existing, "content", &args,
+                    )?;
+                    (existing.clone(), outcome, indexed)
+                } else {
+                    let dest_file = match number {
+                        Some(number) => {
+                            with_conflict_suffix(&dest_file, number)
+                        }
+                        None => dest_file,
+                    };
+                    create_parent(&dest_file, &args)?;
+
+                    let outcome = move_file(
+                        &item.path,
+                        &dest_file,
+                        checksum,
+                        args.clone(),
+                        &multi,
+                    )?;
+                    if outcome == Outcome::Conflict {
+                        warn!(
+                            "{} exists; {} was separated from {}.",
+                            dest_file.display(),
+                            item.path.display(),
+                            group.items[0].path.display()
+                        );
+                    }
+                    (dest_file, outcome, indexed)
+                }
+            }
+        };
+
+        let (outcome, destination) = match (quarantine, outcome) {
+            (Some(quarantine), Outcome::Duplicate | Outcome::Conflict) => {
+                let reason = if outcome == Outcome::Duplicate {
+                    Reason::Duplicate
+                } else {
+                    Reason::Conflict
+                };
+                let target =
+                    quarantine.put(item, reason, Some(&dest_file), &multi)?;
+                (Outcome::Quarantined, target)
+            }
+            _ => (outcome, dest_file.clone()),
+        };
+
+        if let (Some(index), Some((key, _))) = (index, indexed) {
+            match outcome {
+                Outcome::Moved => index.moved(key, &item.path, &dest_file),
+                Outcome::Removed | Outcome::Trashed | Outcome::Quarantined => {
+                    index.removed(&item.path)
+                }
+                _ => (),
+            }
+        }
+        let mut record =
+            Record::new(item, bytes, to_action(outcome), Some(&destination));
+        if outcome == Outcome::Conflict {
+            record.error =
+                Some(error::conflict(&item.path, &dest_file).to_string());
+        }
+
+        // The file is where it goes; what fails from here on is recorded
+        // with it.
+        let mut result = Ok(());
+        if outcome == Outcome::Moved
+            && config.set_mtime.unwrap_or(false)
+            && !args.get_flag("dry-run")
+            && let Some(metadata) = &metadata
+        {
+            let time_stamp =
+                item.metadata.as_ref().unwrap_or(metadata).time_stamp;
+            result = set_modified(&dest_file, time_stamp);
+        }
+
+        // Move possible sidecar files, unless the file stayed where it was
+        // because of a conflict or was quarantined with them.
+        if result.is_ok()
+            && !matches!(outcome, Outcome::Conflict | Outcome::Quarantined)
+        {
+            result = item.sidecars.iter().try_for_each(|sidecar| {
+                move_file(
+                    &sidecar.path,
+                    &sidecar.destination(&dest_file, make_lowercase),
+                    checksum,
+                    args.clone(),
+                    &multi,
+                )
+                .map(|_| ())
+            });
+        }
+
+        if let Err(e) = &result {
+            record.error = Some(e.to_string());
+        }
+        records.push(record);
+        result?;
+    }
+
+    Ok(())
+}
+
+/// Builds the template context of `item` from the metadata of its group.
+fn template_context(
+    item: &Item,
+    source_dir: &Path,
+    metadata: &Metadata,
+    time_offset: &NaiveTime,
+    make_lowercase: bool,
+    config: &AppConfig,
+) -> Result<TemplateContext> {
+    let source_file = &item.path;
+    let time_stamp = exif_date_time_from(metadata.time_stamp);
 
     let date = if day_wrap(&time_stamp, time_offset) == 1 {
-        date.checked_add_days(Days::new(1)).with_context(|| {
-            format!("Date overflow for '{}'.", source_file.display())
-        })?
+        metadata
+            .time_stamp
+            .date()
+            .checked_add_days(Days::new(1))
+            .with_context(|| {
+                format!("Date overflow for '{}'.", source_file.display())
+            })?
     } else {
-        date
+        metadata.time_stamp.date()
     };
 
+    Ok(TemplateContext {
+        year: format!("{}", date.year()),
+        month: format!("{:02}", date.month()),
+        day: format!("{:02}", date.day()),
+        hour: format!("{:02}", time_stamp.hour),
+        minute: format!("{:02}", time_stamp.minute),
+        second: format!("{:02}", time_stamp.second),
+        camera_make: metadata.camera_make.clone(),
+        camera_model: metadata.camera_model.clone(),
+        lens: metadata.lens.clone(),
+        iso: metadata.iso.clone(),
+        focal_length: metadata.focal_length.clone(),
+        place: config
+            .place_name(metadata.gps)
+            .as_deref()
+            .and_then(path_safe),
+        rating: metadata.rating.as_deref().and_then(path_safe),
+        label: metadata.label.as_deref().and_then(path_safe),
+        keywords: Some(
+            metadata
+                .keywords
+                .iter()
+                .filter_map(|k| path_safe(k))
+                .collect::<Vec<_>>()
+                .join(","),
+        )
+        .filter(|keywords| !keywords.is_empty()),
+        ..file_context(item, source_dir, make_lowercase)
+    })
+}
+
+/// Builds the template context of `item` from its file alone, e.g. for
+/// files without metadata. Other variables are empty or `unknown`.
+fn file_context(
+    item: &Item,
+    source_dir: &Path,
+    make_lowercase: bool,
+) -> TemplateContext {
+    let source_file = &item.path;
+
     // Extract filename and extension.
     let file_stem = source_file
         .file_stem()
@@ -447,14 +1877,16 @@ pub(crate) fn move_image(
         .and_then(|s| s.to_str())
         .unwrap_or("");
 
-    // Build template context.
-    let ctx = TemplateContext {
-        year: format!("{}", date.year()),
-        month: format!("{:02}", date.month()),
-        day: format!("{:02}", date.day()),
-        hour: format!("{:02}", time_stamp.hour),
-        minute: format!("{:02}", time_stamp.minute),
-        second: format!("{:02}", time_stamp.second),
+    let source_relpath = source_file
+        .strip_prefix(source_dir)
+        .ok()
+        .filter(|path| !path.as_os_str().is_empty())
+        .unwrap_or_else(|| {
+            Path::new(source_file.file_name().unwrap_or_default())
+        })
+        .to_string_lossy();
+
+    TemplateContext {
         filename: if make_lowercase {
             file_stem.to_lowercase()
         } else {
@@ -465,19 +1897,60 @@ pub(crate) fn move_image(
         } else {
             extension.to_string()
         },
-        camera_make: exif_string(&meta_data, Tag::Make),
-        camera_model: exif_string(&meta_data, Tag::Model),
-        lens: exif_string(&meta_data, Tag::LensModel),
-        iso: exif_string(&meta_data, Tag::PhotographicSensitivity),
-        focal_length: exif_string(&meta_data, Tag::FocalLength)
-            .map(|s| s.trim_end_matches("-mm").to_string()),
+        pair_role: item.pair_role.map(|r| r.to_string()).unwrap_or_default(),
+        burst: item
+            .burst
+            .as_deref()
+            .map(|burst| {
+                if make_lowercase {
+                    burst.to_lowercase()
+                } else {
+                    burst.to_string()
+                }
+            })
+            .unwrap_or_default(),
+        live: if item.live
+            || item.metadata.as_ref().is_ok_and(|m| m.motion_photo)
+        {
+            "live".to_string()
+        } else {
+            String::new()
+        },
+        source_relpath: if make_lowercase {
+            source_relpath.to_lowercase()
+        } else {
+            source_relpath.into_owned()
+        },
+        ..Default::default()
+    }
+}
+
+/// Looks `path` up in `index`. With `--byte-compare`, a file with the same
+/// hash only counts if its content matches byte for byte; if duplicates are
+/// removed, trashed or quarantined, only if its hash still matches.
+fn find_indexed(
+    index: Option<&Index>,
+    path: &Path,
+    args: &ArgMatches,
+) -> Result<Option<(Key, Option<PathBuf>)>> {
+    let Some((key, existing)) = index.map(|i| i.find(path)).transpose()? else {
+        return Ok(None);
+    };
+    let existing = match existing {
+        Some(existing) if args.get_flag("byte-compare") => {
+            same_contents(path, &existing)?.then_some(existing)
+        }
+        Some(existing) if is_destructive(args) => {
+            (file_hash(&existing, key.0)? == key.1).then_some(existing)
+        }
+        existing => existing,
     };
 
-    // Expand template to get relative path.
-    let relative_path = template.expand(&ctx);
-    let mut dest_file = dest_dir.join(&relative_path);
+    Ok(Some((key, existing)))
+}
 
-    // Create parent directories.
+/// Creates the parent directories of `dest_file`, unless in a dry run.
+fn create_parent(dest_file: &Path, args: &ArgMatches) -> Result<()> {
     if let Some(parent) = dest_file.parent()
         && !args.get_flag("dry-run")
         && !parent.exists()
@@ -492,41 +1965,69 @@ pub(crate) fn move_image(
         })?;
     }
 
-    move_file(source_file, &dest_file, checksum, args.clone(), &multi)?;
-
-    // Move possible sidecar files.
-    let source_xmp_file = source_file.to_path_buf();
-
-    let mut source_xmp_file_lower = source_xmp_file.clone();
-    source_xmp_file_lower.as_mut_os_string().push(".xmp");
-
-    let mut source_xmp_file_upper = source_xmp_file.clone();
-    source_xmp_file_upper.as_mut_os_string().push(".XMP");
+    Ok(())
+}
 
-    if source_xmp_file_lower.exists() {
-        dest_file.as_mut_os_string().push(".xmp");
+/// Removes or trashes the JPEG of a RAW+JPEG pair and its sidecars.
+///
+/// With `--copy` they are only left behind.
+fn discard(
+    item: &Item,
+    action: JpegWithRaw,
+    args: &ArgMatches,
+) -> Result<Option<Outcome>> {
+    if copying(args) {
+        info!("Not copying {}.", item.path.display());
+        return Ok(None);
+    }
 
-        move_file(&source_xmp_file_lower, &dest_file, checksum, args, &multi)?;
-    } else if source_xmp_file_upper.exists() {
-        if make_lowercase {
-            dest_file.as_mut_os_string().push(".xmp");
+    for path in std::iter::once(&item.path)
+        .chain(item.sidecars.iter().map(|sidecar| &sidecar.path))
+    {
+        if args.get_flag("dry-run") {
+            info!("Would {} {}.", action, path.display());
+        } else if action == JpegWithRaw::Trash {
+            trash::delete(path)
+                .map_err(std::io::Error::other)
+                .io_context(|| {
+                    format!("Failed to trash {}.", path.display())
+                })?;
+            info!("Trashed {}.", path.display());
         } else {
-            dest_file.as_mut_os_string().push(".XMP");
-        };
-
-        move_file(&source_xmp_file_upper, &dest_file, checksum, args, &multi)?;
+            std::fs::remove_file(path).io_context(|| {
+                format!("Failed to remove {}.", path.display())
+            })?;
+            info!("Removed {}.", path.display());
+        }
     }
 
-    Ok(())
+    Ok(Some(if action == JpegWithRaw::Trash {
+        Outcome::Trashed
+    } else {
+        Outcome::Removed
+    }))
+}
+
+/// Convert a `chrono` timestamp into the EXIF representation.
+fn exif_date_time_from(time_stamp: NaiveDateTime) -> DateTime {
+    DateTime {
+        year: time_stamp.year() as u16,
+        month: time_stamp.month() as u8,
+        day: time_stamp.day() as u8,
+        hour: time_stamp.hour() as u8,
+        minute: time_stamp.minute() as u8,
+        second: time_stamp.second() as u8,
+        nanosecond: None,
+        offset: None,
+    }
 }
 
-/// Extract a string value from EXIF metadata.
-/// Spaces are replaced with hyphens for filesystem-friendly paths.
-fn exif_string(meta_data: &exif::Exif, tag: Tag) -> Option<String> {
-    meta_data
-        .get_field(tag, exif::In::PRIMARY)
-        .map(|f| f.display_value().to_string().trim().replace(' ', "-"))
-        .filter(|s| !s.is_empty())
+/// Make a metadata value safe for use as (part of) a path component.
+/// Spaces and path separators are replaced with hyphens. Values that are
+/// empty or only dots, like `..`, would name another folder and are dropped.
+fn path_safe(value: &str) -> Option<String> {
+    let value = value.trim().replace([' ', '/', '\\'], "-");
+    (!value.trim_matches('.').is_empty()).then_some(value)
 }
 
 pub(crate) fn day_wrap(time_stamp: &DateTime, time_offset: &NaiveTime) -> u8 {
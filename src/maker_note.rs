@@ -0,0 +1,59 @@
+//! Vendor MakerNote parsing.
+//!
+//! `kamadak-exif` hands out MakerNotes as opaque bytes. Only what `exifmv`
+//! uses is decoded.
+
+use exif::{Tag, Value};
+
+/// Header of an Apple MakerNote.
+const APPLE_HEADER: &[u8] = b"Apple iOS\0";
+
+/// Returns an ASCII value from an Apple MakerNote.
+pub fn apple_string(meta_data: &exif::Exif, tag: u16) -> Option<String> {
+    let maker_note = match meta_data
+        .get_field(Tag::MakerNote, exif::In::PRIMARY)?
+        .value
+    {
+        Value::Undefined(ref data, _) => data,
+        _ => return None,
+    };
+
+    // Header, version and byte order ("MM"), then an IFD whose offsets are
+    // relative to the start of the MakerNote.
+    if !maker_note.starts_with(APPLE_HEADER) || maker_note.get(12..14)? != b"MM"
+    {
+        return None;
+    }
+    let u16_at = |offset: usize| {
+        Some(u16::from_be_bytes(
+            maker_note.get(offset..offset + 2)?.try_into().ok()?,
+        ))
+    };
+    let u32_at = |offset: usize| {
+        Some(u32::from_be_bytes(
+            maker_note.get(offset..offset + 4)?.try_into().ok()?,
+        ) as usize)
+    };
+
+    let entries = u16_at(14)? as usize;
+    (0..entries).find_map(|entry| {
+        let offset = 16 + entry * 12;
+        // Type 2 is ASCII.
+        if u16_at(offset)? != tag || u16_at(offset + 2)? != 2 {
+            return None;
+        }
+
+        let count = u32_at(offset + 4)?;
+        let value = if count <= 4 {
+            maker_note.get(offset + 8..offset + 8 + count)?
+        } else {
+            let start = u32_at(offset + 8)?;
+            maker_note.get(start..start + count)?
+        };
+
+        let value = String::from_utf8_lossy(value)
+            .trim_end_matches('\0')
+            .to_string();
+        (!value.is_empty()).then_some(value)
+    })
+}
@@ -0,0 +1,247 @@
+//! Metadata extraction from files and their sidecars.
+
+use crate::{
+    burst,
+    config::{Config as AppConfig, DateSource},
+    error::{Error, IoContext},
+    live, quicktime,
+    sidecar::Sidecar,
+    takeout,
+    util::{FileKind, file_kind, is_jpeg},
+    xmp,
+};
+use anyhow::Result;
+use chrono::{NaiveDate, NaiveDateTime};
+use exif::{DateTime, Tag, Value};
+use log::{info, warn};
+use std::path::Path;
+
+/// Everything we know about a file, from the file itself and its sidecars.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Metadata {
+    /// Capture time, as local time.
+    pub time_stamp: NaiveDateTime,
+    /// Where `time_stamp` was taken from.
+    pub date_source: Option<DateSource>,
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub lens: Option<String>,
+    pub iso: Option<String>,
+    pub focal_length: Option<String>,
+    /// GPS coordinates as `(latitude, longitude)`.
+    pub gps: Option<(f64, f64)>,
+    pub rating: Option<String>,
+    pub label: Option<String>,
+    pub keywords: Vec<String>,
+    /// Links the still and the movie of an Apple Live Photo.
+    pub content_identifier: Option<String>,
+    /// Links the frames of a burst.
+    pub burst_identifier: Option<String>,
+    /// Google/Samsung motion photo with an embedded video.
+    pub motion_photo: bool,
+}
+
+/// Reads the metadata of `source_file` and its `sidecars`.
+///
+/// Fails if no configured date source has a capture time.
+pub(crate) fn read(
+    source_file: &Path,
+    sidecars: &[Sidecar],
+    config: &AppConfig,
+) -> Result<Metadata> {
+    let source_file_handle =
+        std::fs::File::open(source_file).io_context(|| {
+            format!("Unable to open '{}'.", source_file.display())
+        })?;
+
+    let exif_reader = exif::Reader::new();
+    let meta_data = exif_reader
+        .read_from_container(&mut std::io::BufReader::new(&source_file_handle));
+
+    // Movies carry their dates in QuickTime metadata instead of EXIF.
+    let quicktime = (file_kind(source_file) == Some(FileKind::Movie))
+        .then(|| {
+            quicktime::read(source_file)
+                .inspect_err(|e| info!("{}", e))
+                .ok()
+        })
+        .flatten();
+
+    let xmp = sidecars
+        .iter()
+        .find(|s| s.has_extension("xmp"))
+        .and_then(|s| {
+            xmp::read_sidecar(&s.path)
+                .inspect_err(|e| warn!("{}", e))
+                .ok()
+        });
+
+    let takeout =
+        sidecars
+            .iter()
+            .find(|s| s.has_extension("json"))
+            .and_then(|s| {
+                takeout::read_sidecar(&s.path)
+                    .inspect_err(|e| warn!("{}", e))
+                    .ok()
+            });
+
+    let Some((time_stamp, date_source)) = capture_time(
+        source_file,
+        meta_data.as_ref().ok(),
+        quicktime.as_ref(),
+        xmp.as_ref(),
+        takeout.as_ref(),
+        config,
+    ) else {
+        // Files without EXIF data, like movies, are not unreadable.
+        return Err(match meta_data {
+            // Truncated files are unreadable.
+            Err(exif::Error::Io(e))
+                if e.kind() != std::io::ErrorKind::UnexpectedEof =>
+            {
+                Error::io(
+                    e,
+                    format!("Unable to read '{}'.", source_file.display()),
+                )
+            }
+            Err(
+                e @ (exif::Error::InvalidFormat(_)
+                | exif::Error::NotSupported(_)
+                | exif::Error::Io(_)),
+            ) if file_kind(source_file) != Some(FileKind::Movie) => {
+                Error::Unreadable {
+                    path: source_file.to_path_buf(),
+                    source: e,
+                }
+            }
+            _ => Error::MetadataMissing(source_file.to_path_buf()),
+        }
+        .into());
+    };
+    if date_source != DateSource::Exif {
+        info!(
+            "Using {} date for '{}'.",
+            date_source,
+            source_file.display()
+        );
+    }
+    let meta_data = meta_data.ok();
+    let xmp = xmp.unwrap_or_default();
+
+    Ok(Metadata {
+        time_stamp,
+        date_source: Some(date_source),
+        camera_make: exif_string(meta_data.as_ref(), Tag::Make),
+        camera_model: exif_string(meta_data.as_ref(), Tag::Model),
+        lens: exif_string(meta_data.as_ref(), Tag::LensModel),
+        iso: exif_string(meta_data.as_ref(), Tag::PhotographicSensitivity),
+        focal_length: exif_string(meta_data.as_ref(), Tag::FocalLength)
+            .map(|s| s.trim_end_matches("-mm").to_string()),
+        gps: meta_data
+            .as_ref()
+            .and_then(exif_gps)
+            .or_else(|| takeout.and_then(|t| t.gps)),
+        rating: xmp.rating,
+        label: xmp.label,
+        keywords: xmp.keywords,
+        content_identifier: meta_data
+            .as_ref()
+            .and_then(live::content_identifier)
+            .or_else(|| quicktime.and_then(|q| q.content_identifier)),
+        burst_identifier: meta_data.as_ref().and_then(burst::burst_identifier),
+        motion_photo: is_jpeg(source_file)
+            && live::is_motion_photo(source_file),
+    })
+}
+
+/// Determine the capture time by trying the configured date sources in order.
+fn capture_time(
+    source_file: &Path,
+    meta_data: Option<&exif::Exif>,
+    quicktime: Option<&quicktime::QuickTime>,
+    xmp: Option<&xmp::Xmp>,
+    takeout: Option<&takeout::Takeout>,
+    config: &AppConfig,
+) -> Option<(NaiveDateTime, DateSource)> {
+    config.date_sources().iter().find_map(|date_source| {
+        match date_source {
+            DateSource::Xmp => xmp.and_then(|x| x.time_stamp),
+            DateSource::Exif => meta_data
+                .and_then(exif_date_time)
+                .or_else(|| quicktime.and_then(|q| q.time_stamp)),
+            DateSource::Takeout => takeout.and_then(|t| t.time_stamp),
+            DateSource::Filename => source_file
+                .file_name()
+                .and_then(|s| s.to_str())
+                .and_then(|s| config.filename_dates().parse(s)),
+        }
+        .map(|time_stamp| (time_stamp, *date_source))
+    })
+}
+
+/// Extract a valid `DateTimeOriginal` from EXIF metadata.
+///
+/// Placeholder dates like `0000:00:00 00:00:00` written by cameras with an
+/// unset clock are rejected so the next date source gets a chance.
+fn exif_date_time(meta_data: &exif::Exif) -> Option<NaiveDateTime> {
+    let mut t = meta_data
+        .get_field(Tag::DateTimeOriginal, exif::In::PRIMARY)
+        .and_then(|f| match f.value {
+            Value::Ascii(ref vec) if !vec.is_empty() => {
+                DateTime::from_ascii(&vec[0]).ok()
+            }
+            _ => None,
+        })?;
+
+    // Fractions of a second tell the frames of a burst apart.
+    if let Some(Value::Ascii(vec)) = meta_data
+        .get_field(Tag::SubSecTimeOriginal, exif::In::PRIMARY)
+        .map(|f| &f.value)
+        && let Some(subsec) = vec.first()
+    {
+        t.parse_subsec(subsec).ok();
+    }
+
+    NaiveDate::from_ymd_opt(t.year as i32, t.month as u32, t.day as u32)?
+        .and_hms_nano_opt(
+            t.hour as u32,
+            t.minute as u32,
+            t.second as u32,
+            t.nanosecond.unwrap_or(0),
+        )
+}
+
+/// Extract a string value from EXIF metadata.
+/// Spaces are replaced with hyphens for filesystem-friendly paths.
+fn exif_string(meta_data: Option<&exif::Exif>, tag: Tag) -> Option<String> {
+    meta_data?
+        .get_field(tag, exif::In::PRIMARY)
+        .map(|f| f.display_value().to_string().trim().replace(' ', "-"))
+        .filter(|s| !s.is_empty())
+}
+
+/// Extract GPS coordinates as decimal `(latitude, longitude)` from EXIF
+/// metadata.
+fn exif_gps(meta_data: &exif::Exif) -> Option<(f64, f64)> {
+    let coordinate = |tag: Tag, ref_tag: Tag, negative: &[u8]| {
+        let degrees = match meta_data.get_field(tag, exif::In::PRIMARY)?.value {
+            Value::Rational(ref dms) if dms.len() >= 3 => {
+                dms[0].to_f64()
+                    + dms[1].to_f64() / 60.0
+                    + dms[2].to_f64() / 3600.0
+            }
+            _ => return None,
+        };
+        match meta_data.get_field(ref_tag, exif::In::PRIMARY)?.value {
+            Value::Ascii(ref vec) if vec.first()? == negative => Some(-degrees),
+            Value::Ascii(_) => Some(degrees),
+            _ => None,
+        }
+    };
+
+    Some((
+        coordinate(Tag::GPSLatitude, Tag::GPSLatitudeRef, b"S")?,
+        coordinate(Tag::GPSLongitude, Tag::GPSLongitudeRef, b"W")?,
+    ))
+}
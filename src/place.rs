@@ -0,0 +1,126 @@
+//! Named geofences used to resolve the `{place}` template variable.
+
+use crate::config::Place;
+
+/// Mean earth radius in meters.
+const EARTH_RADIUS: f64 = 6_371_000.0;
+
+impl Place {
+    /// Returns `true` if the given coordinate lies within this place.
+    ///
+    /// A place is either a circle (`center` plus `radius` in meters) or a
+    /// `polygon`. If both are given, a coordinate matching either counts.
+    pub fn contains(&self, latitude: f64, longitude: f64) -> bool {
+        let in_circle = match (self.center, self.radius) {
+            (Some(center), Some(radius)) => {
+                haversine_distance(center, [latitude, longitude]) <= radius
+            }
+            _ => false,
+        };
+
+        in_circle
+            || self.polygon.as_deref().is_some_and(|polygon| {
+                polygon_contains(polygon, [latitude, longitude])
+            })
+    }
+
+    /// Returns `true` if this place has a usable shape.
+    pub fn is_valid(&self) -> bool {
+        (self.center.is_some() && self.radius.is_some())
+            || self.polygon.as_ref().is_some_and(|p| p.len() >= 3)
+    }
+}
+
+/// Returns the first place in `places` containing the given coordinate.
+pub fn find_place(
+    places: &[Place],
+    latitude: f64,
+    longitude: f64,
+) -> Option<&Place> {
+    places
+        .iter()
+        .find(|place| place.contains(latitude, longitude))
+}
+
+/// Great-circle distance in meters between two `[latitude, longitude]`
+/// coordinates.
+fn haversine_distance(a: [f64; 2], b: [f64; 2]) -> f64 {
+    let (lat_a, lat_b) = (a[0].to_radians(), b[0].to_radians());
+    let delta_lat = (b[0] - a[0]).to_radians();
+    let delta_lon = (b[1] - a[1]).to_radians();
+
+    let h = (delta_lat / 2.0).sin().powi(2)
+        + lat_a.cos() * lat_b.cos() * (delta_lon / 2.0).sin().powi(2);
+
+    2.0 * EARTH_RADIUS * h.sqrt().asin()
+}
+
+/// Even-odd rule point-in-polygon test.
+///
+/// Treats coordinates as planar which is accurate enough for the
+/// neighborhood-sized areas geofences are used for.
+fn polygon_contains(polygon: &[[f64; 2]], point: [f64; 2]) -> bool {
+    let [y, x] = point;
+    let mut inside = false;
+
+    for (i, vertex) in polygon.iter().enumerate() {
+        let [y_i, x_i] = *vertex;
+        let [y_j, x_j] = polygon[(i + polygon.len() - 1) % polygon.len()];
+
+        if (y_i > y) != (y_j > y)
+            && x < (x_j - x_i) * (y - y_i) / (y_j - y_i) + x_i
+        {
+            inside = !inside;
+        }
+    }
+
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn circle(name: &str, center: [f64; 2], radius: f64) -> Place {
+        Place {
+            name: name.to_string(),
+            center: Some(center),
+            radius: Some(radius),
+            polygon: None,
+        }
+    }
+
+    #[test]
+    fn circle_contains() {
+        let home = circle("home", [52.5200, 13.4050], 100.0);
+        // ~55m north.
+        assert!(home.contains(52.5205, 13.4050));
+        // ~1.1km north.
+        assert!(!home.contains(52.5300, 13.4050));
+    }
+
+    #[test]
+    fn polygon_contains_point() {
+        let studio = Place {
+            name: "studio".to_string(),
+            center: None,
+            radius: None,
+            polygon: Some(vec![[0.0, 0.0], [0.0, 1.0], [1.0, 1.0], [1.0, 0.0]]),
+        };
+        assert!(studio.contains(0.5, 0.5));
+        assert!(!studio.contains(1.5, 0.5));
+    }
+
+    #[test]
+    fn first_match_wins() {
+        let places = [
+            circle("office", [10.0, 10.0], 1000.0),
+            circle("campus", [10.0, 10.0], 5000.0),
+        ];
+        assert_eq!(
+            find_place(&places, 10.0, 10.0).map(|p| p.name.as_str()),
+            Some("office")
+        );
+        assert!(find_place(&places, 20.0, 20.0).is_none());
+    }
+}
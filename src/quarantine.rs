@@ -0,0 +1,229 @@
+//! Quarantining files instead of removing or trashing them.
+//!
+//! With `--quarantine DIR`, duplicates, conflicting files and files whose
+//! metadata cannot be read are moved (with `--copy`, copied) into a folder
+//! for the day below `DIR`, e.g. `DIR/2024-05-01`. There they keep their path
+//! relative to the source, sidecars included.
+//!
+//! Every file quarantined is recorded in `manifest.jsonl` in that folder, one
+//! JSON object per line with where it came from and why.
+
+use crate::{
+    group::Item,
+    util::{copy, move_or_copy, with_conflict_suffix},
+};
+use anyhow::{Context, Result};
+use chrono::Local;
+use indicatif::MultiProgress;
+use log::info;
+use serde::Serialize;
+use std::{
+    fmt, fs,
+    io::Write,
+    path::{Component, Path, PathBuf},
+    sync::Mutex,
+};
+
+const MANIFEST_FILE: &str = "manifest.jsonl";
+
+/// Why a file was quarantined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Reason {
+    /// A file with the same content is at the destination.
+    Duplicate,
+    /// A different file is at the destination.
+    Conflict,
+    /// Its metadata cannot be read.
+    Unreadable,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Duplicate => "duplicate",
+            Self::Conflict => "conflict",
+            Self::Unreadable => "unreadable",
+        })
+    }
+}
+
+/// A line of the manifest.
+#[derive(Debug, Serialize)]
+struct Entry<'a> {
+    /// Where the file was.
+    source: PathBuf,
+    /// Relative to the folder of the day.
+    path: &'a Path,
+    reason: Reason,
+    /// The file at the destination, for duplicates and conflicts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    existing: Option<&'a Path>,
+    time: String,
+}
+
+/// The quarantine folder of a run.
+#[derive(Debug)]
+pub(crate) struct Quarantine {
+    /// The folder of the day.
+    dir: PathBuf,
+    /// What paths are kept relative to.
+    source: PathBuf,
+    copy: bool,
+    dry_run: bool,
+    /// Opened with the first file quarantined.
+    manifest: Mutex<Option<fs::File>>,
+}
+
+impl Quarantine {
+    /// Quarantines into a folder for today below `root`, keeping paths
+    /// relative to `source`.
+    pub fn new(root: &Path, source: &Path, copy: bool, dry_run: bool) -> Self {
+        Self {
+            dir: root.join(Local::now().format("%Y-%m-%d").to_string()),
+            source: source.to_path_buf(),
+            copy,
+            dry_run,
+            manifest: Mutex::new(None),
+        }
+    }
+
+    /// Quarantines `item` and its sidecars. `existing` is the file at the
+    /// destination, if any.
+    ///
+    /// Returns where `item` went.
+    pub fn put(
+        &self,
+        item: &Item,
+        reason: Reason,
+        existing: Option<&Path>,
+        multi: &MultiProgress,
+    ) -> Result<PathBuf> {
+        let target = self.put_file(&item.path, reason, existing, multi)?;
+        for sidecar in &item.sidecars {
+            self.put_file(&sidecar.path, reason, existing, multi)?;
+        }
+
+        Ok(target)
+    }
+
+    fn put_file(
+        &self,
+        path: &Path,
+        reason: Reason,
+        existing: Option<&Path>,
+        multi: &MultiProgress,
+    ) -> Result<PathBuf> {
+        let relative = self.relative(path);
+        let mut target = self.dir.join(&relative);
+        let mut number = 0;
+        while target.exists() {
+            number += 1;
+            target = with_conflict_suffix(&self.dir.join(&relative), number);
+        }
+
+        if self.dry_run {
+            info!(
+                "Would quarantine {} ({}) to {}.",
+                path.display(),
+                reason,
+                target.display()
+            );
+            return Ok(target);
+        }
+
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).with_context(|| {
+                format!("Unable to create folder '{}'.", parent.display())
+            })?;
+        }
+        if self.copy {
+            copy(path, &target, true, multi)
+        } else {
+            move_or_copy(path, &target, true, multi)
+        }
+        .with_context(|| {
+            format!(
+                "Unable to quarantine {} to {}.",
+                path.display(),
+                target.display()
+            )
+        })?;
+        info!(
+            "Quarantined {} ({}) to {}.",
+            path.display(),
+            reason,
+            target.display()
+        );
+
+        let entry = Entry {
+            source: std::path::absolute(path)?,
+            path: target.strip_prefix(&self.dir).unwrap_or(&target),
+            reason,
+            existing,
+            time: Local::now().to_rfc3339(),
+        };
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+
+        let manifest_path = self.dir.join(MANIFEST_FILE);
+        let mut manifest = self.manifest.lock().unwrap();
+        if manifest.is_none() {
+            *manifest = Some(
+                fs::File::options()
+                    .create(true)
+                    .append(true)
+                    .open(&manifest_path)
+                    .with_context(|| {
+                        format!("Unable to open '{}'.", manifest_path.display())
+                    })?,
+            );
+        }
+        let file = manifest.as_mut().unwrap();
+        file.write_all(line.as_bytes())
+            .and_then(|()| file.sync_data())
+            .with_context(|| {
+                format!("Unable to write '{}'.", manifest_path.display())
+            })?;
+
+        Ok(target)
+    }
+
+    /// Returns `path` relative to the source, or else without its root.
+    fn relative(&self, path: &Path) -> PathBuf {
+        path.strip_prefix(&self.source)
+            .ok()
+            .filter(|relative| !relative.as_os_str().is_empty())
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| {
+                std::path::absolute(path)
+                    .unwrap_or_else(|_| path.to_path_buf())
+                    .components()
+                    .filter(|c| matches!(c, Component::Normal(_)))
+                    .collect()
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_relative_to_source() {
+        let quarantine = Quarantine::new(
+            Path::new("/q"),
+            Path::new("/card/DCIM"),
+            false,
+            true,
+        );
+        assert_eq!(
+            quarantine.relative(Path::new("/card/DCIM/100/IMG_1.jpg")),
+            Path::new("100/IMG_1.jpg")
+        );
+        assert_eq!(
+            quarantine.relative(Path::new("/other/IMG_2.jpg")),
+            Path::new("other/IMG_2.jpg")
+        );
+    }
+}
@@ -0,0 +1,275 @@
+//! QuickTime/MP4 movie metadata.
+//!
+//! Only the `moov` atom is read: the creation time from `mvhd` and the Apple
+//! metadata keys `com.apple.quicktime.creationdate` and
+//! `com.apple.quicktime.content.identifier` from `meta`.
+
+use anyhow::{Context, Result, anyhow};
+use chrono::{Local, NaiveDateTime};
+use std::{
+    fs,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+/// Seconds between 1904-01-01, the QuickTime epoch, and the UNIX epoch.
+const QUICKTIME_EPOCH_OFFSET: i64 = 2_082_844_800;
+
+/// `moov` atoms larger than this are not read.
+const MAX_MOOV_SIZE: u64 = 64 * 1024 * 1024;
+
+const CREATION_DATE_KEY: &[u8] = b"com.apple.quicktime.creationdate";
+const CONTENT_IDENTIFIER_KEY: &[u8] = b"com.apple.quicktime.content.identifier";
+
+/// Metadata read from a movie.
+#[derive(Debug, Default)]
+pub struct QuickTime {
+    /// Capture time, as local time.
+    pub time_stamp: Option<NaiveDateTime>,
+    /// Links the movie of an Apple Live Photo to its still.
+    pub content_identifier: Option<String>,
+}
+
+/// Reads the metadata of a QuickTime or MP4 movie.
+pub fn read(path: &Path) -> Result<QuickTime> {
+    let mut file = fs::File::open(path)
+        .with_context(|| format!("Unable to open '{}'.", path.display()))?;
+    let moov = read_moov(&mut file)
+        .with_context(|| format!("Unable to read '{}'.", path.display()))?
+        .ok_or_else(|| {
+            anyhow!("No movie metadata found in '{}'.", path.display())
+        })?;
+
+    Ok(parse_moov(&moov))
+}
+
+/// Returns the contents of the top level `moov` atom.
+fn read_moov(file: &mut fs::File) -> std::io::Result<Option<Vec<u8>>> {
+    let length = file.metadata()?.len();
+    let mut position = 0;
+
+    while position + 8 <= length {
+        file.seek(SeekFrom::Start(position))?;
+        let mut header = [0u8; 8];
+        file.read_exact(&mut header)?;
+
+        let mut header_size = 8;
+        let size = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            0 => length - position,
+            1 => {
+                let mut large_size = [0u8; 8];
+                file.read_exact(&mut large_size)?;
+                header_size = 16;
+                u64::from_be_bytes(large_size)
+            }
+            size => size as u64,
+        };
+        // Sizes come from the file; a broken one must not run past its end.
+        if size < header_size || size > length - position {
+            break;
+        }
+
+        if &header[4..] == b"moov" {
+            if size > MAX_MOOV_SIZE {
+                break;
+            }
+            let mut moov = vec![0u8; (size - header_size) as usize];
+            file.read_exact(&mut moov)?;
+            return Ok(Some(moov));
+        }
+
+        position += size;
+    }
+
+    Ok(None)
+}
+
+fn parse_moov(moov: &[u8]) -> QuickTime {
+    let mut quicktime = QuickTime::default();
+    let mut keys: Vec<&[u8]> = Vec::new();
+
+    for (kind, data) in atoms(moov) {
+        match kind {
+            b"mvhd" => {
+                quicktime.time_stamp =
+                    quicktime.time_stamp.or_else(|| mvhd_time_stamp(data));
+            }
+            b"meta" => {
+                // In QuickTime files `meta` is a plain atom, in MP4 files it
+                // has a version and flags.
+                let data = match data.get(4..8) {
+                    Some(b"hdlr") => data,
+                    _ => data.get(4..).unwrap_or_default(),
+                };
+
+                for (kind, data) in atoms(data) {
+                    match kind {
+                        b"keys" => keys = parse_keys(data),
+                        b"ilst" => {
+                            for (index, value) in parse_ilst(data) {
+                                let Some(key) = keys.get(index.wrapping_sub(1))
+                                else {
+                                    continue;
+                                };
+                                if *key == CREATION_DATE_KEY {
+                                    // Local time as written, ignoring the
+                                    // offset like for EXIF.
+                                    quicktime.time_stamp = value
+                                        .get(..19)
+                                        .and_then(|s| {
+                                            NaiveDateTime::parse_from_str(
+                                                s,
+                                                "%Y-%m-%dT%H:%M:%S",
+                                            )
+                                            .ok()
+                                        })
+                                        .or(quicktime.time_stamp);
+                                } else if *key == CONTENT_IDENTIFIER_KEY {
+                                    quicktime.content_identifier = Some(value);
+                                }
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    quicktime
+}
+
+/// Iterates over the `(type, contents)` of the atoms in `data`.
+fn atoms(mut data: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    std::iter::from_fn(move || {
+        let size = u32::from_be_bytes(data.get(..4)?.try_into().ok()?) as usize;
+        let size = if size == 0 { data.len() } else { size };
+        if size < 8 || size > data.len() {
+            return None;
+        }
+
+        let atom = (&data[4..8], &data[8..size]);
+        data = &data[size..];
+        Some(atom)
+    })
+}
+
+/// Creation time from a `mvhd` atom, in seconds since 1904 UTC.
+fn mvhd_time_stamp(data: &[u8]) -> Option<NaiveDateTime> {
+    let seconds = match data.first()? {
+        0 => u32::from_be_bytes(data.get(4..8)?.try_into().ok()?) as i64,
+        1 => {
+            i64::try_from(u64::from_be_bytes(data.get(4..12)?.try_into().ok()?))
+                .ok()?
+        }
+        _ => return None,
+    };
+    // Many cameras leave this unset.
+    if seconds == 0 {
+        return None;
+    }
+
+    chrono::DateTime::from_timestamp(seconds - QUICKTIME_EPOCH_OFFSET, 0)
+        // EXIF timestamps are local time, so are ours.
+        .map(|utc| utc.with_timezone(&Local).naive_local())
+}
+
+/// Key names from a `keys` atom, in order.
+fn parse_keys(data: &[u8]) -> Vec<&[u8]> {
+    // Skip version, flags and entry count; the entries are atoms with the
+    // namespace as type.
+    atoms(data.get(8..).unwrap_or_default())
+        .map(|(_, key)| key)
+        .collect()
+}
+
+/// `(key index, value)` of the UTF-8 entries of an `ilst` atom.
+fn parse_ilst(data: &[u8]) -> impl Iterator<Item = (usize, String)> {
+    atoms(data).filter_map(|(index, item)| {
+        let index = u32::from_be_bytes(index.try_into().ok()?) as usize;
+        let (kind, value) = atoms(item).next()?;
+        // Type indicator 1 is UTF-8.
+        if kind != b"data" || value.get(..4)? != [0, 0, 0, 1] {
+            return None;
+        }
+
+        Some((index, String::from_utf8_lossy(value.get(8..)?).into_owned()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn atom(kind: &[u8], contents: &[u8]) -> Vec<u8> {
+        let mut atom = ((contents.len() + 8) as u32).to_be_bytes().to_vec();
+        atom.extend_from_slice(kind);
+        atom.extend_from_slice(contents);
+        atom
+    }
+
+    fn utf8_item(index: u32, value: &str) -> Vec<u8> {
+        let mut data = vec![0, 0, 0, 1, 0, 0, 0, 0];
+        data.extend_from_slice(value.as_bytes());
+        atom(&index.to_be_bytes(), &atom(b"data", &data))
+    }
+
+    #[test]
+    fn parse_live_photo_movie() {
+        let mut mvhd = vec![0u8; 100];
+        mvhd[4..8].copy_from_slice(&3_775_000_000u32.to_be_bytes());
+
+        let mut keys = vec![0, 0, 0, 0, 0, 0, 0, 2];
+        keys.extend(atom(b"mdta", CREATION_DATE_KEY));
+        keys.extend(atom(b"mdta", CONTENT_IDENTIFIER_KEY));
+
+        let mut ilst = utf8_item(1, "2023-08-15T14:30:00+0200");
+        ilst.extend(utf8_item(2, "4F2B6C1E-0D5A-4B8E-9C3F-0123456789AB"));
+
+        let mut meta = atom(b"hdlr", &[0; 24]);
+        meta.extend(atom(b"keys", &keys));
+        meta.extend(atom(b"ilst", &ilst));
+
+        let mut moov = atom(b"mvhd", &mvhd);
+        moov.extend(atom(b"meta", &meta));
+
+        let quicktime = parse_moov(&moov);
+        assert_eq!(
+            quicktime.time_stamp,
+            NaiveDate::from_ymd_opt(2023, 8, 15)
+                .unwrap()
+                .and_hms_opt(14, 30, 0)
+        );
+        assert_eq!(
+            quicktime.content_identifier.as_deref(),
+            Some("4F2B6C1E-0D5A-4B8E-9C3F-0123456789AB")
+        );
+    }
+
+    #[test]
+    fn parse_mvhd_only() {
+        let mut mvhd = vec![0u8; 100];
+        mvhd[4..8].copy_from_slice(&3_775_000_000u32.to_be_bytes());
+
+        let quicktime = parse_moov(&atom(b"mvhd", &mvhd));
+        assert!(quicktime.time_stamp.is_some());
+        assert!(quicktime.content_identifier.is_none());
+    }
+
+    #[test]
+    fn read_broken_large_size() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let path = tmp.path().join("broken.mov");
+        let mut movie = atom(b"ftyp", b"qt  ");
+        movie.extend_from_slice(&1u32.to_be_bytes());
+        movie.extend_from_slice(b"mdat");
+        movie.extend_from_slice(&u64::MAX.to_be_bytes());
+        movie.extend_from_slice(&atom(b"moov", &[]));
+        fs::write(&path, &movie).unwrap();
+
+        let mut file = fs::File::open(&path).unwrap();
+        assert!(read_moov(&mut file).unwrap().is_none());
+    }
+}
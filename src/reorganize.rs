@@ -0,0 +1,378 @@
+//! Moving a library from one template to another.
+//!
+//! All moves are planned before any file is touched. A group of files (see
+//! [`group`](crate::group)) with its sidecars stays where it is if any of its
+//! files would overwrite a file that stays or that another group moves to.
+//! Moves into places other moves free up are ordered accordingly; cycles
+//! are broken by moving one file to a temporary name like
+//! `exifmv-1-IMG_1234.CR3` first.
+
+use crate::{
+    config::Config as AppConfig,
+    group::Group,
+    template::{PathMatcher, Template},
+    template_context,
+};
+use anyhow::{Context, Result, anyhow};
+use chrono::NaiveTime;
+use log::{info, warn};
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// A planned move.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Move {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+/// Plans the moves of `groups` in `library` from the `from` to the `to`
+/// template, one list per group.
+///
+/// `{filename}` and `{extension}` are taken from the old path where `from`
+/// has them, so names changed by the old template are restored. Files not
+/// matching `from` stay where they are.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn plan(
+    library: &Path,
+    groups: Vec<Group>,
+    from: &Template,
+    to: &Template,
+    time_offset: &NaiveTime,
+    make_lowercase: bool,
+    config: &AppConfig,
+) -> Vec<Vec<Move>> {
+    let matcher = from.matcher();
+
+    let planned: Vec<_> = groups
+        .into_iter()
+        .filter_map(|group| {
+            match plan_group(
+                library,
+                &group,
+                &matcher,
+                to,
+                time_offset,
+                make_lowercase,
+                config,
+            ) {
+                Ok(moves) => Some(moves),
+                Err(e) => {
+                    warn!("{}", e);
+                    None
+                }
+            }
+        })
+        .collect();
+
+    resolve_collisions(planned)
+}
+
+fn plan_group(
+    library: &Path,
+    group: &Group,
+    matcher: &PathMatcher,
+    to: &Template,
+    time_offset: &NaiveTime,
+    make_lowercase: bool,
+    config: &AppConfig,
+) -> Result<Vec<Move>> {
+    let metadata = match group.metadata() {
+        Some(metadata) => metadata,
+        None => {
+            return Err(anyhow!(
+                "Not moving {}: {}",
+                group.items[0].path.display(),
+                group.items[0].metadata.as_ref().unwrap_err()
+            ));
+        }
+    };
+
+    let mut moves = Vec::new();
+    for item in &group.items {
+        let relative = item.path.strip_prefix(library).unwrap_or(&item.path);
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        let Some(values) = matcher.captures(&relative) else {
+            return Err(anyhow!(
+                "Not moving {} as it does not match the old template.",
+                item.path.display()
+            ));
+        };
+
+        let mut ctx = template_context(
+            item,
+            library,
+            metadata,
+            time_offset,
+            make_lowercase,
+            config,
+        )?;
+        for (variable, value) in [
+            (&mut ctx.filename, values.get("filename")),
+            (&mut ctx.extension, values.get("extension")),
+        ] {
+            if let Some(value) = value {
+                *variable = if make_lowercase {
+                    value.to_lowercase()
+                } else {
+                    value.to_string()
+                };
+            }
+        }
+
+        let to: PathBuf = library.join(to.expand(&ctx)).components().collect();
+        for sidecar in &item.sidecars {
+            moves.push(Move {
+                from: sidecar.path.clone(),
+                to: sidecar.destination(&to, make_lowercase),
+            });
+        }
+        moves.push(Move {
+            from: item.path.clone(),
+            to,
+        });
+    }
+
+    moves.retain(|m| m.from != m.to);
+    Ok(moves)
+}
+
+/// Drops the groups that would overwrite a file, until none do.
+fn resolve_collisions(mut groups: Vec<Vec<Move>>) -> Vec<Vec<Move>> {
+    loop {
+        let moving: HashSet<PathBuf> =
+            groups.iter().flatten().map(|m| m.from.clone()).collect();
+        let mut claimed: HashSet<PathBuf> = HashSet::new();
+
+        let count = groups.len();
+        groups.retain(|moves| {
+            let collision = moves.iter().find(|m| {
+                claimed.contains(&m.to)
+                    || (m.to.exists() && !moving.contains(&m.to))
+            });
+            match collision {
+                Some(m) => {
+                    warn!(
+                        "{} would overwrite {}; leaving it and its \
+                         companions in place.",
+                        m.from.display(),
+                        m.to.display()
+                    );
+                    false
+                }
+                None => {
+                    claimed.extend(moves.iter().map(|m| m.to.clone()));
+                    true
+                }
+            }
+        });
+
+        if groups.len() == count {
+            return groups;
+        }
+    }
+}
+
+/// Carries out `moves`, then removes the folders left empty below
+/// `library`.
+pub(crate) fn execute(
+    library: &Path,
+    moves: Vec<Move>,
+    dry_run: bool,
+) -> Result<()> {
+    if dry_run {
+        for m in &moves {
+            info!("{} ➔ {}", m.from.display(), m.to.display());
+        }
+        return Ok(());
+    }
+
+    let old_folders: HashSet<PathBuf> = moves
+        .iter()
+        .filter_map(|m| m.from.parent().map(Path::to_path_buf))
+        .collect();
+
+    let mut pending = moves;
+    let mut temporary = 0;
+    // Files under a temporary name. Not hidden, so they are not lost to
+    // later runs if we fail before moving them on.
+    let mut parked: Vec<PathBuf> = Vec::new();
+    while !pending.is_empty() {
+        let sources: HashSet<PathBuf> =
+            pending.iter().map(|m| m.from.clone()).collect();
+        let (ready, mut blocked): (Vec<_>, Vec<_>) =
+            pending.into_iter().partition(|m| !sources.contains(&m.to));
+
+        if ready.is_empty() {
+            // Every destination is the source of another move: a cycle.
+            let m = &mut blocked[0];
+            let name = m.from.file_name().unwrap_or_default().to_string_lossy();
+            let temp = loop {
+                temporary += 1;
+                let temp =
+                    m.from.with_file_name(format!("exifmv-{temporary}-{name}"));
+                if !temp.exists() {
+                    break temp;
+                }
+            };
+            rename(&m.from, &temp).map_err(|e| with_parked(e, &parked))?;
+            parked.push(temp.clone());
+            m.from = temp;
+        }
+
+        for m in ready {
+            info!("{} ➔ {}", m.from.display(), m.to.display());
+            rename(&m.from, &m.to).map_err(|e| with_parked(e, &parked))?;
+            parked.retain(|path| *path != m.from);
+        }
+        pending = blocked;
+    }
+
+    remove_empty_folders(library, old_folders);
+    Ok(())
+}
+
+fn rename(from: &Path, to: &Path) -> Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent).with_context(|| {
+            format!("Unable to create folder '{}'.", parent.display())
+        })?;
+    }
+    fs::rename(from, to).with_context(|| {
+        format!("Unable to move {} to {}.", from.display(), to.display())
+    })
+}
+
+/// Adds the files still under a temporary name to `error`.
+fn with_parked(error: anyhow::Error, parked: &[PathBuf]) -> anyhow::Error {
+    if parked.is_empty() {
+        return error;
+    }
+    let paths: Vec<_> = parked
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+    error.context(format!(
+        "Reorganizing stopped with files under temporary names: {}",
+        paths.join(", ")
+    ))
+}
+
+/// Removes `folders` and their parents below `library` if empty.
+fn remove_empty_folders(library: &Path, folders: HashSet<PathBuf>) {
+    let mut folders: Vec<_> = folders.into_iter().collect();
+    // Deepest first, so parents are empty when it is their turn.
+    folders
+        .sort_by_key(|folder| std::cmp::Reverse(folder.components().count()));
+
+    for folder in folders {
+        for folder in folder.ancestors() {
+            if !folder.starts_with(library)
+                || folder == library
+                || fs::remove_dir(folder).is_err()
+            {
+                break;
+            }
+            info!("Removed empty folder {}.", folder.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn moves_in_order_and_breaks_cycles() {
+        let tmp = TempDir::new().unwrap();
+        let library = tmp.path();
+        fs::create_dir_all(library.join("old")).unwrap();
+        fs::write(library.join("a.jpg"), b"a").unwrap();
+        fs::write(library.join("b.jpg"), b"b").unwrap();
+        fs::write(library.join("old/c.jpg"), b"c").unwrap();
+
+        let m = |from: &str, to: &str| Move {
+            from: library.join(from),
+            to: library.join(to),
+        };
+        execute(
+            library,
+            vec![
+                // Swapped.
+                m("a.jpg", "b.jpg"),
+                m("b.jpg", "a.jpg"),
+                // Needs the place `a.jpg` leaves.
+                m("old/c.jpg", "new/c.jpg"),
+            ],
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(fs::read(library.join("a.jpg")).unwrap(), b"b");
+        assert_eq!(fs::read(library.join("b.jpg")).unwrap(), b"a");
+        assert_eq!(fs::read(library.join("new/c.jpg")).unwrap(), b"c");
+        assert!(!library.join("old").exists());
+        assert_eq!(fs::read_dir(library).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn failures_name_parked_files() {
+        let tmp = TempDir::new().unwrap();
+        let library = tmp.path();
+        fs::write(library.join("a.jpg"), b"a").unwrap();
+        fs::write(library.join("b.jpg"), b"b").unwrap();
+
+        let m = |from: &str, to: &str| Move {
+            from: library.join(from),
+            to: library.join(to),
+        };
+        // `c.jpg` is gone, so the cycle is never closed.
+        let error = execute(
+            library,
+            vec![
+                m("a.jpg", "b.jpg"),
+                m("b.jpg", "c.jpg"),
+                m("c.jpg", "a.jpg"),
+            ],
+            false,
+        )
+        .unwrap_err();
+
+        let parked = library.join("exifmv-1-a.jpg");
+        assert!(parked.exists());
+        assert!(error.to_string().contains(&parked.display().to_string()));
+    }
+
+    #[test]
+    fn drops_colliding_groups() {
+        let tmp = TempDir::new().unwrap();
+        let library = tmp.path();
+        fs::write(library.join("stays.jpg"), b"").unwrap();
+        fs::write(library.join("moves.jpg"), b"").unwrap();
+
+        let m = |from: &str, to: &str| Move {
+            from: library.join(from),
+            to: library.join(to),
+        };
+        let groups = resolve_collisions(vec![
+            vec![m("x.jpg", "stays.jpg")],
+            vec![m("y.jpg", "moves.jpg")],
+            vec![m("moves.jpg", "z.jpg")],
+            // Both want `w.jpg`; so does the first file of this pair.
+            vec![m("v.jpg", "w.jpg")],
+            vec![m("u.jpg", "t.jpg"), m("u.xmp", "w.jpg")],
+        ]);
+        assert_eq!(
+            groups,
+            [
+                vec![m("y.jpg", "moves.jpg")],
+                vec![m("moves.jpg", "z.jpg")],
+                vec![m("v.jpg", "w.jpg")],
+            ]
+        );
+    }
+}
@@ -0,0 +1,241 @@
+//! Reporting what a run did with each file.
+//!
+//! `--report FILE` writes one record per file (sidecars not counted) with its
+//! source, destination, action, size, date source and error, if any. The
+//! format follows the extension of FILE: `.json` for an array, `.ndjson` or
+//! `.jsonl` for one object per line and `.csv` for a table with a header.
+//!
+//! Totals per action are printed at the end of every run.
+
+use crate::{config::DateSource, group::Item, util::Outcome};
+use anyhow::{Context, Result, anyhow};
+use indicatif::HumanBytes;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+/// What was done with a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Action {
+    Moved,
+    Copied,
+    /// Source and destination are the same file.
+    InPlace,
+    SkippedDuplicate,
+    SkippedConflict,
+    Removed,
+    Trashed,
+    Quarantined,
+    Failed,
+}
+
+impl Action {
+    /// The action for `outcome`; with `copy` files are copied, not moved.
+    pub fn new(outcome: Outcome, copy: bool) -> Self {
+        match outcome {
+            Outcome::InPlace => Self::InPlace,
+            Outcome::Moved if copy => Self::Copied,
+            Outcome::Moved => Self::Moved,
+            Outcome::Duplicate => Self::SkippedDuplicate,
+            Outcome::Removed => Self::Removed,
+            Outcome::Trashed => Self::Trashed,
+            Outcome::Conflict => Self::SkippedConflict,
+            Outcome::Quarantined => Self::Quarantined,
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Moved => "moved",
+            Self::Copied => "copied",
+            Self::InPlace => "in-place",
+            Self::SkippedDuplicate => "skipped-duplicate",
+            Self::SkippedConflict => "skipped-conflict",
+            Self::Removed => "removed",
+            Self::Trashed => "trashed",
+            Self::Quarantined => "quarantined",
+            Self::Failed => "failed",
+        })
+    }
+}
+
+/// What was done with a file, for the report.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Record {
+    pub source: PathBuf,
+    /// Where the file went or, if skipped, the file in its way.
+    pub destination: Option<PathBuf>,
+    pub action: Action,
+    pub bytes: u64,
+    pub date_source: Option<DateSource>,
+    pub error: Option<String>,
+}
+
+impl Record {
+    /// A record of `item`, which is `bytes` large.
+    pub fn new(
+        item: &Item,
+        bytes: u64,
+        action: Action,
+        destination: Option<&Path>,
+    ) -> Self {
+        Self {
+            source: item.path.clone(),
+            destination: destination.map(Path::to_path_buf),
+            action,
+            bytes,
+            date_source: item
+                .metadata
+                .as_ref()
+                .ok()
+                .and_then(|m| m.date_source),
+            error: None,
+        }
+    }
+
+    /// A record of `item` failing, until it is known to have succeeded.
+    pub fn failed(item: &Item) -> Self {
+        let bytes = item.path.metadata().map_or(0, |m| m.len());
+        Self::new(item, bytes, Action::Failed, None)
+    }
+}
+
+/// Format of the report file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Format {
+    Json,
+    Ndjson,
+    Csv,
+}
+
+impl Format {
+    /// Returns the format for the extension of `path`.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        match path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase)
+            .as_deref()
+        {
+            Some("json") => Ok(Self::Json),
+            Some("ndjson" | "jsonl") => Ok(Self::Ndjson),
+            Some("csv") => Ok(Self::Csv),
+            _ => Err(anyhow!(
+                "Unknown report format of '{}'; use a .json, .ndjson or .csv \
+                 file.",
+                path.display()
+            )),
+        }
+    }
+}
+
+/// Writes `records` to `path` in `format`.
+pub(crate) fn write(
+    path: &Path,
+    format: Format,
+    records: &[Record],
+) -> Result<()> {
+    let mut writer =
+        BufWriter::new(fs::File::create(path).with_context(|| {
+            format!("Unable to create report '{}'.", path.display())
+        })?);
+
+    match format {
+        Format::Json => serde_json::to_writer_pretty(&mut writer, records)?,
+        Format::Ndjson => {
+            for record in records {
+                serde_json::to_writer(&mut writer, record)?;
+                writeln!(writer)?;
+            }
+        }
+        Format::Csv => {
+            writeln!(
+                writer,
+                "source,destination,action,bytes,date_source,error"
+            )?;
+            for record in records {
+                writeln!(
+                    writer,
+                    "{},{},{},{},{},{}",
+                    csv_field(&record.source.to_string_lossy()),
+                    csv_field(
+                        &record
+                            .destination
+                            .as_deref()
+                            .map(|p| p.to_string_lossy())
+                            .unwrap_or_default()
+                    ),
+                    record.action,
+                    record.bytes,
+                    record
+                        .date_source
+                        .map(|d| d.to_string())
+                        .unwrap_or_default(),
+                    csv_field(record.error.as_deref().unwrap_or_default()),
+                )?;
+            }
+        }
+    }
+
+    writer.flush().with_context(|| {
+        format!("Unable to write report '{}'.", path.display())
+    })
+}
+
+/// Quotes `value` for CSV if needed.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Prints the number and size of files per action in `records`.
+pub(crate) fn print_totals(records: &[Record]) {
+    if records.is_empty() {
+        return;
+    }
+
+    let mut totals: BTreeMap<Action, (u64, u64)> = BTreeMap::new();
+    for record in records {
+        let (files, bytes) = totals.entry(record.action).or_default();
+        *files += 1;
+        *bytes += record.bytes;
+    }
+
+    let width = totals
+        .keys()
+        .map(|action| action.to_string().len())
+        .chain(["Action".len()])
+        .max()
+        .unwrap_or_default();
+    println!("{:<width$}  {:>8}  {:>10}", "Action", "Files", "Size");
+    for (action, (files, bytes)) in totals {
+        println!(
+            "{:<width$}  {:>8}  {:>10}",
+            action.to_string(),
+            files,
+            HumanBytes(bytes).to_string()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_fields_are_quoted() {
+        assert_eq!(csv_field("a.jpg"), "a.jpg");
+        assert_eq!(csv_field("a,b.jpg"), "\"a,b.jpg\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+}
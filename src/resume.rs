@@ -0,0 +1,216 @@
+//! Resuming interrupted runs.
+//!
+//! Every group of files is recorded in `.exifmv/run` in the destination once
+//! it is done. The file is removed when a run completes without errors, so if
+//! it exists the last run was interrupted or failed. `--resume` then skips
+//! the files it records, e.g. those already copied with `import --copy`, and
+//! removes the hidden temp files of copies that were cut short.
+//!
+//! On Unix, the first Ctrl-C lets the files being moved finish and skips the
+//! rest; a second one exits immediately.
+
+use crate::{index::STATE_DIR, util::TEMP_SUFFIX};
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::{
+    collections::HashSet,
+    fs,
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+use walkdir::WalkDir;
+
+const RUN_FILE: &str = "run";
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Lets Ctrl-C stop a run after the files being moved, see [`interrupted`].
+pub(crate) fn handle_interrupt() {
+    #[cfg(unix)]
+    {
+        extern "C" fn handler(_: libc::c_int) {
+            if INTERRUPTED.swap(true, Ordering::SeqCst) {
+                // SAFETY: `_exit` is async-signal-safe.
+                unsafe { libc::_exit(130) };
+            }
+        }
+
+        // SAFETY: the handler only touches an atomic and calls `_exit`.
+        unsafe {
+            libc::signal(
+                libc::SIGINT,
+                handler as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            )
+        };
+    }
+}
+
+/// Returns `true` once the user asked to stop.
+pub(crate) fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// The progress of a run.
+#[derive(Debug)]
+pub(crate) struct RunState {
+    path: PathBuf,
+    done: HashSet<PathBuf>,
+    file: Mutex<fs::File>,
+}
+
+impl RunState {
+    /// Starts recording a run into `dest_dir`.
+    ///
+    /// With `resume` the files recorded by an interrupted run are skipped.
+    /// Either way, temp files left by one are removed.
+    pub fn open(dest_dir: &Path, resume: bool) -> Result<Self> {
+        let dir = dest_dir.join(STATE_DIR);
+        let path = dir.join(RUN_FILE);
+
+        let mut done = HashSet::new();
+        if path.exists() {
+            if resume {
+                let file = fs::File::open(&path).with_context(|| {
+                    format!("Unable to open '{}'.", path.display())
+                })?;
+                for line in BufReader::new(file).lines() {
+                    let line = line.with_context(|| {
+                        format!("Unable to read '{}'.", path.display())
+                    })?;
+                    // The last line may be cut short.
+                    if let Ok(done_path) = serde_json::from_str(&line) {
+                        done.insert(done_path);
+                    }
+                }
+                info!("Resuming; {} file(s) are done already.", done.len());
+            } else {
+                warn!(
+                    "The last run into {} did not complete; starting over. Use \
+                     --resume to skip the files it moved.",
+                    dest_dir.display()
+                );
+            }
+            remove_temp_files(dest_dir);
+        } else if resume {
+            info!("Nothing to resume in {}.", dest_dir.display());
+        }
+
+        fs::create_dir_all(&dir).with_context(|| {
+            format!("Unable to create folder '{}'.", dir.display())
+        })?;
+        let mut file = fs::File::create(&path).with_context(|| {
+            format!("Unable to create '{}'.", path.display())
+        })?;
+        // Keep what is done, in case this run is interrupted too.
+        for done_path in &done {
+            writeln!(file, "{}", serde_json::to_string(done_path)?)?;
+        }
+
+        Ok(Self {
+            path,
+            done,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Returns `true` if `path` was done by the run resumed.
+    pub fn is_done(&self, path: &Path) -> bool {
+        std::path::absolute(path).is_ok_and(|path| self.done.contains(&path))
+    }
+
+    /// Records that `paths` are done.
+    pub fn record<'a>(
+        &self,
+        paths: impl IntoIterator<Item = &'a Path>,
+    ) -> Result<()> {
+        let mut lines = String::new();
+        for path in paths {
+            lines
+                .push_str(&serde_json::to_string(&std::path::absolute(path)?)?);
+            lines.push('\n');
+        }
+
+        let mut file = self.file.lock().unwrap();
+        file.write_all(lines.as_bytes())
+            .and_then(|()| file.sync_data())
+            .with_context(|| {
+                format!("Unable to write '{}'.", self.path.display())
+            })
+    }
+
+    /// Removes the state of a run that completed.
+    pub fn finish(self) -> Result<()> {
+        drop(self.file);
+        fs::remove_file(&self.path).with_context(|| {
+            format!("Unable to remove '{}'.", self.path.display())
+        })?;
+        // Unless it holds anything else, e.g. an index.
+        if let Some(dir) = self.path.parent() {
+            let _ = fs::remove_dir(dir);
+        }
+
+        Ok(())
+    }
+}
+
+/// Removes the temp files of copies cut short below `dest_dir`.
+fn remove_temp_files(dest_dir: &Path) {
+    for entry in WalkDir::new(dest_dir)
+        .into_iter()
+        .filter_entry(|e| {
+            e.depth() == 0
+                || !e.file_type().is_dir()
+                || !e.file_name().to_string_lossy().starts_with('.')
+        })
+        .flatten()
+    {
+        let name = entry.file_name().to_string_lossy();
+        if entry.file_type().is_file()
+            && name.starts_with('.')
+            && name.ends_with(TEMP_SUFFIX)
+        {
+            match fs::remove_file(entry.path()) {
+                Ok(()) => info!("Removed {}.", entry.path().display()),
+                Err(e) => {
+                    warn!("Unable to remove {}: {}", entry.path().display(), e)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::temp_path;
+    use tempfile::TempDir;
+
+    #[test]
+    fn resume_skips_done_files() {
+        let tmp = TempDir::new().unwrap();
+        let dest = tmp.path().join("dest");
+        let done = tmp.path().join("done.jpg");
+        let todo = tmp.path().join("todo.jpg");
+
+        let state = RunState::open(&dest, false).unwrap();
+        state.record([done.as_path()]).unwrap();
+        // Interrupted while copying.
+        fs::create_dir_all(dest.join("2023")).unwrap();
+        let temp = temp_path(&dest.join("2023/todo.jpg"));
+        fs::write(&temp, b"partial").unwrap();
+        drop(state);
+
+        let state = RunState::open(&dest, true).unwrap();
+        assert!(state.is_done(&done));
+        assert!(!state.is_done(&todo));
+        assert!(!temp.exists(), "Temp file should be removed");
+        state.finish().unwrap();
+
+        let state = RunState::open(&dest, true).unwrap();
+        assert!(!state.is_done(&done), "Completed runs are not resumed");
+    }
+}
@@ -0,0 +1,223 @@
+//! Sidecar discovery and naming.
+//!
+//! Sidecars are described by patterns like `{name}.xmp` or `{stem}.thm`.
+//! `{name}` is the primary's full file name, `{stem}` its name without the
+//! extension. The suffix is matched as written, lowercase or uppercase.
+
+use crate::takeout;
+use anyhow::{Result, anyhow};
+use std::path::{Path, PathBuf};
+
+/// What a sidecar's name is derived from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Base {
+    /// The primary's full file name, e.g. `IMG_1234.CR3.xmp`.
+    Name,
+    /// The primary's file name without extension, e.g. `IMG_1234.xmp`.
+    Stem,
+}
+
+/// A parsed sidecar pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    base: Base,
+    suffix: String,
+}
+
+impl Pattern {
+    /// Parses a pattern like `{name}.xmp` or `{stem}.pp3`.
+    pub fn parse(pattern: &str) -> Result<Self> {
+        let (base, suffix) = if let Some(suffix) =
+            pattern.strip_prefix("{name}")
+        {
+            (Base::Name, suffix)
+        } else if let Some(suffix) = pattern.strip_prefix("{stem}") {
+            (Base::Stem, suffix)
+        } else {
+            return Err(anyhow!(
+                "Sidecar pattern '{}' must start with '{{name}}' or '{{stem}}'.",
+                pattern
+            ));
+        };
+
+        if suffix.is_empty() || suffix.contains(['/', '\\', '{', '}']) {
+            return Err(anyhow!(
+                "Sidecar pattern '{}' needs a plain suffix, e.g. '.xmp'.",
+                pattern
+            ));
+        }
+
+        Ok(Self {
+            base,
+            suffix: suffix.to_string(),
+        })
+    }
+
+    /// Returns `true` if a file named like `path` could be a sidecar of this
+    /// pattern, whether or not its primary exists.
+    pub fn matches_name(&self, path: &Path) -> bool {
+        path.file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| {
+                name.len() > self.suffix.len()
+                    && name
+                        .to_ascii_lowercase()
+                        .ends_with(&self.suffix.to_ascii_lowercase())
+            })
+    }
+}
+
+/// A sidecar found next to a primary file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sidecar {
+    /// Where the sidecar currently is.
+    pub path: PathBuf,
+    base: Base,
+    /// The suffix as found on disk.
+    suffix: String,
+}
+
+impl Sidecar {
+    /// Returns `true` if the sidecar has the given extension, ignoring case.
+    pub fn has_extension(&self, extension: &str) -> bool {
+        self.path
+            .extension()
+            .and_then(|s| s.to_str())
+            .is_some_and(|s| s.eq_ignore_ascii_case(extension))
+    }
+
+    /// Returns `true` if the sidecar is named after its primary's stem, as
+    /// with `{stem}.xmp`, so other files with that stem may share it.
+    pub fn by_stem(&self) -> bool {
+        self.base == Base::Stem
+    }
+
+    /// Returns where the sidecar goes if its primary goes to `dest_file`.
+    pub fn destination(
+        &self,
+        dest_file: &Path,
+        make_lowercase: bool,
+    ) -> PathBuf {
+        let base = match self.base {
+            Base::Name => dest_file.file_name(),
+            Base::Stem => dest_file.file_stem(),
+        }
+        .unwrap_or_default();
+
+        let mut name = base.to_os_string();
+        if make_lowercase {
+            name.push(self.suffix.to_lowercase());
+        } else {
+            name.push(&self.suffix);
+        }
+
+        dest_file.with_file_name(name)
+    }
+}
+
+/// Finds the sidecars of `source_file` matching `patterns`.
+pub fn find(source_file: &Path, patterns: &[Pattern]) -> Vec<Sidecar> {
+    let mut sidecars: Vec<Sidecar> = Vec::new();
+
+    for pattern in patterns {
+        let sidecar = if pattern.base == Base::Name
+            && pattern.suffix.eq_ignore_ascii_case(".json")
+        {
+            // Takeout truncates long sidecar names.
+            takeout::find_sidecar(source_file).map(|path| Sidecar {
+                path,
+                base: Base::Name,
+                suffix: ".json".to_string(),
+            })
+        } else {
+            find_one(source_file, pattern)
+        };
+
+        if let Some(sidecar) = sidecar
+            && sidecar.path != source_file
+            && !sidecars.iter().any(|s| s.path == sidecar.path)
+        {
+            sidecars.push(sidecar);
+        }
+    }
+
+    sidecars
+}
+
+fn find_one(source_file: &Path, pattern: &Pattern) -> Option<Sidecar> {
+    let base = match pattern.base {
+        Base::Name => source_file.file_name(),
+        Base::Stem => source_file.file_stem(),
+    }?;
+
+    let mut suffixes = vec![
+        pattern.suffix.clone(),
+        pattern.suffix.to_lowercase(),
+        pattern.suffix.to_uppercase(),
+    ];
+    suffixes.dedup();
+
+    suffixes.into_iter().find_map(|suffix| {
+        let mut name = base.to_os_string();
+        name.push(&suffix);
+        let path = source_file.with_file_name(name);
+
+        path.is_file().then_some(Sidecar {
+            path,
+            base: pattern.base,
+            suffix,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn patterns(patterns: &[&str]) -> Vec<Pattern> {
+        patterns
+            .iter()
+            .map(|p| Pattern::parse(p).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn parse_patterns() {
+        assert!(Pattern::parse("{name}.xmp").is_ok());
+        assert!(Pattern::parse("{stem}.THM").is_ok());
+        assert!(Pattern::parse("*.xmp").is_err());
+        assert!(Pattern::parse("{stem}").is_err());
+        assert!(Pattern::parse("{stem}/x.xmp").is_err());
+    }
+
+    #[test]
+    fn find_and_rename() {
+        let tmp = TempDir::new().unwrap();
+        let primary = tmp.path().join("MVI_0001.MP4");
+        fs::write(&primary, b"video").unwrap();
+        fs::write(tmp.path().join("MVI_0001.THM"), b"thumb").unwrap();
+        fs::write(tmp.path().join("MVI_0001.MP4.xmp"), b"xmp").unwrap();
+
+        let sidecars = find(
+            &primary,
+            &patterns(&["{name}.xmp", "{stem}.thm", "{stem}.lrv"]),
+        );
+        assert_eq!(sidecars.len(), 2);
+
+        let dest = Path::new("/library/2023/mvi_0001-1.mp4");
+        assert_eq!(
+            sidecars[0].destination(dest, false),
+            Path::new("/library/2023/mvi_0001-1.mp4.xmp")
+        );
+        assert_eq!(
+            sidecars[1].destination(dest, false),
+            Path::new("/library/2023/mvi_0001-1.THM")
+        );
+        assert_eq!(
+            sidecars[1].destination(dest, true),
+            Path::new("/library/2023/mvi_0001-1.thm")
+        );
+    }
+}
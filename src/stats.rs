@@ -0,0 +1,191 @@
+//! Statistics of a library.
+//!
+//! Files are read like when moving them, sidecars included, so the numbers
+//! show what templates would see.
+
+use crate::{
+    config::Config as AppConfig,
+    device::Throttle,
+    group::Item,
+    util::{FileKind, file_kind},
+};
+use anyhow::Result;
+use chrono::Datelike;
+use indicatif::HumanBytes;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::{collections::BTreeMap, path::PathBuf};
+
+/// Number and total size of files.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub(crate) struct Count {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+impl Count {
+    fn add(&mut self, bytes: u64) {
+        self.files += 1;
+        self.bytes += bytes;
+    }
+}
+
+/// Counts of the media files of a library, by category.
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct Stats {
+    pub total: Count,
+    /// By capture year and month, e.g. `2023-08`.
+    pub months: BTreeMap<String, Count>,
+    /// By camera make and model.
+    pub cameras: BTreeMap<String, Count>,
+    pub lenses: BTreeMap<String, Count>,
+    /// By kind: `raw`, `image` or `movie`.
+    pub kinds: BTreeMap<String, Count>,
+    /// By what is missing: `capture time`, `camera`, `lens` or `location`.
+    pub missing: BTreeMap<String, Count>,
+}
+
+/// Reads `files` and counts them.
+pub(crate) fn collect(
+    files: &[PathBuf],
+    config: &AppConfig,
+    throttle: &Throttle,
+) -> Result<Stats> {
+    let items = files
+        .par_iter()
+        .map(|file| {
+            let bytes = file.metadata().map(|m| m.len()).unwrap_or(0);
+            Ok((throttle.run(&[file], || Item::read(file, config))?, bytes))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut stats = Stats::default();
+    for (item, bytes) in items {
+        stats.total.add(bytes);
+
+        let kind = match file_kind(&item.path) {
+            Some(FileKind::Raw) => "raw",
+            Some(FileKind::Image) => "image",
+            Some(FileKind::Movie) => "movie",
+            None => "other",
+        };
+        stats.kinds.entry(kind.to_string()).or_default().add(bytes);
+
+        let Ok(metadata) = &item.metadata else {
+            stats
+                .missing
+                .entry("capture time".to_string())
+                .or_default()
+                .add(bytes);
+            continue;
+        };
+
+        let time_stamp = metadata.time_stamp;
+        stats
+            .months
+            .entry(format!(
+                "{:04}-{:02}",
+                time_stamp.year(),
+                time_stamp.month()
+            ))
+            .or_default()
+            .add(bytes);
+
+        match camera(
+            metadata.camera_make.as_deref(),
+            metadata.camera_model.as_deref(),
+        ) {
+            Some(camera) => stats.cameras.entry(camera),
+            None => stats.missing.entry("camera".to_string()),
+        }
+        .or_default()
+        .add(bytes);
+
+        match &metadata.lens {
+            Some(lens) => stats.lenses.entry(lens.clone()),
+            None => stats.missing.entry("lens".to_string()),
+        }
+        .or_default()
+        .add(bytes);
+
+        if metadata.gps.is_none() {
+            stats
+                .missing
+                .entry("location".to_string())
+                .or_default()
+                .add(bytes);
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Combines make and model as they appear in paths, e.g. `Canon` and
+/// `Canon-EOS-R5` to `Canon-EOS-R5` or `SONY` and `ILCE-7M4` to
+/// `SONY-ILCE-7M4`.
+fn camera(make: Option<&str>, model: Option<&str>) -> Option<String> {
+    match (make, model) {
+        (Some(make), Some(model)) if model.starts_with(make) => {
+            Some(model.to_string())
+        }
+        (Some(make), Some(model)) => Some(format!("{make}-{model}")),
+        (make, model) => make.or(model).map(str::to_string),
+    }
+}
+
+/// Prints `stats` as tables.
+pub(crate) fn print(stats: &Stats) {
+    for (title, counts) in [
+        ("Month", &stats.months),
+        ("Camera", &stats.cameras),
+        ("Lens", &stats.lenses),
+        ("Kind", &stats.kinds),
+        ("Missing", &stats.missing),
+    ] {
+        if counts.is_empty() {
+            continue;
+        }
+
+        let width = counts
+            .keys()
+            .map(|key| key.chars().count())
+            .chain([title.len()])
+            .max()
+            .unwrap_or_default();
+        println!("{:<width$}  {:>8}  {:>10}", title, "Files", "Size");
+        for (key, count) in counts {
+            println!(
+                "{:<width$}  {:>8}  {:>10}",
+                key,
+                count.files,
+                HumanBytes(count.bytes).to_string()
+            );
+        }
+        println!();
+    }
+
+    println!(
+        "{} file(s), {}.",
+        stats.total.files,
+        HumanBytes(stats.total.bytes)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn camera_names() {
+        assert_eq!(
+            camera(Some("Canon"), Some("Canon-EOS-R5")).as_deref(),
+            Some("Canon-EOS-R5")
+        );
+        assert_eq!(
+            camera(Some("SONY"), Some("ILCE-7M4")).as_deref(),
+            Some("SONY-ILCE-7M4")
+        );
+        assert_eq!(camera(None, Some("X100V")).as_deref(), Some("X100V"));
+        assert_eq!(camera(None, None), None);
+    }
+}
@@ -0,0 +1,149 @@
+//! Google Photos Takeout JSON sidecars.
+//!
+//! Takeout strips EXIF dates from exported files but ships a JSON sidecar
+//! next to each, named `IMG_1234.jpg.json`,
+//! `IMG_1234.jpg.supplemental-metadata.json` or a truncation thereof like
+//! `IMG_1234.j.json`.
+
+use anyhow::{Context, Result};
+use chrono::{Local, NaiveDateTime};
+use serde::Deserialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Maximum file name length of a sidecar, including the `.json` suffix.
+const MAX_NAME_LENGTH: usize = 51;
+
+/// Metadata read from a Takeout sidecar.
+#[derive(Debug, Default)]
+pub struct Takeout {
+    /// Capture time converted to local time.
+    pub time_stamp: Option<NaiveDateTime>,
+    /// GPS coordinates as `(latitude, longitude)`.
+    pub gps: Option<(f64, f64)>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Sidecar {
+    photo_taken_time: Option<Timestamp>,
+    geo_data: Option<GeoData>,
+    geo_data_exif: Option<GeoData>,
+}
+
+#[derive(Deserialize)]
+struct Timestamp {
+    /// Seconds since the UNIX epoch, as a string.
+    timestamp: String,
+}
+
+#[derive(Deserialize)]
+struct GeoData {
+    latitude: f64,
+    longitude: f64,
+}
+
+impl GeoData {
+    /// Takeout uses `0.0, 0.0` for "no location".
+    fn coordinates(&self) -> Option<(f64, f64)> {
+        (self.latitude != 0.0 || self.longitude != 0.0)
+            .then_some((self.latitude, self.longitude))
+    }
+}
+
+/// Finds the Takeout sidecar of `source_file`, if any.
+///
+/// Tries `<name>.json` first, then `<name>.supplemental-metadata.json` and
+/// ever shorter truncations of it down to the first character of the
+/// extension.
+pub fn find_sidecar(source_file: &Path) -> Option<PathBuf> {
+    let name = source_file.file_name()?.to_str()?;
+    let stem_length = source_file.file_stem()?.len();
+    let directory = source_file.parent()?;
+
+    // Long names are truncated below the length of their stem.
+    let min_length = (stem_length + 2).min(MAX_NAME_LENGTH - ".json".len());
+
+    // As `name` is a prefix of this, its truncations are covered too.
+    let base = format!("{name}.supplemental-metadata");
+
+    std::iter::once(name.len())
+        .chain((min_length..=base.len()).rev())
+        .filter(|&length| base.is_char_boundary(length))
+        .map(|length| directory.join(format!("{}.json", &base[..length])))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Reads a Takeout sidecar.
+pub fn read_sidecar(path: &Path) -> Result<Takeout> {
+    let json = fs::read_to_string(path)
+        .with_context(|| format!("Unable to read '{}'.", path.display()))?;
+    let sidecar: Sidecar = serde_json::from_str(&json).with_context(|| {
+        format!("Unable to parse Takeout sidecar '{}'.", path.display())
+    })?;
+
+    let time_stamp = sidecar
+        .photo_taken_time
+        .and_then(|t| t.timestamp.parse().ok())
+        .and_then(|seconds| chrono::DateTime::from_timestamp(seconds, 0))
+        // EXIF timestamps are local time, so are ours.
+        .map(|utc| utc.with_timezone(&Local).naive_local());
+
+    let gps = sidecar
+        .geo_data
+        .and_then(|g| g.coordinates())
+        .or_else(|| sidecar.geo_data_exif.and_then(|g| g.coordinates()));
+
+    Ok(Takeout { time_stamp, gps })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn parse_sidecar() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("IMG_1234.jpg.json");
+        fs::write(
+            &path,
+            r#"{
+                "title": "IMG_1234.jpg",
+                "photoTakenTime": {
+                    "timestamp": "1692109800",
+                    "formatted": "Aug 15, 2023, 2:30:00 PM UTC"
+                },
+                "geoData": { "latitude": 0.0, "longitude": 0.0 },
+                "geoDataExif": { "latitude": 52.52, "longitude": 13.405 }
+            }"#,
+        )
+        .unwrap();
+
+        let takeout = read_sidecar(&path).unwrap();
+        assert!(takeout.time_stamp.is_some());
+        assert_eq!(takeout.gps, Some((52.52, 13.405)));
+    }
+
+    #[test]
+    fn find_truncated_sidecar() {
+        let tmp = TempDir::new().unwrap();
+        let image = tmp.path().join("IMG_1234.jpg");
+        assert_eq!(find_sidecar(&image), None);
+
+        let truncated = tmp.path().join("IMG_1234.j.json");
+        fs::write(&truncated, "{}").unwrap();
+        assert_eq!(find_sidecar(&image), Some(truncated));
+
+        let supplemental =
+            tmp.path().join("IMG_1234.jpg.supplemental-metadata.json");
+        fs::write(&supplemental, "{}").unwrap();
+        assert_eq!(find_sidecar(&image), Some(supplemental));
+
+        let full = tmp.path().join("IMG_1234.jpg.json");
+        fs::write(&full, "{}").unwrap();
+        assert_eq!(find_sidecar(&image), Some(full));
+    }
+}
@@ -1,8 +1,10 @@
 //! Template parsing and expansion for destination paths.
 
-use anyhow::{Result, anyhow};
+use crate::error::Error;
+use anyhow::Result;
 use ariadne::{Color, Label, Report, ReportKind, Source};
-use std::collections::HashSet;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
 
 /// Known template variables.
 const KNOWN_VARIABLES: &[&str] = &[
@@ -16,12 +18,22 @@ const KNOWN_VARIABLES: &[&str] = &[
     // File.
     "filename",
     "extension",
+    "pair_role",
+    "live",
+    "burst",
+    "source_relpath",
     // EXIF.
     "camera_make",
     "camera_model",
     "lens",
     "iso",
     "focal_length",
+    // Location.
+    "place",
+    // XMP sidecar.
+    "rating",
+    "label",
+    "keywords",
 ];
 
 /// A segment of a parsed template.
@@ -40,6 +52,35 @@ pub struct Template {
     source: String,
 }
 
+/// Matches paths expanded from a template, see [`Template::matcher()`].
+#[derive(Debug)]
+pub struct PathMatcher {
+    regex: Regex,
+    /// Variable name of each capture group.
+    names: Vec<String>,
+}
+
+impl PathMatcher {
+    /// Returns the values of the variables in `path`, a path relative to
+    /// the destination, if it matches.
+    pub fn captures<'a>(
+        &self,
+        path: &'a str,
+    ) -> Option<HashMap<&str, &'a str>> {
+        let captures = self.regex.captures(path)?;
+        let mut values = HashMap::new();
+        for (name, value) in self.names.iter().zip(captures.iter().skip(1)) {
+            let value = value.map_or("", |m| m.as_str());
+            // A variable used twice must have the same value both times.
+            if *values.entry(name.as_str()).or_insert(value) != value {
+                return None;
+            }
+        }
+
+        Some(values)
+    }
+}
+
 /// Context providing values for template variables.
 #[derive(Debug, Default)]
 pub struct TemplateContext {
@@ -51,11 +92,23 @@ pub struct TemplateContext {
     pub second: String,
     pub filename: String,
     pub extension: String,
+    /// `raw` or `jpeg` for RAW+JPEG pairs, empty otherwise.
+    pub pair_role: String,
+    /// `live` for Live Photos and motion photos, empty otherwise.
+    pub live: String,
+    /// Name of the burst for its frames, empty otherwise.
+    pub burst: String,
+    /// Path of the file relative to the source, with its name.
+    pub source_relpath: String,
     pub camera_make: Option<String>,
     pub camera_model: Option<String>,
     pub lens: Option<String>,
     pub iso: Option<String>,
     pub focal_length: Option<String>,
+    pub place: Option<String>,
+    pub rating: Option<String>,
+    pub label: Option<String>,
+    pub keywords: Option<String>,
 }
 
 impl Template {
@@ -133,7 +186,10 @@ impl Template {
                                 format!("Missing '}}'{}", suggestion)
                             };
                             Self::report_errors(input, &[(start, j, msg)]);
-                            return Err(anyhow!("Failed to parse template."));
+                            return Err(Error::Template(
+                                "Failed to parse template.".to_string(),
+                            )
+                            .into());
                         } else {
                             var_name.push(ch);
                         }
@@ -168,7 +224,10 @@ impl Template {
         if !errors.is_empty() {
             // Show only the first error to avoid confusing cascading errors.
             Self::report_errors(input, &errors[..1]);
-            return Err(anyhow!("Failed to parse template."));
+            return Err(Error::Template(
+                "Failed to parse template.".to_string(),
+            )
+            .into());
         }
 
         Ok(Self {
@@ -198,10 +257,11 @@ impl Template {
             Self::report_errors(&self.source, &errors);
 
             let available = KNOWN_VARIABLES.join(", ");
-            return Err(anyhow!(
+            return Err(Error::Template(format!(
                 "Template contains unknown variables. Available: {}",
                 available
-            ));
+            ))
+            .into());
         }
 
         Ok(())
@@ -224,6 +284,10 @@ impl Template {
                         "second" => &ctx.second,
                         "filename" => &ctx.filename,
                         "extension" => &ctx.extension,
+                        "pair_role" => &ctx.pair_role,
+                        "live" => &ctx.live,
+                        "burst" => &ctx.burst,
+                        "source_relpath" => &ctx.source_relpath,
                         "camera_make" => {
                             ctx.camera_make.as_deref().unwrap_or("unknown")
                         }
@@ -235,6 +299,12 @@ impl Template {
                         "focal_length" => {
                             ctx.focal_length.as_deref().unwrap_or("unknown")
                         }
+                        "place" => ctx.place.as_deref().unwrap_or("unknown"),
+                        "rating" => ctx.rating.as_deref().unwrap_or("unknown"),
+                        "label" => ctx.label.as_deref().unwrap_or("unknown"),
+                        "keywords" => {
+                            ctx.keywords.as_deref().unwrap_or("unknown")
+                        }
                         _ => "unknown",
                     };
                     result.push_str(value);
@@ -245,6 +315,65 @@ impl Template {
         result
     }
 
+    /// Returns a matcher for paths this template expands to.
+    ///
+    /// A variable making up a whole path component may be empty, like an
+    /// unpaired `{pair_role}`, which removes the component.
+    pub fn matcher(&self) -> PathMatcher {
+        let mut pattern = String::from("^");
+        let mut names = Vec::new();
+        let mut skip_slash = false;
+
+        for (i, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Literal(s) => {
+                    let s = if skip_slash { &s[1..] } else { s.as_str() };
+                    pattern.push_str(&regex::escape(s));
+                    skip_slash = false;
+                }
+                Segment::Variable { name, .. } => {
+                    let starts_component = match i.checked_sub(1) {
+                        None => true,
+                        Some(j) => matches!(
+                            &self.segments[j],
+                            Segment::Literal(s) if s.ends_with('/')
+                        ),
+                    };
+                    let ends_component = matches!(
+                        self.segments.get(i + 1),
+                        Some(Segment::Literal(s)) if s.starts_with('/')
+                    );
+
+                    // Dates and times have fixed widths, so e.g.
+                    // `{year}{month}` can be split.
+                    let value = match name.as_str() {
+                        "year" => r"\d{4}",
+                        "month" | "day" | "hour" | "minute" | "second" => {
+                            r"\d{2}"
+                        }
+                        "source_relpath" => ".+",
+                        _ => "[^/]*",
+                    };
+                    if starts_component && ends_component {
+                        let value =
+                            if value == "[^/]*" { "[^/]+" } else { value };
+                        pattern.push_str(&format!("(?:({value})/)?"));
+                        skip_slash = true;
+                    } else {
+                        pattern.push_str(&format!("({value})"));
+                    }
+                    names.push(name.clone());
+                }
+            }
+        }
+        pattern.push('$');
+
+        PathMatcher {
+            regex: Regex::new(&pattern).expect("escaped template pattern"),
+            names,
+        }
+    }
+
     /// Report parse errors using ariadne.
     fn report_errors(source: &str, errors: &[(usize, usize, String)]) {
         for (start, end, msg) in errors {
@@ -303,6 +432,27 @@ mod tests {
         assert_eq!(t.expand(&ctx), "2023/08/IMG_1234.jpg");
     }
 
+    #[test]
+    fn match_expanded_paths() {
+        let matcher = Template::parse(
+            "{year}/{pair_role}/{year}{month}_{filename}.{extension}",
+        )
+        .unwrap()
+        .matcher();
+
+        let values = matcher.captures("2023/raw/202308_DSC_1.ARW").unwrap();
+        assert_eq!(values["pair_role"], "raw");
+        assert_eq!(values["filename"], "DSC_1");
+        assert_eq!(values["extension"], "ARW");
+
+        let values = matcher.captures("2023/202308_IMG.1.jpg").unwrap();
+        assert_eq!(values["pair_role"], "");
+        assert_eq!(values["filename"], "IMG.1");
+
+        assert!(matcher.captures("2023/202408_IMG_1.jpg").is_none());
+        assert!(matcher.captures("2023/08/IMG_1.jpg").is_none());
+    }
+
     #[test]
     fn validate_unknown_variable() {
         let t = Template::parse("{year}/{unknown}").unwrap();
@@ -3,14 +3,61 @@
 //! All tests use `tempfile::TempDir` which creates directories in the system
 //! temp directory, ensuring no artifacts are left in the source tree.
 
-use crate::{Template, TemplateContext, day_wrap, move_image, util::move_file};
-use chrono::NaiveTime;
+use crate::{
+    AppConfig, DateSource, JpegWithRaw, Template, TemplateContext, day_wrap,
+    device::Throttle,
+    error::{self, ErrorClass},
+    group::{self, Group, Item},
+    index::Index,
+    move_group,
+    quarantine::Quarantine,
+    reorganize,
+    report::Action,
+    stats,
+    util::{Outcome, move_file, temp_path},
+    verify,
+};
+use anyhow::Result;
+use chrono::{NaiveDateTime, NaiveTime};
 use clap::{Arg, ArgAction, ArgMatches, Command};
 use exif::DateTime;
 use indicatif::MultiProgress;
 use std::{fs, io::Write, path::Path, sync::Arc};
 use tempfile::TempDir;
 
+/// Moves a single file, as `main()` does for files without companions.
+#[allow(clippy::too_many_arguments)]
+fn move_image(
+    source_file: &Path,
+    dest_dir: &Path,
+    time_offset: &NaiveTime,
+    template: &Template,
+    make_lowercase: bool,
+    checksum: bool,
+    config: &AppConfig,
+    args: Arc<ArgMatches>,
+    multi: Arc<MultiProgress>,
+) -> Result<()> {
+    let item = Item::read(source_file, config)?;
+    move_group(
+        Group { items: vec![item] },
+        source_file.parent().unwrap(),
+        dest_dir,
+        time_offset,
+        template,
+        None,
+        make_lowercase,
+        checksum,
+        config,
+        None,
+        None,
+        args,
+        multi,
+        &mut Vec::new(),
+    )?;
+    Ok(())
+}
+
 /// Creates a minimal valid JPEG file with EXIF DateTimeOriginal tag.
 ///
 /// The datetime format is "YYYY:MM:DD HH:MM:SS".
@@ -167,6 +214,166 @@ fn create_jpeg_without_exif(path: &Path) {
     file.write_all(&jpeg).expect("Failed to write test JPEG");
 }
 
+/// IFD a `TestField` is stored in.
+#[derive(Clone, Copy, PartialEq)]
+enum TestIfd {
+    Primary,
+    Exif,
+    Gps,
+}
+
+/// A raw EXIF field for `create_test_jpeg_with_fields()`.
+struct TestField {
+    ifd: TestIfd,
+    tag: u16,
+    kind: u16,
+    count: u32,
+    data: Vec<u8>,
+}
+
+impl TestField {
+    fn ascii(ifd: TestIfd, tag: u16, value: &str) -> Self {
+        let mut data = value.as_bytes().to_vec();
+        data.push(0);
+        Self {
+            ifd,
+            tag,
+            kind: 2,
+            count: data.len() as u32,
+            data,
+        }
+    }
+
+    fn undefined(ifd: TestIfd, tag: u16, data: &[u8]) -> Self {
+        Self {
+            ifd,
+            tag,
+            kind: 7,
+            count: data.len() as u32,
+            data: data.to_vec(),
+        }
+    }
+
+    fn rationals(ifd: TestIfd, tag: u16, values: &[(u32, u32)]) -> Self {
+        Self {
+            ifd,
+            tag,
+            kind: 5,
+            count: values.len() as u32,
+            data: values
+                .iter()
+                .flat_map(|(n, d)| [n.to_le_bytes(), d.to_le_bytes()])
+                .flatten()
+                .collect(),
+        }
+    }
+}
+
+/// Creates a minimal JPEG with arbitrary EXIF fields.
+///
+/// Pointers to the EXIF and GPS IFDs are added to IFD0 as needed.
+fn create_test_jpeg_with_fields(path: &Path, fields: &[TestField]) {
+    const EXIF_POINTER: u16 = 0x8769;
+    const GPS_POINTER: u16 = 0x8825;
+
+    let ifd_fields = |ifd: TestIfd| {
+        let mut fields: Vec<_> =
+            fields.iter().filter(|f| f.ifd == ifd).collect();
+        fields.sort_by_key(|f| f.tag);
+        fields
+    };
+    let exif_fields = ifd_fields(TestIfd::Exif);
+    let gps_fields = ifd_fields(TestIfd::Gps);
+    let primary_fields = ifd_fields(TestIfd::Primary);
+
+    // IFD0 entries are the primary fields plus the sub-IFD pointers.
+    let primary_count = primary_fields.len()
+        + !exif_fields.is_empty() as usize
+        + !gps_fields.is_empty() as usize;
+    let ifd_size = |count: usize| 2 + 12 * count as u32 + 4;
+
+    let primary_offset = 8u32;
+    let exif_offset = primary_offset + ifd_size(primary_count);
+    let gps_offset = exif_offset
+        + if exif_fields.is_empty() {
+            0
+        } else {
+            ifd_size(exif_fields.len())
+        };
+    let mut data_offset = gps_offset
+        + if gps_fields.is_empty() {
+            0
+        } else {
+            ifd_size(gps_fields.len())
+        };
+
+    let mut tiff: Vec<u8> = Vec::new();
+    tiff.extend_from_slice(b"II");
+    tiff.extend_from_slice(&42u16.to_le_bytes());
+    tiff.extend_from_slice(&primary_offset.to_le_bytes());
+
+    let mut data_area: Vec<u8> = Vec::new();
+    let mut write_ifd =
+        |tiff: &mut Vec<u8>, fields: &[&TestField], pointers: &[(u16, u32)]| {
+            let mut entries: Vec<(u16, u16, u32, Vec<u8>)> = fields
+                .iter()
+                .map(|f| (f.tag, f.kind, f.count, f.data.clone()))
+                .collect();
+            entries.extend(pointers.iter().map(|(tag, offset)| {
+                (*tag, 4, 1, offset.to_le_bytes().to_vec())
+            }));
+            entries.sort_by_key(|e| e.0);
+
+            tiff.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+            for (tag, kind, count, data) in entries {
+                tiff.extend_from_slice(&tag.to_le_bytes());
+                tiff.extend_from_slice(&kind.to_le_bytes());
+                tiff.extend_from_slice(&count.to_le_bytes());
+                if data.len() <= 4 {
+                    let mut value = data.clone();
+                    value.resize(4, 0);
+                    tiff.extend_from_slice(&value);
+                } else {
+                    tiff.extend_from_slice(&data_offset.to_le_bytes());
+                    data_area.extend_from_slice(&data);
+                    if data.len() % 2 == 1 {
+                        data_area.push(0);
+                    }
+                    data_offset += data.len().next_multiple_of(2) as u32;
+                }
+            }
+            // Next IFD offset (0 = none).
+            tiff.extend_from_slice(&0u32.to_le_bytes());
+        };
+
+    let mut pointers = Vec::new();
+    if !exif_fields.is_empty() {
+        pointers.push((EXIF_POINTER, exif_offset));
+    }
+    if !gps_fields.is_empty() {
+        pointers.push((GPS_POINTER, gps_offset));
+    }
+    write_ifd(&mut tiff, &primary_fields, &pointers);
+    if !exif_fields.is_empty() {
+        write_ifd(&mut tiff, &exif_fields, &[]);
+    }
+    if !gps_fields.is_empty() {
+        write_ifd(&mut tiff, &gps_fields, &[]);
+    }
+    tiff.extend_from_slice(&data_area);
+
+    let mut app1: Vec<u8> = vec![0xFF, 0xE1];
+    app1.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+    app1.extend_from_slice(b"Exif\x00\x00");
+    app1.extend_from_slice(&tiff);
+
+    // Splice the APP1 segment in right after SOI.
+    create_jpeg_without_exif(path);
+    let mut jpeg = fs::read(path).unwrap();
+    jpeg.splice(2..2, app1);
+    fs::write(path, jpeg).expect("Failed to write test JPEG");
+}
+
 /// Builds test `ArgMatches` with specified flags.
 fn make_test_args(flags: &[&str]) -> Arc<ArgMatches> {
     let cmd = Command::new("test")
@@ -195,11 +402,23 @@ fn make_test_args(flags: &[&str]) -> Arc<ArgMatches> {
                 .long("make-lowercase")
                 .action(ArgAction::SetTrue),
         )
+        .arg(Arg::new("copy").long("copy").action(ArgAction::SetTrue))
         .arg(
             Arg::new("checksum")
                 .long("checksum")
                 .action(ArgAction::SetTrue),
-        );
+        )
+        .arg(
+            Arg::new("trust-size")
+                .long("trust-size")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("byte-compare")
+                .long("byte-compare")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(Arg::new("quarantine").long("quarantine"));
 
     let args: Vec<_> = std::iter::once(&"test").chain(flags.iter()).collect();
 
@@ -226,6 +445,118 @@ fn move_to_new_location() {
     assert_eq!(fs::read(&dest).unwrap(), b"test content");
 }
 
+#[test]
+fn copy_to_new_location() {
+    let tmp = TempDir::new().unwrap();
+    let source = tmp.path().join("source.jpg");
+    let dest = tmp.path().join("dest.jpg");
+
+    fs::write(&source, b"test content").unwrap();
+    let args = make_test_args(&["--copy"]);
+
+    move_file(&source, &dest, false, args, &MultiProgress::new()).unwrap();
+
+    assert!(source.exists(), "Source should be kept");
+    assert_eq!(fs::read(&dest).unwrap(), b"test content");
+}
+
+#[test]
+fn verified_copy_leaves_no_temp_file() {
+    let tmp = TempDir::new().unwrap();
+    let source = tmp.path().join("source.jpg");
+    let dest = tmp.path().join("dest.jpg");
+
+    fs::write(&source, b"test content").unwrap();
+    let args = make_test_args(&["--copy", "--checksum"]);
+
+    move_file(&source, &dest, true, args, &MultiProgress::new()).unwrap();
+
+    assert_eq!(fs::read(&dest).unwrap(), b"test content");
+    assert!(!temp_path(&dest).exists(), "Temp file should be renamed");
+    assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 2);
+}
+
+#[test]
+fn copy_preserves_times_and_permissions() {
+    let tmp = TempDir::new().unwrap();
+    let source = tmp.path().join("source.jpg");
+    let dest = tmp.path().join("dest.jpg");
+
+    fs::write(&source, b"test content").unwrap();
+    let modified = std::time::SystemTime::UNIX_EPOCH
+        + std::time::Duration::from_secs(1_600_000_000);
+    fs::File::options()
+        .write(true)
+        .open(&source)
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+    // Where the file system supports user extended attributes.
+    #[cfg(target_os = "linux")]
+    let xattr = rustix::fs::setxattr(
+        &source,
+        "user.exifmv.test",
+        b"value",
+        rustix::fs::XattrFlags::empty(),
+    )
+    .is_ok();
+    let mut permissions = source.metadata().unwrap().permissions();
+    permissions.set_readonly(true);
+    fs::set_permissions(&source, permissions).unwrap();
+
+    let args = make_test_args(&["--copy"]);
+    move_file(&source, &dest, false, args, &MultiProgress::new()).unwrap();
+
+    let metadata = dest.metadata().unwrap();
+    assert_eq!(metadata.modified().unwrap(), modified);
+    assert!(metadata.permissions().readonly());
+    #[cfg(target_os = "linux")]
+    if xattr {
+        let mut value = [0u8; 16];
+        let size =
+            rustix::fs::getxattr(&dest, "user.exifmv.test", &mut value[..])
+                .unwrap();
+        assert_eq!(&value[..size], b"value");
+    }
+}
+
+#[test]
+fn set_mtime_to_capture_time() {
+    let tmp = TempDir::new().unwrap();
+    let source_file = tmp.path().join("IMG_1234.jpg");
+    let dest_dir = tmp.path().join("dest");
+    create_test_jpeg(&source_file, "2023:08:15 14:30:00");
+
+    let config = AppConfig {
+        set_mtime: Some(true),
+        ..Default::default()
+    };
+    move_image(
+        &source_file,
+        &dest_dir,
+        &NaiveTime::from_hms_opt(0, 0, 0).unwrap(),
+        &Template::parse("{filename}.{extension}").unwrap(),
+        false,
+        false,
+        &config,
+        make_test_args(&[]),
+        Arc::new(MultiProgress::new()),
+    )
+    .unwrap();
+
+    let modified: chrono::DateTime<chrono::Local> = dest_dir
+        .join("IMG_1234.jpg")
+        .metadata()
+        .unwrap()
+        .modified()
+        .unwrap()
+        .into();
+    assert_eq!(
+        modified.naive_local(),
+        NaiveDateTime::parse_from_str("2023-08-15 14:30:00", "%F %T").unwrap()
+    );
+}
+
 #[test]
 fn skip_when_source_equals_dest() {
     let tmp = TempDir::new().unwrap();
@@ -381,6 +712,7 @@ fn move_image_creates_date_hierarchy() {
         &template,
         false,
         false,
+        &AppConfig::default(),
         args,
         Arc::new(MultiProgress::new()),
     )
@@ -418,6 +750,7 @@ fn move_image_missing_exif_fails() {
         &template,
         false,
         false,
+        &AppConfig::default(),
         args,
         Arc::new(MultiProgress::new()),
     );
@@ -426,6 +759,94 @@ fn move_image_missing_exif_fails() {
     assert!(source_file.exists(), "Source should be preserved on error");
 }
 
+#[test]
+fn errors_are_classified() {
+    let tmp = TempDir::new().unwrap();
+    let config = AppConfig::default();
+
+    let no_exif = tmp.path().join("no_exif.jpg");
+    create_jpeg_without_exif(&no_exif);
+    let error = Item::read(&no_exif, &config).unwrap().metadata.unwrap_err();
+    assert_eq!(error::class(&error), ErrorClass::Metadata);
+
+    let garbage = tmp.path().join("garbage.jpg");
+    fs::write(&garbage, b"not an image").unwrap();
+    let error = Item::read(&garbage, &config).unwrap().metadata.unwrap_err();
+    assert_eq!(error::class(&error), ErrorClass::Unreadable);
+    assert_eq!(error::exit_code(&error), 4);
+
+    let error = Template::parse("{yaer}").unwrap().validate().unwrap_err();
+    assert_eq!(error::class(&error), ErrorClass::Template);
+}
+
+#[test]
+fn failed_run_can_be_resumed() {
+    let tmp = TempDir::new().unwrap();
+    let source_dir = tmp.path().join("source");
+    let dest_dir = tmp.path().join("dest");
+    fs::create_dir_all(&source_dir).unwrap();
+    create_test_jpeg(&source_dir.join("good.jpg"), "2023:08:15 14:30:00");
+    fs::write(source_dir.join("broken.jpg"), b"not an image").unwrap();
+    let run = |resume: bool| {
+        let mut args = vec![
+            "exifmv".into(),
+            "--config".into(),
+            tmp.path().join("config.toml").into_os_string(),
+            "--fail-on".into(),
+            "unreadable".into(),
+            source_dir.clone().into_os_string(),
+            dest_dir.clone().into_os_string(),
+        ];
+        if resume {
+            args.push("--resume".into());
+        }
+        crate::run(args)
+    };
+    let journal = dest_dir.join(".exifmv/run");
+
+    let error = run(false).unwrap_err();
+    assert_eq!(error::exit_code(&error), 4);
+    assert!(format!("{:?}", error).contains("--resume"));
+    assert!(journal.exists(), "The state of a failed run should be kept");
+    assert!(dest_dir.join("2023/08/15/good.jpg").exists());
+
+    create_test_jpeg(&source_dir.join("broken.jpg"), "2023:08:16 10:00:00");
+    run(true).unwrap();
+    assert!(!journal.exists(), "A completed run should remove its state");
+    assert!(dest_dir.join("2023/08/16/broken.jpg").exists());
+    assert!(!source_dir.join("broken.jpg").exists());
+}
+
+#[test]
+fn files_without_date_fail_runs_only_when_asked() {
+    let tmp = TempDir::new().unwrap();
+    let source_dir = tmp.path().join("source");
+    let dest_dir = tmp.path().join("dest");
+    fs::create_dir_all(&source_dir).unwrap();
+    create_jpeg_without_exif(&source_dir.join("no_exif.jpg"));
+    let run = |flags: &[&str]| {
+        let mut args = vec![
+            "exifmv".into(),
+            "--config".into(),
+            tmp.path().join("config.toml").into_os_string(),
+        ];
+        args.extend(flags.iter().map(Into::into));
+        args.push(source_dir.clone().into_os_string());
+        args.push(dest_dir.clone().into_os_string());
+        crate::run(args)
+    };
+
+    // As before `--fail-on`, a file without a date is only logged.
+    assert!(run(&[]).is_ok());
+    assert!(source_dir.join("no_exif.jpg").exists());
+
+    let error = run(&["--halt-on-errors"]).unwrap_err();
+    assert_eq!(error::exit_code(&error), 3);
+    let error = run(&["--fail-on", "metadata"]).unwrap_err();
+    assert_eq!(error::exit_code(&error), 3);
+    assert!(run(&["--halt-on-errors", "--fail-on", "none"]).is_ok());
+}
+
 #[test]
 fn move_image_respects_custom_template() {
     let tmp = TempDir::new().unwrap();
@@ -449,6 +870,7 @@ fn move_image_respects_custom_template() {
         &template,
         false,
         false,
+        &AppConfig::default(),
         args,
         Arc::new(MultiProgress::new()),
     )
@@ -484,6 +906,7 @@ fn move_image_lowercase_option() {
         &template,
         true,
         false,
+        &AppConfig::default(),
         args,
         Arc::new(MultiProgress::new()),
     )
@@ -518,6 +941,7 @@ fn move_image_day_wrap_shifts_date() {
         &template,
         false,
         false,
+        &AppConfig::default(),
         args,
         Arc::new(MultiProgress::new()),
     )
@@ -557,6 +981,7 @@ fn xmp_sidecar_moves_with_image() {
         &template,
         false,
         false,
+        &AppConfig::default(),
         args,
         Arc::new(MultiProgress::new()),
     )
@@ -595,6 +1020,7 @@ fn xmp_uppercase_moves_with_image() {
         &template,
         false,
         false,
+        &AppConfig::default(),
         args,
         Arc::new(MultiProgress::new()),
     )
@@ -628,6 +1054,7 @@ fn xmp_lowercase_conversion() {
         &template,
         true,
         false,
+        &AppConfig::default(),
         args,
         Arc::new(MultiProgress::new()),
     )
@@ -641,117 +1068,831 @@ fn xmp_lowercase_conversion() {
 }
 
 // =============================================================================
-// day_wrap() Unit Tests
+// Date Source Tests
 // =============================================================================
 
 #[test]
-fn day_wrap_no_wrap_early_time() {
-    let ts = DateTime {
-        year: 2023,
-        month: 8,
-        day: 15,
-        hour: 10,
-        minute: 30,
-        second: 0,
-        nanosecond: None,
-        offset: None,
-    };
-    let offset = NaiveTime::from_hms_opt(4, 0, 0).unwrap();
-    assert_eq!(day_wrap(&ts, &offset), 0);
-}
+fn move_image_falls_back_to_filename_date() {
+    let tmp = TempDir::new().unwrap();
+    let source_dir = tmp.path().join("source");
+    let dest_dir = tmp.path().join("dest");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::create_dir_all(&dest_dir).unwrap();
 
-#[test]
-fn day_wrap_wraps_late_night() {
-    let ts = DateTime {
-        year: 2023,
-        month: 8,
-        day: 15,
-        hour: 22,
-        minute: 0,
-        second: 0,
-        nanosecond: None,
-        offset: None,
-    };
-    let offset = NaiveTime::from_hms_opt(4, 0, 0).unwrap();
-    // 22 + 4 = 26 > 23, so wraps.
-    assert_eq!(day_wrap(&ts, &offset), 1);
+    let source_file = source_dir.join("IMG-20230815-WA0003.jpg");
+    create_jpeg_without_exif(&source_file);
+
+    let template =
+        Template::parse("{year}/{month}/{day}/{filename}.{extension}").unwrap();
+    let time_offset = NaiveTime::from_hms_opt(0, 0, 0).unwrap();
+
+    move_image(
+        &source_file,
+        &dest_dir,
+        &time_offset,
+        &template,
+        false,
+        false,
+        &AppConfig::default(),
+        make_test_args(&[]),
+        Arc::new(MultiProgress::new()),
+    )
+    .unwrap();
+
+    assert!(dest_dir.join("2023/08/15/IMG-20230815-WA0003.jpg").exists());
 }
 
 #[test]
-fn day_wrap_minute_overflow_causes_wrap() {
-    let ts = DateTime {
-        year: 2023,
-        month: 8,
-        day: 15,
-        hour: 23,
-        minute: 30,
-        second: 0,
-        nanosecond: None,
-        offset: None,
-    };
-    let offset = NaiveTime::from_hms_opt(0, 31, 0).unwrap();
-    // 23:30 + 0:31 = 24:01, minute overflow adds 1 to hour check.
-    assert_eq!(day_wrap(&ts, &offset), 1);
-}
+fn move_image_prefers_filename_date_when_configured() {
+    let tmp = TempDir::new().unwrap();
+    let source_dir = tmp.path().join("source");
+    let dest_dir = tmp.path().join("dest");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::create_dir_all(&dest_dir).unwrap();
 
-// =============================================================================
-// Config Tests
-// =============================================================================
+    // Camera clock was off: EXIF says June, the file name says August.
+    let source_file = source_dir.join("PXL_20230815_143000123.jpg");
+    create_test_jpeg(&source_file, "2023:06:01 09:00:00");
 
-#[test]
-fn config_load_missing_returns_default() {
-    use crate::config::Config;
+    let template =
+        Template::parse("{year}/{month}/{day}/{filename}.{extension}").unwrap();
+    let time_offset = NaiveTime::from_hms_opt(0, 0, 0).unwrap();
+    let config = AppConfig {
+        date_sources: Some(vec![DateSource::Filename, DateSource::Exif]),
+        ..Default::default()
+    };
 
-    let tmp = TempDir::new().unwrap();
-    let missing_path = tmp.path().join("nonexistent.toml");
+    move_image(
+        &source_file,
+        &dest_dir,
+        &time_offset,
+        &template,
+        false,
+        false,
+        &config,
+        make_test_args(&[]),
+        Arc::new(MultiProgress::new()),
+    )
+    .unwrap();
 
-    // Loading from a non-existent path should return default (confy creates
-    // it).
-    let config = Config::load(Some(&missing_path)).unwrap();
-    assert!(config.format.is_none());
-    assert!(config.verbose.is_none());
+    assert!(
+        dest_dir
+            .join("2023/08/15/PXL_20230815_143000123.jpg")
+            .exists()
+    );
 }
 
 #[test]
-fn config_load_custom_path() {
-    use crate::config::Config;
-
+fn takeout_sidecar_provides_date_and_moves_with_image() {
     let tmp = TempDir::new().unwrap();
-    let config_path = tmp.path().join("custom.toml");
+    let source_dir = tmp.path().join("source");
+    let dest_dir = tmp.path().join("dest");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::create_dir_all(&dest_dir).unwrap();
 
+    let source_file = source_dir.join("IMG_1234.jpg");
+    let source_json = source_dir.join("IMG_1234.j.json");
+    create_jpeg_without_exif(&source_file);
     fs::write(
-        &config_path,
-        r#"
-format = "{year}-{month}/{filename}.{extension}"
-verbose = true
-make-lowercase = true
-"#,
+        &source_json,
+        r#"{ "photoTakenTime": { "timestamp": "1692100800" } }"#,
     )
     .unwrap();
 
-    let config = Config::load(Some(&config_path)).unwrap();
-    assert_eq!(
-        config.format.as_deref(),
-        Some("{year}-{month}/{filename}.{extension}")
-    );
-    assert_eq!(config.verbose, Some(true));
-    assert_eq!(config.make_lowercase, Some(true));
-}
+    let template =
+        Template::parse("{year}/{month}/{day}/{filename}.{extension}").unwrap();
+    let time_offset = NaiveTime::from_hms_opt(0, 0, 0).unwrap();
 
-#[test]
-fn config_format_returns_default_when_unset() {
-    use crate::config::{Config, DEFAULT_FORMAT};
+    move_image(
+        &source_file,
+        &dest_dir,
+        &time_offset,
+        &template,
+        false,
+        false,
+        &AppConfig::default(),
+        make_test_args(&[]),
+        Arc::new(MultiProgress::new()),
+    )
+    .unwrap();
 
-    let config = Config::default();
-    assert_eq!(config.format(), DEFAULT_FORMAT);
+    // Takeout timestamps are UTC and converted to local time.
+    let day = chrono::DateTime::from_timestamp(1692100800, 0)
+        .unwrap()
+        .with_timezone(&chrono::Local)
+        .format("%Y/%m/%d")
+        .to_string();
+    let expected = dest_dir.join(&day).join("IMG_1234.jpg");
+    assert!(
+        expected.exists(),
+        "File should be at {}",
+        expected.display()
+    );
+    assert!(
+        dest_dir.join(&day).join("IMG_1234.jpg.json").exists(),
+        "Takeout sidecar should follow image under its full name"
+    );
+    assert!(!source_json.exists(), "Source sidecar should be moved");
 }
 
 // =============================================================================
-// Template Tests (additional coverage)
+// Place Tests
 // =============================================================================
 
+/// Creates a JPEG with DateTimeOriginal and GPS coordinates given as whole
+/// degrees.
+fn create_test_jpeg_with_gps(path: &Path, datetime: &str, lat: i32, lon: i32) {
+    let dms = |degrees: i32| [(degrees.unsigned_abs(), 1), (0, 1), (0, 1)];
+    create_test_jpeg_with_fields(
+        path,
+        &[
+            TestField::ascii(TestIfd::Exif, 0x9003, datetime),
+            TestField::ascii(
+                TestIfd::Gps,
+                0x0001,
+                if lat < 0 { "S" } else { "N" },
+            ),
+            TestField::rationals(TestIfd::Gps, 0x0002, &dms(lat)),
+            TestField::ascii(
+                TestIfd::Gps,
+                0x0003,
+                if lon < 0 { "W" } else { "E" },
+            ),
+            TestField::rationals(TestIfd::Gps, 0x0004, &dms(lon)),
+        ],
+    );
+}
+
 #[test]
-fn template_expand_with_all_fields() {
+fn move_image_resolves_place() {
+    let tmp = TempDir::new().unwrap();
+    let source_dir = tmp.path().join("source");
+    let dest_dir = tmp.path().join("dest");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::create_dir_all(&dest_dir).unwrap();
+
+    let home = source_dir.join("home.jpg");
+    let away = source_dir.join("away.jpg");
+    let no_gps = source_dir.join("no_gps.jpg");
+    create_test_jpeg_with_gps(&home, "2023:08:15 14:30:00", 52, -13);
+    create_test_jpeg_with_gps(&away, "2023:08:15 14:30:00", -33, 151);
+    create_test_jpeg(&no_gps, "2023:08:15 14:30:00");
+
+    let config: AppConfig = toml::from_str(
+        r#"
+place-fallback = "elsewhere"
+
+[[place]]
+name = "home"
+center = [52.0, -13.0]
+radius = 500.0
+"#,
+    )
+    .unwrap();
+
+    let template = Template::parse("{place}/{filename}.{extension}").unwrap();
+    let time_offset = NaiveTime::from_hms_opt(0, 0, 0).unwrap();
+
+    for file in [&home, &away, &no_gps] {
+        move_image(
+            file,
+            &dest_dir,
+            &time_offset,
+            &template,
+            false,
+            false,
+            &config,
+            make_test_args(&[]),
+            Arc::new(MultiProgress::new()),
+        )
+        .unwrap();
+    }
+
+    assert!(dest_dir.join("home/home.jpg").exists());
+    assert!(dest_dir.join("elsewhere/away.jpg").exists());
+    assert!(dest_dir.join("elsewhere/no_gps.jpg").exists());
+}
+
+#[test]
+fn xmp_sidecar_provides_date_and_variables() {
+    let tmp = TempDir::new().unwrap();
+    let source_dir = tmp.path().join("source");
+    let dest_dir = tmp.path().join("dest");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::create_dir_all(&dest_dir).unwrap();
+
+    // EXIF date is wrong; it was corrected in the editor.
+    let source_file = source_dir.join("DSC_0001.jpg");
+    let source_xmp = source_dir.join("DSC_0001.jpg.xmp");
+    create_test_jpeg(&source_file, "2023:06:01 09:00:00");
+    fs::write(
+        &source_xmp,
+        r#"<x:xmpmeta><rdf:RDF><rdf:Description
+   exif:DateTimeOriginal="2023-08-15T14:30:00"
+   xmp:Rating="5"
+   xmp:Label="Green">
+   <dc:subject><rdf:Bag>
+    <rdf:li>New York</rdf:li>
+    <rdf:li>street</rdf:li>
+   </rdf:Bag></dc:subject>
+</rdf:Description></rdf:RDF></x:xmpmeta>"#,
+    )
+    .unwrap();
+
+    let template = Template::parse(
+        "{rating}/{label}/{keywords}/{year}-{month}-{day}/{filename}.{extension}",
+    )
+    .unwrap();
+    let time_offset = NaiveTime::from_hms_opt(0, 0, 0).unwrap();
+
+    move_image(
+        &source_file,
+        &dest_dir,
+        &time_offset,
+        &template,
+        false,
+        false,
+        &AppConfig::default(),
+        make_test_args(&[]),
+        Arc::new(MultiProgress::new()),
+    )
+    .unwrap();
+
+    let expected = dest_dir.join("5/Green/New-York,street/2023-08-15");
+    assert!(
+        expected.join("DSC_0001.jpg").exists(),
+        "File should be in {}",
+        expected.display()
+    );
+    assert!(expected.join("DSC_0001.jpg.xmp").exists());
+}
+
+#[test]
+fn stem_sidecars_move_with_image() {
+    let tmp = TempDir::new().unwrap();
+    let source_dir = tmp.path().join("source");
+    let dest_dir = tmp.path().join("dest");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::create_dir_all(&dest_dir).unwrap();
+
+    let source_file = source_dir.join("IMG_0001.JPG");
+    create_test_jpeg(&source_file, "2023:06:15 09:00:00");
+    fs::write(source_dir.join("IMG_0001.AAE"), b"<plist/>").unwrap();
+    fs::write(source_dir.join("IMG_0001.JPG.pp3"), b"[Version]").unwrap();
+
+    let template = Template::parse("{year}/{filename}.{extension}").unwrap();
+    let time_offset = NaiveTime::from_hms_opt(0, 0, 0).unwrap();
+
+    move_image(
+        &source_file,
+        &dest_dir,
+        &time_offset,
+        &template,
+        true,
+        false,
+        &AppConfig::default(),
+        make_test_args(&[]),
+        Arc::new(MultiProgress::new()),
+    )
+    .unwrap();
+
+    assert!(dest_dir.join("2023/img_0001.jpg").exists());
+    assert!(dest_dir.join("2023/img_0001.aae").exists());
+    assert!(dest_dir.join("2023/img_0001.jpg.pp3").exists());
+    assert!(!source_dir.join("IMG_0001.AAE").exists());
+}
+
+#[test]
+fn sidecars_follow_conflict_suffix() {
+    let tmp = TempDir::new().unwrap();
+    let source_dir = tmp.path().join("source");
+    let dest_dir = tmp.path().join("dest");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::create_dir_all(dest_dir.join("2023")).unwrap();
+
+    let source_file = source_dir.join("photo.jpg");
+    create_test_jpeg(&source_file, "2023:06:15 09:00:00");
+    fs::write(source_dir.join("photo.xmp"), b"<xmp/>").unwrap();
+    // A different photo with the same name is already in the library.
+    fs::write(dest_dir.join("2023/photo.jpg"), b"another photo").unwrap();
+
+    let template = Template::parse("{year}/{filename}.{extension}").unwrap();
+    let time_offset = NaiveTime::from_hms_opt(0, 0, 0).unwrap();
+    let config = AppConfig {
+        rename_conflicts: Some(true),
+        ..Default::default()
+    };
+
+    move_image(
+        &source_file,
+        &dest_dir,
+        &time_offset,
+        &template,
+        false,
+        false,
+        &config,
+        make_test_args(&[]),
+        Arc::new(MultiProgress::new()),
+    )
+    .unwrap();
+
+    assert_eq!(
+        fs::read(dest_dir.join("2023/photo.jpg")).unwrap(),
+        b"another photo"
+    );
+    assert!(dest_dir.join("2023/photo-1.jpg").exists());
+    assert!(dest_dir.join("2023/photo-1.xmp").exists());
+    assert!(!dest_dir.join("2023/photo.xmp").exists());
+}
+
+#[test]
+fn dot_labels_stay_inside_destination() {
+    let tmp = TempDir::new().unwrap();
+    let source_dir = tmp.path().join("source");
+    let dest_dir = tmp.path().join("dest");
+    fs::create_dir_all(&source_dir).unwrap();
+
+    let source_file = source_dir.join("photo.jpg");
+    create_test_jpeg(&source_file, "2023:06:15 09:00:00");
+    fs::write(
+        source_dir.join("photo.jpg.xmp"),
+        br#"<rdf:Description xmp:Label=".." xmp:Rating="1"/>"#,
+    )
+    .unwrap();
+
+    let template = Template::parse("{label}/{filename}.{extension}").unwrap();
+    move_image(
+        &source_file,
+        &dest_dir,
+        &NaiveTime::from_hms_opt(0, 0, 0).unwrap(),
+        &template,
+        false,
+        false,
+        &AppConfig::default(),
+        make_test_args(&[]),
+        Arc::new(MultiProgress::new()),
+    )
+    .unwrap();
+
+    assert!(dest_dir.join("unknown/photo.jpg").exists());
+    assert!(!tmp.path().join("photo.jpg").exists());
+}
+
+#[test]
+fn sidecars_stay_on_conflict() {
+    let tmp = TempDir::new().unwrap();
+    let source_dir = tmp.path().join("source");
+    let dest_dir = tmp.path().join("dest");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::create_dir_all(dest_dir.join("2023")).unwrap();
+
+    let source_file = source_dir.join("photo.jpg");
+    let source_xmp = source_dir.join("photo.jpg.xmp");
+    create_test_jpeg(&source_file, "2023:06:15 09:00:00");
+    fs::write(&source_xmp, b"<xmp/>").unwrap();
+    fs::write(dest_dir.join("2023/photo.jpg"), b"another photo").unwrap();
+
+    let template = Template::parse("{year}/{filename}.{extension}").unwrap();
+    let time_offset = NaiveTime::from_hms_opt(0, 0, 0).unwrap();
+
+    move_image(
+        &source_file,
+        &dest_dir,
+        &time_offset,
+        &template,
+        false,
+        false,
+        &AppConfig::default(),
+        make_test_args(&[]),
+        Arc::new(MultiProgress::new()),
+    )
+    .unwrap();
+
+    assert!(source_file.exists(), "Conflicting source should stay");
+    assert!(source_xmp.exists(), "Sidecar should stay with its primary");
+}
+
+/// Reads, groups and moves `files`, as `main()` does.
+fn move_images(
+    files: &[&Path],
+    dest_dir: &Path,
+    template: &Template,
+    config: &AppConfig,
+    args: Arc<ArgMatches>,
+) {
+    let items = files
+        .iter()
+        .map(|file| Item::read(file, config).unwrap())
+        .collect();
+
+    for group in group::group(items) {
+        move_group(
+            group,
+            files[0].parent().unwrap(),
+            dest_dir,
+            &NaiveTime::from_hms_opt(0, 0, 0).unwrap(),
+            template,
+            None,
+            false,
+            false,
+            config,
+            None,
+            None,
+            args.clone(),
+            Arc::new(MultiProgress::new()),
+            &mut Vec::new(),
+        )
+        .unwrap();
+    }
+}
+
+#[test]
+fn raw_jpeg_pair_stays_together() {
+    let tmp = TempDir::new().unwrap();
+    let source_dir = tmp.path().join("source");
+    let dest_dir = tmp.path().join("dest");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::create_dir_all(dest_dir.join("2023")).unwrap();
+
+    // The RAW has no readable EXIF and follows its JPEG.
+    let raw = source_dir.join("DSC_0001.NEF");
+    let jpeg = source_dir.join("DSC_0001.JPG");
+    fs::write(&raw, b"raw data").unwrap();
+    create_test_jpeg(&jpeg, "2023:06:15 09:00:00");
+    fs::write(source_dir.join("DSC_0001.xmp"), b"<xmp/>").unwrap();
+    fs::write(dest_dir.join("2023/DSC_0001.NEF"), b"another raw").unwrap();
+
+    let template = Template::parse("{year}/{filename}.{extension}").unwrap();
+    let config = AppConfig {
+        rename_conflicts: Some(true),
+        ..Default::default()
+    };
+
+    move_images(
+        &[&jpeg, &raw],
+        &dest_dir,
+        &template,
+        &config,
+        make_test_args(&[]),
+    );
+
+    // The JPEG takes the RAW's conflict suffix although its own name is free.
+    assert!(dest_dir.join("2023/DSC_0001-1.NEF").exists());
+    assert!(dest_dir.join("2023/DSC_0001-1.JPG").exists());
+    assert!(dest_dir.join("2023/DSC_0001-1.xmp").exists());
+    assert!(!dest_dir.join("2023/DSC_0001.JPG").exists());
+}
+
+#[test]
+fn pair_role_routes_jpegs() {
+    let tmp = TempDir::new().unwrap();
+    let source_dir = tmp.path().join("source");
+    let dest_dir = tmp.path().join("dest");
+    fs::create_dir_all(&source_dir).unwrap();
+
+    let raw = source_dir.join("DSC_0001.NEF");
+    let jpeg = source_dir.join("DSC_0001.JPG");
+    let single = source_dir.join("DSC_0002.JPG");
+    fs::write(&raw, b"raw data").unwrap();
+    create_test_jpeg(&jpeg, "2023:06:15 09:00:00");
+    create_test_jpeg(&single, "2023:06:15 09:00:05");
+
+    let template =
+        Template::parse("{year}/{pair_role}/{filename}.{extension}").unwrap();
+
+    move_images(
+        &[&raw, &jpeg, &single],
+        &dest_dir,
+        &template,
+        &AppConfig::default(),
+        make_test_args(&[]),
+    );
+
+    assert!(dest_dir.join("2023/raw/DSC_0001.NEF").exists());
+    assert!(dest_dir.join("2023/jpeg/DSC_0001.JPG").exists());
+    assert!(dest_dir.join("2023/DSC_0002.JPG").exists());
+}
+
+#[test]
+fn jpeg_with_raw_remove() {
+    let tmp = TempDir::new().unwrap();
+    let source_dir = tmp.path().join("source");
+    let dest_dir = tmp.path().join("dest");
+    fs::create_dir_all(&source_dir).unwrap();
+
+    let raw = source_dir.join("DSC_0001.NEF");
+    let jpeg = source_dir.join("DSC_0001.JPG");
+    let jpeg_xmp = source_dir.join("DSC_0001.JPG.xmp");
+    fs::write(&raw, b"raw data").unwrap();
+    create_test_jpeg(&jpeg, "2023:06:15 09:00:00");
+    fs::write(&jpeg_xmp, b"<xmp/>").unwrap();
+
+    let template = Template::parse("{year}/{filename}.{extension}").unwrap();
+    let config = AppConfig {
+        jpeg_with_raw: Some(JpegWithRaw::Remove),
+        ..Default::default()
+    };
+
+    // A dry run keeps everything.
+    move_images(
+        &[&raw, &jpeg],
+        &dest_dir,
+        &template,
+        &config,
+        make_test_args(&["--dry-run"]),
+    );
+    assert!(jpeg.exists());
+    assert!(jpeg_xmp.exists());
+
+    move_images(
+        &[&raw, &jpeg],
+        &dest_dir,
+        &template,
+        &config,
+        make_test_args(&[]),
+    );
+
+    assert!(dest_dir.join("2023/DSC_0001.NEF").exists());
+    assert!(!dest_dir.join("2023/DSC_0001.JPG").exists());
+    assert!(!jpeg.exists());
+    assert!(!jpeg_xmp.exists());
+}
+
+/// Returns an Apple MakerNote holding a Live Photo content identifier.
+fn apple_maker_note(content_identifier: &str) -> Vec<u8> {
+    let mut value = content_identifier.as_bytes().to_vec();
+    value.push(0);
+
+    let mut note = b"Apple iOS\0\0\x01MM".to_vec();
+    note.extend_from_slice(&1u16.to_be_bytes());
+    note.extend_from_slice(&0x0011u16.to_be_bytes());
+    note.extend_from_slice(&2u16.to_be_bytes());
+    note.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    // Value offset, right after the IFD and its next-IFD offset.
+    note.extend_from_slice(&(16u32 + 12 + 4).to_be_bytes());
+    note.extend_from_slice(&[0; 4]);
+    note.extend_from_slice(&value);
+    note
+}
+
+/// Creates a minimal QuickTime movie with Apple creation date and content
+/// identifier keys.
+fn create_test_mov(path: &Path, creation_date: &str, content_identifier: &str) {
+    fn atom(kind: &[u8], contents: &[u8]) -> Vec<u8> {
+        let mut atom = ((contents.len() + 8) as u32).to_be_bytes().to_vec();
+        atom.extend_from_slice(kind);
+        atom.extend_from_slice(contents);
+        atom
+    }
+    let item = |index: u32, value: &str| {
+        let mut data = vec![0, 0, 0, 1, 0, 0, 0, 0];
+        data.extend_from_slice(value.as_bytes());
+        atom(&index.to_be_bytes(), &atom(b"data", &data))
+    };
+
+    let mut keys = vec![0, 0, 0, 0, 0, 0, 0, 2];
+    keys.extend(atom(b"mdta", b"com.apple.quicktime.creationdate"));
+    keys.extend(atom(b"mdta", b"com.apple.quicktime.content.identifier"));
+    let mut ilst = item(1, creation_date);
+    ilst.extend(item(2, content_identifier));
+
+    let mut meta = atom(b"hdlr", &[0; 24]);
+    meta.extend(atom(b"keys", &keys));
+    meta.extend(atom(b"ilst", &ilst));
+
+    let mut mov = atom(b"ftyp", b"qt  \0\0\0\0qt  ");
+    mov.extend(atom(b"mdat", b"video"));
+    mov.extend(atom(b"moov", &atom(b"meta", &meta)));
+    fs::write(path, mov).unwrap();
+}
+
+#[test]
+fn live_photo_follows_still() {
+    let tmp = TempDir::new().unwrap();
+    let source_dir = tmp.path().join("source");
+    let dest_dir = tmp.path().join("dest");
+    fs::create_dir_all(&source_dir).unwrap();
+
+    // The edited still and the movie are linked by content identifier only.
+    let still = source_dir.join("IMG_E0001.JPG");
+    let movie = source_dir.join("IMG_0001.MOV");
+    let single = source_dir.join("IMG_0002.MOV");
+    create_test_jpeg_with_fields(
+        &still,
+        &[
+            TestField::ascii(TestIfd::Exif, 0x9003, "2023:08:15 23:59:59"),
+            TestField::undefined(
+                TestIfd::Exif,
+                0x927c,
+                &apple_maker_note("4F2B6C1E"),
+            ),
+        ],
+    );
+    // The movie's own date falls on the next day.
+    create_test_mov(&movie, "2023-08-16T00:00:01+0200", "4F2B6C1E");
+    create_test_mov(&single, "2023-08-16T10:00:00+0200", "0D5A4B8E");
+
+    let template =
+        Template::parse("{year}/{month}/{day}/{live}/{filename}.{extension}")
+            .unwrap();
+
+    move_images(
+        &[&still, &movie, &single],
+        &dest_dir,
+        &template,
+        &AppConfig::default(),
+        make_test_args(&[]),
+    );
+
+    assert!(dest_dir.join("2023/08/15/live/IMG_E0001.JPG").exists());
+    assert!(dest_dir.join("2023/08/15/live/IMG_0001.MOV").exists());
+    assert!(dest_dir.join("2023/08/16/IMG_0002.MOV").exists());
+}
+
+#[test]
+fn bursts_get_their_own_folder() {
+    let tmp = TempDir::new().unwrap();
+    let source_dir = tmp.path().join("source");
+    let dest_dir = tmp.path().join("dest");
+    fs::create_dir_all(&source_dir).unwrap();
+
+    // The first three frames are only a burst thanks to the fractions of a
+    // second.
+    let frames: Vec<_> = [
+        ("DSC_0100.JPG", "14:30:00", "90"),
+        ("DSC_0101.JPG", "14:30:01", "10"),
+        ("DSC_0102.JPG", "14:30:01", "30"),
+        ("DSC_0103.JPG", "14:30:05", "00"),
+    ]
+    .iter()
+    .map(|(name, time, subsec)| {
+        let path = source_dir.join(name);
+        create_test_jpeg_with_fields(
+            &path,
+            &[
+                TestField::ascii(
+                    TestIfd::Exif,
+                    0x9003,
+                    &format!("2023:08:15 {time}"),
+                ),
+                TestField::ascii(TestIfd::Exif, 0x9291, subsec),
+            ],
+        );
+        path
+    })
+    .collect();
+
+    let template =
+        Template::parse("{day}/{burst}/{filename}.{extension}").unwrap();
+    let mut groups = group::group(
+        frames
+            .iter()
+            .map(|frame| Item::read(frame, &AppConfig::default()).unwrap())
+            .collect(),
+    );
+    let config = AppConfig {
+        burst_threshold: Some(0.3),
+        ..Default::default()
+    };
+    crate::burst::mark_bursts(&mut groups, config.burst_threshold());
+    for group in groups {
+        move_group(
+            group,
+            tmp.path(),
+            &dest_dir,
+            &NaiveTime::from_hms_opt(0, 0, 0).unwrap(),
+            &template,
+            None,
+            true,
+            false,
+            &AppConfig::default(),
+            None,
+            None,
+            make_test_args(&[]),
+            Arc::new(MultiProgress::new()),
+            &mut Vec::new(),
+        )
+        .unwrap();
+    }
+
+    assert!(dest_dir.join("15/dsc_0100/dsc_0100.jpg").exists());
+    assert!(dest_dir.join("15/dsc_0100/dsc_0101.jpg").exists());
+    assert!(dest_dir.join("15/dsc_0100/dsc_0102.jpg").exists());
+    assert!(dest_dir.join("15/dsc_0103.jpg").exists());
+}
+
+// =============================================================================
+// day_wrap() Unit Tests
+// =============================================================================
+
+#[test]
+fn day_wrap_no_wrap_early_time() {
+    let ts = DateTime {
+        year: 2023,
+        month: 8,
+        day: 15,
+        hour: 10,
+        minute: 30,
+        second: 0,
+        nanosecond: None,
+        offset: None,
+    };
+    let offset = NaiveTime::from_hms_opt(4, 0, 0).unwrap();
+    assert_eq!(day_wrap(&ts, &offset), 0);
+}
+
+#[test]
+fn day_wrap_wraps_late_night() {
+    let ts = DateTime {
+        year: 2023,
+        month: 8,
+        day: 15,
+        hour: 22,
+        minute: 0,
+        second: 0,
+        nanosecond: None,
+        offset: None,
+    };
+    let offset = NaiveTime::from_hms_opt(4, 0, 0).unwrap();
+    // 22 + 4 = 26 > 23, so wraps.
+    assert_eq!(day_wrap(&ts, &offset), 1);
+}
+
+#[test]
+fn day_wrap_minute_overflow_causes_wrap() {
+    let ts = DateTime {
+        year: 2023,
+        month: 8,
+        day: 15,
+        hour: 23,
+        minute: 30,
+        second: 0,
+        nanosecond: None,
+        offset: None,
+    };
+    let offset = NaiveTime::from_hms_opt(0, 31, 0).unwrap();
+    // 23:30 + 0:31 = 24:01, minute overflow adds 1 to hour check.
+    assert_eq!(day_wrap(&ts, &offset), 1);
+}
+
+// =============================================================================
+// Config Tests
+// =============================================================================
+
+#[test]
+fn config_load_missing_returns_default() {
+    use crate::config::Config;
+
+    let tmp = TempDir::new().unwrap();
+    let missing_path = tmp.path().join("nonexistent.toml");
+
+    // Loading from a non-existent path should return default (confy creates
+    // it).
+    let config = Config::load(Some(&missing_path)).unwrap();
+    assert!(config.format.is_none());
+    assert!(config.verbose.is_none());
+}
+
+#[test]
+fn config_load_custom_path() {
+    use crate::config::Config;
+
+    let tmp = TempDir::new().unwrap();
+    let config_path = tmp.path().join("custom.toml");
+
+    fs::write(
+        &config_path,
+        r#"
+format = "{year}-{month}/{filename}.{extension}"
+verbose = true
+make-lowercase = true
+"#,
+    )
+    .unwrap();
+
+    let config = Config::load(Some(&config_path)).unwrap();
+    assert_eq!(
+        config.format.as_deref(),
+        Some("{year}-{month}/{filename}.{extension}")
+    );
+    assert_eq!(config.verbose, Some(true));
+    assert_eq!(config.make_lowercase, Some(true));
+}
+
+#[test]
+fn config_format_returns_default_when_unset() {
+    use crate::config::{Config, DEFAULT_FORMAT};
+
+    let config = Config::default();
+    assert_eq!(config.format(), DEFAULT_FORMAT);
+}
+
+// =============================================================================
+// Template Tests (additional coverage)
+// =============================================================================
+
+#[test]
+fn template_expand_with_all_fields() {
     let template = Template::parse(
         "{year}/{month}/{day}/{hour}{minute}{second}_{filename}.{extension}",
     )
@@ -766,11 +1907,19 @@ fn template_expand_with_all_fields() {
         second: "45".to_string(),
         filename: "IMG_001".to_string(),
         extension: "jpg".to_string(),
+        pair_role: String::new(),
+        live: String::new(),
+        burst: String::new(),
+        source_relpath: String::new(),
         camera_make: Some("Canon".to_string()),
         camera_model: Some("EOS R5".to_string()),
         lens: None,
         iso: None,
         focal_length: None,
+        place: None,
+        rating: None,
+        label: None,
+        keywords: None,
     };
 
     let result = template.expand(&ctx);
@@ -791,11 +1940,19 @@ fn template_expand_optional_fields_fallback() {
         second: "00".to_string(),
         filename: "test".to_string(),
         extension: "jpg".to_string(),
+        pair_role: String::new(),
+        live: String::new(),
+        burst: String::new(),
+        source_relpath: String::new(),
         camera_make: None,
         camera_model: None,
         lens: None,
         iso: None,
         focal_length: None,
+        place: None,
+        rating: None,
+        label: None,
+        keywords: None,
     };
 
     let result = template.expand(&ctx);
@@ -808,8 +1965,8 @@ fn template_expand_optional_fields_fallback() {
 
 #[test]
 fn same_size_different_content_risk() {
-    // This test documents the current behavior: size-based duplicate detection.
-    // Two files with same size but different content are treated as duplicates.
+    // Removing the source needs a content match; a size match is only trusted
+    // with --trust-size.
     let tmp = TempDir::new().unwrap();
     let source = tmp.path().join("source.jpg");
     let dest = tmp.path().join("dest.jpg");
@@ -819,15 +1976,43 @@ fn same_size_different_content_risk() {
     fs::write(&dest, b"BBBBBBBBB").unwrap();
 
     let args = make_test_args(&["--remove-source"]);
-    move_file(&source, &dest, false, args, &MultiProgress::new()).unwrap();
+    let outcome =
+        move_file(&source, &dest, false, args, &MultiProgress::new()).unwrap();
+    assert_eq!(outcome, Outcome::Conflict);
+    assert!(source.exists(), "Source preserved (content differs)");
 
-    // Current behavior: source is deleted because sizes match.
-    // This is a known limitation - size-based detection can have false
-    // positives.
-    assert!(!source.exists(), "Source deleted (size match)");
+    let args = make_test_args(&["--remove-source", "--trust-size"]);
+    move_file(&source, &dest, false, args, &MultiProgress::new()).unwrap();
+    assert!(!source.exists(), "Source deleted (size match trusted)");
     assert_eq!(fs::read(&dest).unwrap(), b"BBBBBBBBB", "Dest unchanged");
 }
 
+#[test]
+fn byte_compare_removes_only_identical_files() {
+    let tmp = TempDir::new().unwrap();
+    let source = tmp.path().join("source.jpg");
+    let dest = tmp.path().join("dest.jpg");
+
+    // Differ in the last byte only.
+    fs::write(&source, vec![7u8; 200_000]).unwrap();
+    let mut different = vec![7u8; 200_000];
+    different[199_999] = 8;
+    fs::write(&dest, &different).unwrap();
+
+    let args = make_test_args(&["--remove-source", "--byte-compare"]);
+    let outcome =
+        move_file(&source, &dest, false, args.clone(), &MultiProgress::new())
+            .unwrap();
+    assert_eq!(outcome, Outcome::Conflict);
+    assert!(source.exists(), "Source preserved (content differs)");
+
+    fs::write(&dest, vec![7u8; 200_000]).unwrap();
+    let outcome =
+        move_file(&source, &dest, false, args, &MultiProgress::new()).unwrap();
+    assert_eq!(outcome, Outcome::Removed);
+    assert!(!source.exists(), "Source removed (identical)");
+}
+
 // =============================================================================
 // Checksum-Based Duplicate Detection
 // =============================================================================
@@ -893,3 +2078,419 @@ fn checksum_skips_without_remove_source() {
     assert!(source.exists(), "Source preserved");
     assert!(dest.exists(), "Dest preserved");
 }
+
+#[test]
+fn index_finds_duplicates_under_other_names() {
+    let tmp = TempDir::new().unwrap();
+    let source_dir = tmp.path().join("source");
+    let dest_dir = tmp.path().join("dest");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::create_dir_all(dest_dir.join("old-layout")).unwrap();
+
+    // Imported before under another template and name.
+    let source_file = source_dir.join("IMG_1234.jpg");
+    create_test_jpeg(&source_file, "2023:08:15 14:30:00");
+    fs::copy(&source_file, dest_dir.join("old-layout/holiday.jpg")).unwrap();
+    fs::write(source_dir.join("IMG_1234.jpg.xmp"), b"<xmp/>").unwrap();
+    let new_file = source_dir.join("IMG_1235.jpg");
+    create_test_jpeg(&new_file, "2023:08:15 14:31:00");
+
+    let template =
+        Template::parse("{year}/{month}/{day}/{filename}.{extension}").unwrap();
+    let config = AppConfig::default();
+    let args = make_test_args(&["--remove-source"]);
+    let index = Index::open(&dest_dir, &Throttle::new(None)).unwrap();
+    for file in [&source_file, &new_file] {
+        move_group(
+            Group {
+                items: vec![Item::read(file, &config).unwrap()],
+            },
+            &source_dir,
+            &dest_dir,
+            &NaiveTime::from_hms_opt(0, 0, 0).unwrap(),
+            &template,
+            None,
+            false,
+            false,
+            &config,
+            Some(&index),
+            None,
+            args.clone(),
+            Arc::new(MultiProgress::new()),
+            &mut Vec::new(),
+        )
+        .unwrap();
+    }
+
+    assert!(!source_file.exists(), "Duplicate source should be removed");
+    assert!(!dest_dir.join("2023/08/15/IMG_1234.jpg").exists());
+    assert!(
+        dest_dir.join("old-layout/holiday.jpg.xmp").exists(),
+        "Sidecar should join the existing copy"
+    );
+    assert!(dest_dir.join("2023/08/15/IMG_1235.jpg").exists());
+
+    // The moved file is indexed.
+    let copy = source_dir.join("copy.jpg");
+    fs::copy(dest_dir.join("2023/08/15/IMG_1235.jpg"), &copy).unwrap();
+    assert_eq!(
+        index.find(&copy).unwrap().1,
+        Some(dest_dir.join("2023/08/15/IMG_1235.jpg"))
+    );
+}
+
+#[test]
+fn records_report_each_file() {
+    let tmp = TempDir::new().unwrap();
+    let source_dir = tmp.path().join("source");
+    let dest_dir = tmp.path().join("dest");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::create_dir_all(dest_dir.join("2023/08/15")).unwrap();
+
+    let copied = source_dir.join("IMG_1.jpg");
+    create_test_jpeg(&copied, "2023:08:15 14:30:00");
+    let conflict = source_dir.join("IMG_2.jpg");
+    create_test_jpeg(&conflict, "2023:08:15 14:31:00");
+    fs::write(dest_dir.join("2023/08/15/IMG_2.jpg"), b"other").unwrap();
+
+    let template =
+        Template::parse("{year}/{month}/{day}/{filename}.{extension}").unwrap();
+    let config = AppConfig::default();
+    let mut records = Vec::new();
+    for file in [&copied, &conflict] {
+        move_group(
+            Group {
+                items: vec![Item::read(file, &config).unwrap()],
+            },
+            &source_dir,
+            &dest_dir,
+            &NaiveTime::from_hms_opt(0, 0, 0).unwrap(),
+            &template,
+            None,
+            false,
+            false,
+            &config,
+            None,
+            None,
+            make_test_args(&["--copy"]),
+            Arc::new(MultiProgress::new()),
+            &mut records,
+        )
+        .unwrap();
+    }
+
+    assert_eq!(records[0].action, Action::Copied);
+    assert_eq!(
+        records[0].destination.as_deref(),
+        Some(dest_dir.join("2023/08/15/IMG_1.jpg").as_path())
+    );
+    assert_eq!(records[0].bytes, fs::metadata(&copied).unwrap().len());
+    assert_eq!(records[0].date_source, Some(DateSource::Exif));
+    assert_eq!(records[1].action, Action::SkippedConflict);
+}
+
+#[test]
+fn failed_sidecar_is_recorded_with_its_file() {
+    let tmp = TempDir::new().unwrap();
+    let source_dir = tmp.path().join("source");
+    let dest_dir = tmp.path().join("dest");
+    fs::create_dir_all(&source_dir).unwrap();
+
+    let source_file = source_dir.join("IMG_1.jpg");
+    let source_xmp = source_dir.join("IMG_1.jpg.xmp");
+    create_test_jpeg(&source_file, "2023:08:15 14:30:00");
+    fs::write(&source_xmp, b"<xmp/>").unwrap();
+
+    let template = Template::parse("{year}/{filename}.{extension}").unwrap();
+    let config = AppConfig::default();
+    let item = Item::read(&source_file, &config).unwrap();
+    // Gone before it is moved.
+    fs::remove_file(&source_xmp).unwrap();
+
+    let mut records = Vec::new();
+    let result = move_group(
+        Group { items: vec![item] },
+        &source_dir,
+        &dest_dir,
+        &NaiveTime::from_hms_opt(0, 0, 0).unwrap(),
+        &template,
+        None,
+        false,
+        false,
+        &config,
+        None,
+        None,
+        make_test_args(&[]),
+        Arc::new(MultiProgress::new()),
+        &mut records,
+    );
+
+    assert!(result.is_err());
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].action, Action::Moved);
+    assert!(records[0].error.is_some());
+    assert!(dest_dir.join("2023/IMG_1.jpg").exists());
+}
+
+#[test]
+fn unsorted_files_keep_their_source_path() {
+    let tmp = TempDir::new().unwrap();
+    let source_dir = tmp.path().join("source");
+    let dest_dir = tmp.path().join("dest");
+    fs::create_dir_all(source_dir.join("DCIM/100")).unwrap();
+
+    let unreadable = source_dir.join("DCIM/100/IMG_1.jpg");
+    create_jpeg_without_exif(&unreadable);
+    fs::write(source_dir.join("DCIM/100/IMG_1.jpg.xmp"), b"<xmp/>").unwrap();
+    let readable = source_dir.join("DCIM/100/IMG_2.jpg");
+    create_test_jpeg(&readable, "2023:08:15 14:30:00");
+
+    let template =
+        Template::parse("{year}/{month}/{day}/{filename}.{extension}").unwrap();
+    let unsorted = Template::parse("unsorted/{source_relpath}").unwrap();
+    let config = AppConfig::default();
+    for file in [&unreadable, &readable] {
+        move_group(
+            Group {
+                items: vec![Item::read(file, &config).unwrap()],
+            },
+            &source_dir,
+            &dest_dir,
+            &NaiveTime::from_hms_opt(0, 0, 0).unwrap(),
+            &template,
+            Some(&unsorted),
+            false,
+            false,
+            &config,
+            None,
+            None,
+            make_test_args(&[]),
+            Arc::new(MultiProgress::new()),
+            &mut Vec::new(),
+        )
+        .unwrap();
+    }
+
+    assert!(!unreadable.exists(), "Source folder should be emptied");
+    assert!(dest_dir.join("unsorted/DCIM/100/IMG_1.jpg").exists());
+    assert!(dest_dir.join("unsorted/DCIM/100/IMG_1.jpg.xmp").exists());
+    assert!(dest_dir.join("2023/08/15/IMG_2.jpg").exists());
+}
+
+#[test]
+fn quarantine_duplicates_conflicts_and_unreadable() {
+    let tmp = TempDir::new().unwrap();
+    let source_dir = tmp.path().join("source");
+    let dest_dir = tmp.path().join("dest");
+    let quarantine_dir = tmp.path().join("quarantine");
+    fs::create_dir_all(source_dir.join("card")).unwrap();
+    fs::create_dir_all(dest_dir.join("2023/08/15")).unwrap();
+
+    let duplicate = source_dir.join("card/IMG_1.jpg");
+    create_test_jpeg(&duplicate, "2023:08:15 14:30:00");
+    fs::write(source_dir.join("card/IMG_1.jpg.xmp"), b"<xmp/>").unwrap();
+    fs::copy(&duplicate, dest_dir.join("2023/08/15/IMG_1.jpg")).unwrap();
+    let conflict = source_dir.join("card/IMG_2.jpg");
+    create_test_jpeg(&conflict, "2023:08:15 14:31:00");
+    fs::write(dest_dir.join("2023/08/15/IMG_2.jpg"), b"other").unwrap();
+    let unreadable = source_dir.join("IMG_3.jpg");
+    create_jpeg_without_exif(&unreadable);
+
+    let template =
+        Template::parse("{year}/{month}/{day}/{filename}.{extension}").unwrap();
+    let config = AppConfig::default();
+    let args =
+        make_test_args(&["--quarantine", quarantine_dir.to_str().unwrap()]);
+    let quarantine =
+        Quarantine::new(&quarantine_dir, &source_dir, false, false);
+    for file in [&duplicate, &conflict, &unreadable] {
+        let mut records = Vec::new();
+        move_group(
+            Group {
+                items: vec![Item::read(file, &config).unwrap()],
+            },
+            &source_dir,
+            &dest_dir,
+            &NaiveTime::from_hms_opt(0, 0, 0).unwrap(),
+            &template,
+            None,
+            false,
+            false,
+            &config,
+            None,
+            Some(&quarantine),
+            args.clone(),
+            Arc::new(MultiProgress::new()),
+            &mut records,
+        )
+        .unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].action, Action::Quarantined);
+        assert!(!file.exists());
+    }
+
+    let day = fs::read_dir(&quarantine_dir)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    for path in [
+        "card/IMG_1.jpg",
+        "card/IMG_1.jpg.xmp",
+        "card/IMG_2.jpg",
+        "IMG_3.jpg",
+    ] {
+        assert!(day.join(path).exists(), "{path} should be quarantined");
+    }
+    assert_eq!(
+        fs::read(dest_dir.join("2023/08/15/IMG_2.jpg")).unwrap(),
+        b"other"
+    );
+
+    let manifest = fs::read_to_string(day.join("manifest.jsonl")).unwrap();
+    let reasons: Vec<_> = manifest
+        .lines()
+        .map(|line| {
+            let entry: serde_json::Value = serde_json::from_str(line).unwrap();
+            entry["reason"].as_str().unwrap().to_string()
+        })
+        .collect();
+    assert_eq!(
+        reasons,
+        ["duplicate", "duplicate", "conflict", "unreadable"]
+    );
+}
+
+#[test]
+fn reorganize_library_to_new_template() {
+    let tmp = TempDir::new().unwrap();
+    let library = tmp.path();
+    fs::create_dir_all(library.join("2023-08-15")).unwrap();
+    fs::create_dir_all(library.join("misc")).unwrap();
+
+    create_test_jpeg(
+        &library.join("2023-08-15/IMG_1234.jpg"),
+        "2023:08:15 14:30:00",
+    );
+    fs::write(library.join("2023-08-15/IMG_1234.jpg.xmp"), b"<xmp/>").unwrap();
+    create_test_jpeg(&library.join("misc/other.jpg"), "2023:08:16 10:00:00");
+
+    let config = AppConfig::default();
+    let items = [
+        library.join("2023-08-15/IMG_1234.jpg"),
+        library.join("misc/other.jpg"),
+    ]
+    .iter()
+    .map(|file| Item::read(file, &config).unwrap())
+    .collect();
+
+    let moves = reorganize::plan(
+        library,
+        group::group(items),
+        &Template::parse("{year}-{month}-{day}/{filename}.{extension}")
+            .unwrap(),
+        &Template::parse("{year}/{month}/{day}/{filename}.{extension}")
+            .unwrap(),
+        &NaiveTime::from_hms_opt(0, 0, 0).unwrap(),
+        false,
+        &config,
+    );
+    reorganize::execute(library, moves.into_iter().flatten().collect(), false)
+        .unwrap();
+
+    assert!(library.join("2023/08/15/IMG_1234.jpg").exists());
+    assert!(
+        library.join("2023/08/15/IMG_1234.jpg.xmp").exists(),
+        "Sidecar should move with its image"
+    );
+    assert!(
+        !library.join("2023-08-15").exists(),
+        "Emptied folder should be removed"
+    );
+    assert!(
+        library.join("misc/other.jpg").exists(),
+        "Files not matching the old template should stay"
+    );
+}
+
+#[test]
+fn verify_library_reports_problems() {
+    let tmp = TempDir::new().unwrap();
+    let library = tmp.path();
+    fs::create_dir_all(library.join("2023/08/15")).unwrap();
+
+    create_test_jpeg(
+        &library.join("2023/08/15/IMG_1234.jpg"),
+        "2023:08:15 14:30:00",
+    );
+    fs::write(library.join("2023/08/15/IMG_1234.jpg.xmp"), b"<xmp/>").unwrap();
+    create_test_jpeg(
+        &library.join("2023/08/15/IMG_1235.jpg"),
+        "2023:08:16 10:00:00",
+    );
+    fs::write(library.join("2023/08/15/IMG_1236.jpg.xmp"), b"<xmp/>").unwrap();
+    create_jpeg_without_exif(&library.join("2023/08/15/broken.jpg"));
+
+    let config = AppConfig::default();
+    let items = fs::read_dir(library.join("2023/08/15"))
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "jpg"))
+        .map(|file| Item::read(&file, &config).unwrap())
+        .collect();
+
+    let problems = verify::verify(
+        library,
+        &group::group(items),
+        &Template::parse("{year}/{month}/{day}/{filename}.{extension}")
+            .unwrap(),
+        &NaiveTime::from_hms_opt(0, 0, 0).unwrap(),
+        false,
+        &config,
+    )
+    .unwrap();
+
+    assert_eq!(problems.len(), 3, "{problems:?}");
+    assert!(matches!(
+        &problems[0],
+        verify::Problem::Misplaced { path, expected }
+            if *path == library.join("2023/08/15/IMG_1235.jpg")
+                && *expected == library.join("2023/08/16/IMG_1235.jpg")
+    ));
+    assert_eq!(
+        problems[1],
+        verify::Problem::OrphanSidecar(
+            library.join("2023/08/15/IMG_1236.jpg.xmp")
+        )
+    );
+    assert!(matches!(
+        &problems[2],
+        verify::Problem::Unreadable { path, .. }
+            if *path == library.join("2023/08/15/broken.jpg")
+    ));
+}
+
+#[test]
+fn stats_count_by_month_and_missing_metadata() {
+    let tmp = TempDir::new().unwrap();
+    let files = [
+        tmp.path().join("a.jpg"),
+        tmp.path().join("b.jpg"),
+        tmp.path().join("c.jpg"),
+    ];
+    create_test_jpeg(&files[0], "2023:08:15 14:30:00");
+    create_test_jpeg(&files[1], "2023:08:16 10:00:00");
+    create_jpeg_without_exif(&files[2]);
+
+    let stats =
+        stats::collect(&files, &AppConfig::default(), &Throttle::new(None))
+            .unwrap();
+
+    assert_eq!(stats.total.files, 3);
+    assert_eq!(stats.months.len(), 1);
+    assert_eq!(stats.months["2023-08"].files, 2);
+    assert_eq!(stats.kinds["image"].files, 3);
+    assert_eq!(stats.missing["capture time"].files, 1);
+    assert_eq!(stats.missing["location"].files, 2);
+}
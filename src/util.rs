@@ -1,38 +1,70 @@
-use crate::*;
+use crate::{error::IoContext, *};
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use log::info;
 use std::{
     fs,
-    io::{self, BufReader, ErrorKind, Read},
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
 };
 use xxhash_rust::xxh3::{Xxh3, xxh3_64};
 
-const EXTENSIONS: &[&str] = &[
-    // RAW file extensions.
+const RAW_EXTENSIONS: &[&str] = &[
     "3fr", "ari", "arw", "bay", "cap", "cr2", "cr3", "crw", "data", "dcr",
     "dcs", "dng", "drf", "eip", "erf", "fff", "gpr", "iiq", "k25", "kdc",
     "mdc", "mef", "mos", "mrw", "nef", "nrw", "obm", "orf", "pef", "ptx",
     "pxn", "r3d", "raf", "raw", "rw2", "rwl", "rwz", "sr2", "srf", "srw",
-    "x3f", // Other image files.
+    "x3f",
+];
+
+const IMAGE_EXTENSIONS: &[&str] = &[
     "avif", "bmp", "fpx", "gif", "heic", "heif", "j2k", "jfif", "jif", "jp2",
-    "jpeg", "jpg", "jpx", "pcd", "png", "psd", "tif", "tiff",
-    "webp", // Movie file formats.
+    "jpeg", "jpg", "jpx", "pcd", "png", "psd", "tif", "tiff", "webp",
+];
+
+const MOVIE_EXTENSIONS: &[&str] = &[
     "264", "3g2", "3gp", "amv", "asf", "avi", "cine", "drc", "f4a", "f4b",
     "f4p", "f4v", "flv", "gifv", "m2ts", "m2v", "m4p", "m4v", "mkv", "mng",
-    "mp4", "mpeg", "mpg", "mts", "mxf", "nsv", "ogg", "qt", "roq", "svi",
-    "vob", "wmv", "yuv",
+    "mov", "mp4", "mpeg", "mpg", "mts", "mxf", "nsv", "ogg", "qt", "roq",
+    "svi", "vob", "wmv", "yuv",
 ];
 
-pub(crate) fn has_image_extension(entry: &walkdir::DirEntry) -> bool {
-    if let Some(extension) = PathBuf::from(entry.file_name()).extension()
-        && let Some(extension) = extension.to_str()
-    {
-        EXTENSIONS.contains(&extension.to_lowercase().as_str())
+/// Kind of media file, derived from the extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FileKind {
+    Raw,
+    Image,
+    Movie,
+}
+
+/// Returns the kind of media file `path` is, if any.
+pub(crate) fn file_kind(path: &Path) -> Option<FileKind> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+
+    if RAW_EXTENSIONS.contains(&extension.as_str()) {
+        Some(FileKind::Raw)
+    } else if IMAGE_EXTENSIONS.contains(&extension.as_str()) {
+        Some(FileKind::Image)
+    } else if MOVIE_EXTENSIONS.contains(&extension.as_str()) {
+        Some(FileKind::Movie)
     } else {
-        false
+        None
     }
 }
 
+/// Extensions of JPEG-like stills: the developed half of a RAW+JPEG pair, the
+/// still of a Live Photo or a motion photo.
+const JPEG_EXTENSIONS: &[&str] = &["jpg", "jpeg", "heic", "heif"];
+
+/// Returns `true` if `path` is a JPEG-like still.
+pub(crate) fn is_jpeg(path: &Path) -> bool {
+    path.extension()
+        .and_then(|s| s.to_str())
+        .is_some_and(|s| JPEG_EXTENSIONS.contains(&s.to_lowercase().as_str()))
+}
+
+pub(crate) fn has_image_extension(entry: &walkdir::DirEntry) -> bool {
+    file_kind(Path::new(entry.file_name())).is_some()
+}
+
 /// Files larger than 64MB use streaming hash to avoid memory pressure.
 const STREAMING_THRESHOLD: u64 = 64 * 1024 * 1024;
 /// Buffer size for streaming hash (64KB).
@@ -41,7 +73,7 @@ const HASH_BUFFER_SIZE: usize = 64 * 1024;
 /// Compute XXH3-64 hash of a file.
 /// Uses streaming for files larger than `STREAMING_THRESHOLD` to reduce memory
 /// usage.
-fn file_hash(path: &Path, size: u64) -> Result<u64> {
+pub(crate) fn file_hash(path: &Path, size: u64) -> Result<u64> {
     let mut file = fs::File::open(path).with_context(|| {
         format!("Unable to open '{}' for hashing.", path.display())
     })?;
@@ -70,148 +102,559 @@ fn file_hash(path: &Path, size: u64) -> Result<u64> {
     }
 }
 
-/// Check if two files are duplicates.
-/// If `use_checksum` is true, compares file contents via XXH3 hash.
-/// Otherwise, only compares file sizes.
+/// How the contents of two files of the same size are compared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    /// Not at all.
+    Size,
+    /// By XXH3 hash.
+    Checksum,
+    /// Byte for byte.
+    Bytes,
+}
+
+impl Comparison {
+    /// Chooses the comparison for `move_file()`.
+    ///
+    /// Sources are only removed, trashed or quarantined if their content
+    /// matches, unless `--trust-size` is given.
+    fn for_args(checksum: bool, args: &ArgMatches) -> Self {
+        if args.get_flag("byte-compare") {
+            Self::Bytes
+        } else if checksum
+            || (is_destructive(args) && !args.get_flag("trust-size"))
+        {
+            Self::Checksum
+        } else {
+            Self::Size
+        }
+    }
+}
+
+/// Returns `true` if duplicates are removed, trashed or quarantined.
+pub(crate) fn is_destructive(args: &ArgMatches) -> bool {
+    args.get_flag("remove-source")
+        || args.get_flag("trash-source")
+        || args.contains_id("quarantine")
+}
+
+/// Check if two files are duplicates, comparing their contents as given.
 fn files_match(
     source: &Path,
     dest: &Path,
     source_size: u64,
     dest_size: u64,
-    use_checksum: bool,
+    comparison: Comparison,
 ) -> Result<bool> {
     if source_size != dest_size {
         return Ok(false);
     }
-    if use_checksum {
-        let source_hash = file_hash(source, source_size)?;
-        let dest_hash = file_hash(dest, dest_size)?;
-        Ok(source_hash == dest_hash)
-    } else {
-        Ok(true)
+    match comparison {
+        Comparison::Size => Ok(true),
+        Comparison::Checksum => {
+            let source_hash = file_hash(source, source_size)?;
+            let dest_hash = file_hash(dest, dest_size)?;
+            Ok(source_hash == dest_hash)
+        }
+        Comparison::Bytes => same_contents(source, dest),
+    }
+}
+
+/// Compares the contents of two files byte for byte.
+pub(crate) fn same_contents(a: &Path, b: &Path) -> Result<bool> {
+    let open = |path: &Path| {
+        fs::File::open(path)
+            .map(|file| BufReader::with_capacity(HASH_BUFFER_SIZE, file))
+            .with_context(|| {
+                format!("Unable to open '{}' for comparing.", path.display())
+            })
+    };
+    let (mut a_reader, mut b_reader) = (open(a)?, open(b)?);
+
+    loop {
+        let a_buffer = a_reader.fill_buf().with_context(|| {
+            format!("Unable to read '{}' for comparing.", a.display())
+        })?;
+        let b_buffer = b_reader.fill_buf().with_context(|| {
+            format!("Unable to read '{}' for comparing.", b.display())
+        })?;
+        let length = a_buffer.len().min(b_buffer.len());
+        if a_buffer[..length] != b_buffer[..length] {
+            return Ok(false);
+        }
+        if length == 0 {
+            return Ok(a_buffer.is_empty() && b_buffer.is_empty());
+        }
+        a_reader.consume(length);
+        b_reader.consume(length);
     }
 }
 
 /// Move a file, falling back to copy+delete with a progress bar for
 /// cross-device moves.
-fn move_or_copy(
+///
+/// The source is only removed once the copy is verified, see [`copy`].
+pub(crate) fn move_or_copy(
     source: &Path,
     dest: &Path,
+    checksum: bool,
     multi: &MultiProgress,
 ) -> io::Result<()> {
     match fs::rename(source, dest) {
         Ok(()) => Ok(()),
         Err(e) if e.kind() == ErrorKind::CrossesDevices => {
-            let size = source.metadata()?.len();
-            let pb = multi.add(ProgressBar::new(size));
-            pb.set_style(
-                ProgressStyle::default_bar()
-                    .template("{msg} [{bar:30}] {bytes}/{total_bytes} {bytes_per_sec}")
-                    .unwrap()
-                    .progress_chars("=> "),
+            copy(source, dest, checksum, multi)?;
+            fs::remove_file(source)?;
+            Ok(())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Suffix of the hidden files copies are written to, see [`temp_path`].
+pub(crate) const TEMP_SUFFIX: &str = ".exifmv-tmp";
+
+/// Returns the hidden file `dest` is written to before it is complete.
+pub(crate) fn temp_path(dest: &Path) -> PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(dest.file_name().unwrap_or_default());
+    name.push(TEMP_SUFFIX);
+    dest.with_file_name(name)
+}
+
+/// Copies `source` to `dest` with a progress bar.
+///
+/// The copy is written to a hidden file next to `dest` (see [`temp_path`]),
+/// synced to disk and checked against the size and, with `checksum`, the
+/// XXH3 hash of `source`. Only then is it renamed to `dest`, so `dest` never
+/// holds a partial copy, even if interrupted.
+///
+/// On Linux, the copy is dropped from the page cache before it is hashed, so
+/// it is read back from disk. Elsewhere it may be read from the cache, which
+/// only checks the data as it was written.
+pub(crate) fn copy(
+    source: &Path,
+    dest: &Path,
+    checksum: bool,
+    multi: &MultiProgress,
+) -> io::Result<()> {
+    let temp = temp_path(dest);
+    let result = copy_to(source, &temp, checksum, multi)
+        .and_then(|()| fs::rename(&temp, dest))
+        .and_then(|()| sync_parent(dest));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+
+    result
+}
+
+fn copy_to(
+    source: &Path,
+    temp: &Path,
+    checksum: bool,
+    multi: &MultiProgress,
+) -> io::Result<()> {
+    let source_metadata = source.metadata()?;
+    let size = source_metadata.len();
+    let pb = multi.add(ProgressBar::new(size));
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{msg} [{bar:30}] {bytes}/{total_bytes} {bytes_per_sec}")
+            .unwrap()
+            .progress_chars("=> "),
+    );
+    pb.set_message(
+        source
+            .file_name()
+            .unwrap_or(source.as_os_str())
+            .to_string_lossy()
+            .to_string(),
+    );
+
+    let mut reader = BufReader::new(fs::File::open(source)?);
+    let mut writer = fs::File::create(temp)?;
+    let mut hasher = Xxh3::new();
+    let mut buffer = vec![0u8; HASH_BUFFER_SIZE];
+    let mut copied = 0;
+    loop {
+        let bytes_read = reader.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        if checksum {
+            hasher.update(&buffer[..bytes_read]);
+        }
+        writer.write_all(&buffer[..bytes_read])?;
+        copied += bytes_read as u64;
+        pb.inc(bytes_read as u64);
+    }
+    writer.sync_all()?;
+    // Synced pages can be dropped from the cache, so the hash below reads
+    // what is on disk rather than what is still in memory.
+    #[cfg(target_os = "linux")]
+    if checksum {
+        let _ =
+            rustix::fs::fadvise(&writer, 0, None, rustix::fs::Advice::DontNeed);
+    }
+    drop(writer);
+    pb.finish_and_clear();
+
+    let written = temp.metadata()?.len();
+    if copied != size || written != size {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "copy has {written} bytes, {} has {size}",
+                source.display()
+            ),
+        ));
+    }
+    if checksum
+        && file_hash(temp, written).map_err(io::Error::other)?
+            != hasher.digest()
+    {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("copy differs from {}", source.display()),
+        ));
+    }
+
+    copy_attributes(source, &source_metadata, temp)
+}
+
+/// Copies the access and modification times, permissions, ownership and user
+/// extended attributes of `source` to `dest`, as far as permitted. File
+/// systems like exFAT or SMB shares may refuse some of them.
+fn copy_attributes(
+    source: &Path,
+    source_metadata: &fs::Metadata,
+    dest: &Path,
+) -> io::Result<()> {
+    let file = fs::File::open(dest)?;
+    let mut times =
+        fs::FileTimes::new().set_modified(source_metadata.modified()?);
+    if let Ok(accessed) = source_metadata.accessed() {
+        times = times.set_accessed(accessed);
+    }
+    if let Err(e) = file.set_times(times) {
+        info!("Unable to set times of '{}': {}", dest.display(), e);
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        // Only root may give files away; anyone may pass on their groups.
+        if std::os::unix::fs::chown(
+            dest,
+            Some(source_metadata.uid()),
+            Some(source_metadata.gid()),
+        )
+        .is_err()
+        {
+            let _ = std::os::unix::fs::chown(
+                dest,
+                None,
+                Some(source_metadata.gid()),
             );
-            pb.set_message(
-                source
-                    .file_name()
-                    .unwrap_or(source.as_os_str())
-                    .to_string_lossy()
-                    .to_string(),
+        }
+    }
+    // After changing ownership, which may clear the setuid/setgid bits.
+    if let Err(e) = fs::set_permissions(dest, source_metadata.permissions()) {
+        info!("Unable to set permissions of '{}': {}", dest.display(), e);
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    copy_xattrs(source, dest);
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    let _ = source;
+
+    file.sync_all()
+}
+
+/// Copies the user extended attributes of `source` to `dest`, as far as the
+/// destination file system supports them.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn copy_xattrs(source: &Path, dest: &Path) {
+    use rustix::fs::{XattrFlags, getxattr, listxattr, setxattr};
+
+    let Ok(size) = listxattr(source, &mut [] as &mut [u8]) else {
+        return;
+    };
+    let mut names = vec![0u8; size];
+    let Ok(size) = listxattr(source, &mut names[..]) else {
+        return;
+    };
+
+    for name in names[..size].split(|b| *b == 0).filter(|n| !n.is_empty()) {
+        // Other namespaces on Linux hold ACLs, capabilities and security
+        // labels, which are not ours to copy.
+        if cfg!(target_os = "linux") && !name.starts_with(b"user.") {
+            continue;
+        }
+        let Ok(name) = std::ffi::CString::new(name) else {
+            continue;
+        };
+        let Ok(size) = getxattr(source, name.as_c_str(), &mut [] as &mut [u8])
+        else {
+            continue;
+        };
+        let mut value = vec![0u8; size];
+        if let Ok(size) = getxattr(source, name.as_c_str(), &mut value[..])
+            && let Err(e) = setxattr(
+                dest,
+                name.as_c_str(),
+                &value[..size],
+                XattrFlags::empty(),
+            )
+        {
+            info!(
+                "Unable to copy attribute {} to {}: {}",
+                name.to_string_lossy(),
+                dest.display(),
+                e
             );
+        }
+    }
+}
 
-            let file = fs::File::open(source)?;
-            let mut reader = pb.wrap_read(BufReader::new(file));
-            let mut writer = fs::File::create(dest)?;
-            io::copy(&mut reader, &mut writer)?;
-            pb.finish_and_clear();
+/// Sets the modification time of `path` to `time_stamp`, a local time.
+pub(crate) fn set_modified(
+    path: &Path,
+    time_stamp: NaiveDateTime,
+) -> Result<()> {
+    use chrono::TimeZone;
 
-            fs::remove_file(source)?;
-            Ok(())
+    let time = chrono::Local
+        .from_local_datetime(&time_stamp)
+        .earliest()
+        .map(std::time::SystemTime::from)
+        .unwrap_or_else(|| time_stamp.and_utc().into());
+
+    fs::File::open(path)
+        .and_then(|file| file.set_modified(time))
+        .with_context(|| {
+            format!("Unable to set modification time of '{}'.", path.display())
+        })
+}
+
+/// Syncs the folder of `path`, so a rename into it is on disk.
+fn sync_parent(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        let parent = if parent.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            parent
+        };
+        fs::File::open(parent)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+
+    Ok(())
+}
+
+/// What [`move_file()`] did with a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    /// Source and destination are the same file.
+    InPlace,
+    /// The file was (or, in a dry run, would have been) moved, or copied
+    /// with `--copy`.
+    Moved,
+    /// A matching file exists at the destination; the source was kept.
+    Duplicate,
+    /// A matching file exists at the destination; the source was removed.
+    Removed,
+    /// A matching file exists at the destination; the source was trashed.
+    Trashed,
+    /// A different file exists at the destination; the source was kept.
+    Conflict,
+    /// The source was moved to the quarantine, see `--quarantine`.
+    Quarantined,
+}
+
+/// Returns `path` with `-<number>` appended to its file stem.
+pub(crate) fn with_conflict_suffix(path: &Path, number: usize) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(format!("-{number}"));
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+
+    path.with_file_name(name)
+}
+
+/// Removes or trashes `source_file` with `--remove-source` or
+/// `--trash-source`, as `existing` has the same `method`, e.g. "size".
+pub(crate) fn handle_duplicate(
+    source_file: &Path,
+    existing: &Path,
+    method: &str,
+    args: &ArgMatches,
+) -> Result<Outcome> {
+    if args.get_flag("remove-source") && !args.get_flag("dry-run") {
+        fs::remove_file(source_file).io_context(|| {
+            format!("Failed to remove {}.", source_file.display())
+        })?;
+        info!("Removed {}.", source_file.display());
+        Ok(Outcome::Removed)
+    } else if args.get_flag("trash-source") && !args.get_flag("dry-run") {
+        trash::delete(source_file)
+            .map_err(io::Error::other)
+            .io_context(|| {
+                format!("Failed to trash {}.", source_file.display())
+            })?;
+        info!("Trashed {}.", source_file.display());
+        Ok(Outcome::Trashed)
+    } else {
+        if args.get_flag("verbose") || args.get_flag("dry-run") {
+            info!(
+                "{} exists with matching {}; skipping {}.",
+                existing.display(),
+                method,
+                source_file.display()
+            );
         }
-        Err(e) => Err(e),
+        Ok(Outcome::Duplicate)
     }
 }
 
+/// Returns `true` if files are copied, not moved: with `import --copy`.
+pub(crate) fn copying(args: &ArgMatches) -> bool {
+    // Other commands do not have the option.
+    matches!(args.try_get_one::<bool>("copy"), Ok(Some(true)))
+}
+
 pub(crate) fn move_file(
     source_file: &Path,
     dest_file: &Path,
     checksum: bool,
     args: Arc<ArgMatches>,
     multi: &MultiProgress,
-) -> Result<()> {
+) -> Result<Outcome> {
     if source_file == dest_file {
         if args.get_flag("verbose") || args.get_flag("dry-run") {
             info!("{} is already in place, skipping.", source_file.display());
         }
+        Ok(Outcome::InPlace)
     } else if dest_file.exists() {
         let source_size = source_file
             .metadata()
-            .with_context(|| {
+            .io_context(|| {
                 format!("Unable to read size of '{}'.", source_file.display())
             })?
             .len();
         let dest_size = fs::File::open(dest_file)
-            .with_context(|| {
+            .io_context(|| {
                 format!("Unable to open '{}'.", dest_file.display())
             })?
             .metadata()
-            .with_context(|| {
+            .io_context(|| {
                 format!("Unable to read size of '{}'.", dest_file.display())
             })?
             .len();
 
+        let comparison = Comparison::for_args(checksum, &args);
         let is_duplicate = files_match(
             source_file,
             dest_file,
             source_size,
             dest_size,
-            checksum,
+            comparison,
         )?;
 
         if is_duplicate {
-            if args.get_flag("remove-source") && !args.get_flag("dry-run") {
-                fs::remove_file(source_file).with_context(|| {
-                    format!("Failed to remove {}.", source_file.display())
-                })?;
-                info!("Removed {}.", source_file.display());
-            } else if args.get_flag("trash-source") && !args.get_flag("dry-run")
-            {
-                trash::delete(source_file).with_context(|| {
-                    format!("Failed to trash {}.", source_file.display())
-                })?;
-                info!("Trashed {}.", source_file.display());
-            } else if args.get_flag("verbose") || args.get_flag("dry-run") {
-                let method = if checksum { "checksum" } else { "size" };
+            let method = match comparison {
+                Comparison::Size => "size",
+                Comparison::Checksum => "checksum",
+                Comparison::Bytes => "content",
+            };
+            handle_duplicate(source_file, dest_file, method, &args)
+        } else {
+            if args.get_flag("verbose") || args.get_flag("dry-run") {
+                let method = if comparison == Comparison::Size {
+                    "size"
+                } else {
+                    "content"
+                };
                 info!(
-                    "{} exists with matching {}; skipping {}.",
+                    "{} exists with different {}; not moving {}.",
                     dest_file.display(),
                     method,
                     source_file.display()
                 );
             }
-        } else if args.get_flag("verbose") || args.get_flag("dry-run") {
-            let method = if checksum { "content" } else { "size" };
-            info!(
-                "{} exists with different {}; not moving {}.",
-                dest_file.display(),
-                method,
-                source_file.display()
-            );
+            Ok(Outcome::Conflict)
         }
     } else {
-        // Move file.
+        // Move or copy file.
         if args.get_flag("verbose") || args.get_flag("dry-run") {
             info!("{} ➔ {}", source_file.display(), dest_file.display());
         }
         if !args.get_flag("dry-run") {
-            move_or_copy(source_file, dest_file, multi).with_context(|| {
-                format!(
-                    "Unable to move {} to {}.",
-                    source_file.display(),
-                    dest_file.display()
-                )
-            })?
+            if copying(&args) {
+                copy(source_file, dest_file, checksum, multi).with_context(
+                    || {
+                        format!(
+                            "Unable to copy {} to {}.",
+                            source_file.display(),
+                            dest_file.display()
+                        )
+                    },
+                )?
+            } else {
+                move_or_copy(source_file, dest_file, checksum, multi)
+                    .with_context(|| {
+                        format!(
+                            "Unable to move {} to {}.",
+                            source_file.display(),
+                            dest_file.display()
+                        )
+                    })?
+            }
         }
+        Ok(Outcome::Moved)
     }
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn attributes_are_best_effort() {
+        use rustix::fs::{IFlags, ioctl_setflags};
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = TempDir::new().unwrap();
+        let source = tmp.path().join("source.jpg");
+        let dest = tmp.path().join("dest.jpg");
+        fs::write(&source, b"photo").unwrap();
+        fs::write(&dest, b"photo").unwrap();
+        fs::set_permissions(&source, fs::Permissions::from_mode(0o600))
+            .unwrap();
+        fs::set_permissions(&dest, fs::Permissions::from_mode(0o644)).unwrap();
+
+        // Even root may not change an immutable file, where the file system
+        // and our privileges allow making one.
+        let file = fs::File::open(&dest).unwrap();
+        if ioctl_setflags(&file, IFlags::IMMUTABLE).is_err() {
+            return;
+        }
+        let result =
+            copy_attributes(&source, &source.metadata().unwrap(), &dest);
+        ioctl_setflags(&file, IFlags::empty()).unwrap();
+
+        result.unwrap();
+        assert_eq!(
+            dest.metadata().unwrap().permissions().mode() & 0o777,
+            0o644
+        );
+    }
 }
@@ -0,0 +1,173 @@
+//! Checking a library against its template.
+//!
+//! Every media file's expected path is derived from its metadata the same way
+//! it would be when moving it into the library. Files renamed to resolve a
+//! conflict, e.g. `IMG_1234-1.jpg`, count as in place.
+
+use crate::{
+    config::Config as AppConfig, group::Group, template::Template,
+    template_context, util::file_kind,
+};
+use anyhow::Result;
+use chrono::NaiveTime;
+use std::{
+    collections::HashSet,
+    fmt,
+    path::{Path, PathBuf},
+};
+use walkdir::WalkDir;
+
+/// Something wrong with a file in a library.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Problem {
+    /// The file is not where the template puts it.
+    Misplaced { path: PathBuf, expected: PathBuf },
+    /// A sidecar without its primary.
+    OrphanSidecar(PathBuf),
+    /// The metadata of the file cannot be read.
+    Unreadable { path: PathBuf, error: String },
+}
+
+impl Problem {
+    fn path(&self) -> &Path {
+        match self {
+            Self::Misplaced { path, .. }
+            | Self::OrphanSidecar(path)
+            | Self::Unreadable { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Misplaced { path, expected } => write!(
+                f,
+                "Misplaced: {} should be {}",
+                path.display(),
+                expected.display()
+            ),
+            Self::OrphanSidecar(path) => {
+                write!(f, "Orphan sidecar: {}", path.display())
+            }
+            Self::Unreadable { path, error } => {
+                write!(f, "Unreadable: {}: {}", path.display(), error)
+            }
+        }
+    }
+}
+
+/// Checks the `groups` of media files in `library` against `template` and
+/// looks for sidecars without a primary.
+///
+/// Returns the problems found, sorted by path.
+pub(crate) fn verify(
+    library: &Path,
+    groups: &[Group],
+    template: &Template,
+    time_offset: &NaiveTime,
+    make_lowercase: bool,
+    config: &AppConfig,
+) -> Result<Vec<Problem>> {
+    let mut problems = Vec::new();
+
+    for group in groups {
+        let Some(metadata) = group.metadata() else {
+            for item in &group.items {
+                if let Err(e) = &item.metadata {
+                    problems.push(Problem::Unreadable {
+                        path: item.path.clone(),
+                        error: e.to_string(),
+                    });
+                }
+            }
+            continue;
+        };
+
+        for item in &group.items {
+            let ctx = template_context(
+                item,
+                library,
+                metadata,
+                time_offset,
+                make_lowercase,
+                config,
+            )?;
+            let expected: PathBuf =
+                library.join(template.expand(&ctx)).components().collect();
+            if !is_at(&item.path, &expected) {
+                problems.push(Problem::Misplaced {
+                    path: item.path.clone(),
+                    expected,
+                });
+            }
+        }
+    }
+
+    let sidecars: HashSet<&Path> = groups
+        .iter()
+        .flat_map(|group| &group.items)
+        .flat_map(|item| &item.sidecars)
+        .map(|sidecar| sidecar.path.as_path())
+        .collect();
+    let patterns = config.sidecar_patterns()?;
+    problems.extend(
+        WalkDir::new(library)
+            .into_iter()
+            .filter_entry(|e| {
+                e.depth() == 0
+                    || !e.file_name().to_string_lossy().starts_with('.')
+            })
+            .flatten()
+            .filter(|e| {
+                e.file_type().is_file()
+                    && file_kind(e.path()).is_none()
+                    && !sidecars.contains(e.path())
+                    && patterns.iter().any(|p| p.matches_name(e.path()))
+            })
+            .map(|e| Problem::OrphanSidecar(e.into_path())),
+    );
+
+    problems.sort_by(|a, b| a.path().cmp(b.path()));
+    Ok(problems)
+}
+
+/// Returns `true` if `path` is `expected` or `expected` with a conflict
+/// suffix.
+fn is_at(path: &Path, expected: &Path) -> bool {
+    if path == expected {
+        return true;
+    }
+
+    let (Some(stem), Some(expected_stem)) = (
+        path.file_stem().and_then(|s| s.to_str()),
+        expected.file_stem().and_then(|s| s.to_str()),
+    ) else {
+        return false;
+    };
+
+    path.parent() == expected.parent()
+        && path.extension() == expected.extension()
+        && stem
+            .strip_prefix(expected_stem)
+            .and_then(|suffix| suffix.strip_prefix('-'))
+            .is_some_and(|number| {
+                !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit())
+            })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conflict_suffix_is_in_place() {
+        let expected = Path::new("2023/08/IMG_1234.jpg");
+        assert!(is_at(expected, expected));
+        assert!(is_at(Path::new("2023/08/IMG_1234-2.jpg"), expected));
+        assert!(!is_at(Path::new("2023/08/IMG_1234-.jpg"), expected));
+        assert!(!is_at(Path::new("2023/08/IMG_1234-a.jpg"), expected));
+        assert!(!is_at(Path::new("2023/09/IMG_1234-1.jpg"), expected));
+        assert!(!is_at(Path::new("2023/08/IMG_1234-1.png"), expected));
+    }
+}
@@ -0,0 +1,257 @@
+//! Watching an ingest folder for new files.
+//!
+//! Files are processed once they and every related file, i.e. one in the same
+//! folder whose name starts with the same stem like a sidecar, were left alone
+//! for the settle time. This covers both the close after writing and sidecars
+//! arriving shortly after their primary. Sizes are polled as well for writers
+//! that don't cause events, e.g. on network shares.
+
+use crate::{resume, util::file_kind};
+use anyhow::{Result, anyhow};
+use log::{info, warn};
+use notify::{
+    EventKind, RecursiveMode, Watcher,
+    event::{AccessKind, AccessMode, ModifyKind, RenameMode},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+
+/// Default time a file and its related files must be left alone.
+pub const DEFAULT_SETTLE: f64 = 2.0;
+
+/// How often pending files are checked.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A file not processed yet.
+#[derive(Debug)]
+struct Entry {
+    /// When the file was last touched or its size changed.
+    changed: Instant,
+    size: Option<u64>,
+}
+
+/// Files seen in the watched folder that are not processed yet.
+#[derive(Debug, Default)]
+struct Pending {
+    files: HashMap<PathBuf, Entry>,
+}
+
+impl Pending {
+    /// Records that `path` was created or written to.
+    fn touch(&mut self, path: PathBuf, now: Instant) {
+        self.files
+            .entry(path)
+            .and_modify(|entry| entry.changed = now)
+            .or_insert(Entry {
+                changed: now,
+                size: None,
+            });
+    }
+
+    fn remove(&mut self, path: &Path) {
+        self.files.remove(path);
+    }
+
+    /// Removes and returns the media files that are ready for processing.
+    ///
+    /// `size` returns the current size of a file or `None` if it is gone.
+    fn take_ready(
+        &mut self,
+        now: Instant,
+        settle: Duration,
+        size: impl Fn(&Path) -> Option<u64>,
+    ) -> Vec<PathBuf> {
+        self.files.retain(|path, entry| {
+            let Some(current) = size(path) else {
+                return false;
+            };
+            if entry.size != Some(current) {
+                entry.size = Some(current);
+                entry.changed = now;
+            }
+            true
+        });
+
+        let busy: HashSet<_> = self
+            .files
+            .iter()
+            .filter(|(_, entry)| now.duration_since(entry.changed) < settle)
+            .map(|(path, _)| related_key(path))
+            .collect();
+
+        let (ready, pending): (HashMap<_, _>, HashMap<_, _>) = self
+            .files
+            .drain()
+            .partition(|(path, _)| !busy.contains(&related_key(path)));
+        self.files = pending;
+
+        let mut ready: Vec<_> = ready
+            .into_keys()
+            .filter(|path| file_kind(path).is_some())
+            .collect();
+        ready.sort();
+        ready
+    }
+}
+
+/// Files with the same key are processed together: the folder and the file
+/// name up to the first dot, so `IMG_1234.HEIC`, `IMG_1234.MOV` and
+/// `IMG_1234.HEIC.xmp` are related.
+fn related_key(path: &Path) -> (Option<PathBuf>, String) {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    (
+        path.parent().map(Path::to_path_buf),
+        name.split('.').next().unwrap_or_default().to_lowercase(),
+    )
+}
+
+/// Returns `true` if `path` or one of its folders below `source` is hidden.
+fn is_hidden(source: &Path, path: &Path) -> bool {
+    path.strip_prefix(source)
+        .unwrap_or(path)
+        .components()
+        .any(|c| c.as_os_str().to_string_lossy().starts_with('.'))
+}
+
+/// Watches `source` and calls `process` with batches of new media files.
+///
+/// `existing` files are processed like new ones. Runs until `process` fails
+/// or the user interrupts.
+pub(crate) fn watch(
+    source: &Path,
+    recursive: bool,
+    settle: Duration,
+    existing: Vec<PathBuf>,
+    mut process: impl FnMut(Vec<PathBuf>) -> Result<()>,
+) -> Result<()> {
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    watcher.watch(
+        source,
+        if recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        },
+    )?;
+    info!("Watching {} for new files.", source.display());
+
+    let mut pending = Pending::default();
+    for file in existing {
+        pending.touch(file, Instant::now());
+    }
+
+    loop {
+        match receiver.recv_timeout(POLL_INTERVAL) {
+            Ok(Ok(event)) => {
+                let now = Instant::now();
+                for path in event.paths {
+                    if is_hidden(source, &path) {
+                        continue;
+                    }
+                    match event.kind {
+                        EventKind::Remove(_)
+                        | EventKind::Modify(ModifyKind::Name(
+                            RenameMode::From,
+                        )) => pending.remove(&path),
+                        // Not reads or attribute changes, as files left in
+                        // place would be picked up again by our own reading.
+                        EventKind::Create(_)
+                        | EventKind::Modify(
+                            ModifyKind::Any
+                            | ModifyKind::Data(_)
+                            | ModifyKind::Name(_),
+                        )
+                        | EventKind::Access(AccessKind::Close(
+                            AccessMode::Write,
+                        )) if path.is_file() => pending.touch(path, now),
+                        _ => (),
+                    }
+                }
+            }
+            Ok(Err(error)) => warn!("{}", error),
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(anyhow!("Stopped watching {}.", source.display()));
+            }
+        }
+
+        let ready = pending.take_ready(Instant::now(), settle, |path| {
+            fs::metadata(path)
+                .ok()
+                .filter(|m| m.is_file())
+                .map(|m| m.len())
+        });
+        if !ready.is_empty() {
+            process(ready)?;
+        }
+        if resume::interrupted() {
+            info!("Stopped watching {}.", source.display());
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETTLE: Duration = Duration::from_secs(2);
+
+    #[test]
+    fn waits_for_related_files() {
+        let start = Instant::now();
+        let at = |seconds: u64| start + Duration::from_secs(seconds);
+        let size = |_: &Path| Some(42);
+
+        let mut pending = Pending::default();
+        pending.touch("/in/IMG_1234.HEIC".into(), at(0));
+        pending.touch("/in/IMG_9999.JPG".into(), at(0));
+        assert!(pending.take_ready(at(1), SETTLE, size).is_empty());
+
+        // The sidecar arrives late and holds back its primary only.
+        pending.touch("/in/IMG_1234.HEIC.xmp".into(), at(2));
+        assert_eq!(
+            pending.take_ready(at(3), SETTLE, size),
+            [Path::new("/in/IMG_9999.JPG")]
+        );
+
+        // Sidecars are moved with their primary, not on their own.
+        assert_eq!(
+            pending.take_ready(at(5), SETTLE, size),
+            [Path::new("/in/IMG_1234.HEIC")]
+        );
+        assert!(pending.files.is_empty());
+    }
+
+    #[test]
+    fn waits_for_size_to_settle() {
+        let start = Instant::now();
+        let at = |seconds: u64| start + Duration::from_secs(seconds);
+
+        let mut pending = Pending::default();
+        pending.touch("/in/MVI_0001.MP4".into(), at(0));
+        assert!(pending.take_ready(at(1), SETTLE, |_| Some(1)).is_empty());
+        // Still growing without any events.
+        assert!(pending.take_ready(at(3), SETTLE, |_| Some(2)).is_empty());
+        assert_eq!(pending.take_ready(at(5), SETTLE, |_| Some(2)).len(), 1);
+
+        // Files that disappear are dropped.
+        pending.touch("/in/MVI_0002.MP4".into(), at(5));
+        assert!(pending.take_ready(at(9), SETTLE, |_| None).is_empty());
+        assert!(pending.files.is_empty());
+    }
+
+    #[test]
+    fn hidden_files() {
+        let source = Path::new("/in");
+        assert!(is_hidden(source, Path::new("/in/.IMG_1234.HEIC.tmp")));
+        assert!(is_hidden(source, Path::new("/in/.stfolder/x.jpg")));
+        assert!(!is_hidden(source, Path::new("/in/IMG_1234.HEIC")));
+    }
+}
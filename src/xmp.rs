@@ -0,0 +1,238 @@
+//! XMP sidecar and embedded packet parsing.
+//!
+//! Only the handful of properties `exifmv` uses are extracted. Both the
+//! attribute (`xmp:Rating="3"`) and the element (`<xmp:Rating>3</xmp:Rating>`)
+//! serializations are understood.
+
+use anyhow::{Context, Result};
+use chrono::{NaiveDate, NaiveDateTime};
+use std::{fs, path::Path};
+
+/// Properties holding the capture date, in order of precedence.
+const DATE_PROPERTIES: &[&str] = &[
+    "exif:DateTimeOriginal",
+    "photoshop:DateCreated",
+    "xmp:CreateDate",
+];
+
+/// Properties flagging a motion photo, old and new style.
+const MOTION_PHOTO_PROPERTIES: &[&str] = &[
+    "Camera:MotionPhoto",
+    "GCamera:MotionPhoto",
+    "GCamera:MicroVideo",
+];
+
+/// Metadata read from an XMP sidecar or packet.
+#[derive(Debug, Default)]
+pub struct Xmp {
+    /// Capture time, as local time.
+    pub time_stamp: Option<NaiveDateTime>,
+    /// `xmp:Rating`, `-1` meaning rejected.
+    pub rating: Option<String>,
+    /// `xmp:Label`, e.g. a color label.
+    pub label: Option<String>,
+    /// `dc:subject` keywords.
+    pub keywords: Vec<String>,
+    /// Google/Samsung motion photo with an embedded video.
+    pub motion_photo: bool,
+}
+
+/// Reads an XMP sidecar.
+pub fn read_sidecar(path: &Path) -> Result<Xmp> {
+    let xml = fs::read_to_string(path)
+        .with_context(|| format!("Unable to read '{}'.", path.display()))?;
+
+    Ok(parse(&xml))
+}
+
+/// Extracts the properties we care about from an XMP packet.
+pub fn parse(xml: &str) -> Xmp {
+    Xmp {
+        time_stamp: DATE_PROPERTIES
+            .iter()
+            .filter_map(|name| property(xml, name))
+            .find_map(|value| parse_date(&value)),
+        // Ends up in paths, so only numbers are taken.
+        rating: property(xml, "xmp:Rating")
+            .and_then(|rating| rating.trim().parse::<i32>().ok())
+            .map(|rating| rating.to_string()),
+        label: property(xml, "xmp:Label"),
+        keywords: keywords(xml),
+        motion_photo: MOTION_PHOTO_PROPERTIES
+            .iter()
+            .any(|name| property(xml, name).as_deref() == Some("1")),
+    }
+}
+
+/// Returns the XMP packet embedded in a file's `data`, if any.
+pub fn find_packet(data: &[u8]) -> Option<&str> {
+    const START: &[u8] = b"<x:xmpmeta";
+    const END: &[u8] = b"</x:xmpmeta>";
+
+    let start = data.windows(START.len()).position(|w| w == START)?;
+    let length =
+        data[start..].windows(END.len()).position(|w| w == END)? + END.len();
+
+    std::str::from_utf8(&data[start..start + length]).ok()
+}
+
+/// Returns the value of a simple property in attribute or element form.
+fn property(xml: &str, name: &str) -> Option<String> {
+    xml.match_indices(name).find_map(|(index, _)| {
+        let after = xml[index + name.len()..].trim_start();
+
+        match xml[..index].chars().next_back()? {
+            // Element: `<name>value</name>`.
+            '<' => {
+                let content = after.strip_prefix('>')?;
+                let value = content[..content.find('<')?].trim();
+                (!value.is_empty()).then(|| unescape(value))
+            }
+            // Attribute: `name="value"`.
+            c if c.is_whitespace() => {
+                let value = after.strip_prefix('=')?.trim_start();
+                let quote =
+                    value.chars().next().filter(|q| "\"'".contains(*q))?;
+                let value = &value[1..];
+                Some(unescape(&value[..value.find(quote)?]))
+            }
+            _ => None,
+        }
+    })
+}
+
+/// Returns the `rdf:li` items of the `dc:subject` bag.
+fn keywords(xml: &str) -> Vec<String> {
+    let Some(start) = xml.find("<dc:subject") else {
+        return Vec::new();
+    };
+    let subject = &xml[start..];
+    let subject =
+        &subject[..subject.find("</dc:subject>").unwrap_or(subject.len())];
+
+    subject
+        .split("<rdf:li")
+        .skip(1)
+        .filter_map(|item| {
+            let item = &item[item.find('>')? + 1..];
+            let value = item[..item.find('<')?].trim();
+            (!value.is_empty()).then(|| unescape(value))
+        })
+        .collect()
+}
+
+/// Parses an XMP date, ignoring any time zone designator.
+///
+/// XMP allows truncated dates like `2023-08-15` or `2023-08-15T14:30`; missing
+/// components are treated as zero.
+fn parse_date(value: &str) -> Option<NaiveDateTime> {
+    let prefix = |length: usize| value.get(..length);
+
+    prefix(19)
+        .and_then(|s| {
+            NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S").ok()
+        })
+        .or_else(|| {
+            prefix(16).and_then(|s| {
+                NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M").ok()
+            })
+        })
+        .or_else(|| {
+            prefix(10)
+                .and_then(|s| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok())
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+}
+
+/// Resolves the predefined XML entities.
+fn unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DARKTABLE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:exif="http://ns.adobe.com/exif/1.0/"
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+    xmlns:dc="http://purl.org/dc/elements/1.1/"
+   exif:DateTimeOriginal="2023-08-15T14:30:00.00"
+   xmp:CreateDate="2023-06-01T09:00:00"
+   xmp:Rating="4">
+   <xmp:Label>Red</xmp:Label>
+   <dc:subject>
+    <rdf:Bag>
+     <rdf:li>Berlin</rdf:li>
+     <rdf:li>Rock &amp; Roll</rdf:li>
+    </rdf:Bag>
+   </dc:subject>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>"#;
+
+    #[test]
+    fn parse_sidecar() {
+        let xmp = parse(DARKTABLE);
+        assert_eq!(
+            xmp.time_stamp,
+            NaiveDate::from_ymd_opt(2023, 8, 15)
+                .unwrap()
+                .and_hms_opt(14, 30, 0)
+        );
+        assert_eq!(xmp.rating.as_deref(), Some("4"));
+        assert_eq!(xmp.label.as_deref(), Some("Red"));
+        assert_eq!(xmp.keywords, ["Berlin", "Rock & Roll"]);
+    }
+
+    #[test]
+    fn parse_truncated_dates() {
+        let xmp = parse(r#"<rdf:Description xmp:CreateDate="2023-08-15"/>"#);
+        assert_eq!(
+            xmp.time_stamp,
+            NaiveDate::from_ymd_opt(2023, 8, 15)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn parse_numeric_ratings_only() {
+        let xmp = parse(r#"<rdf:Description xmp:Rating="-1"/>"#);
+        assert_eq!(xmp.rating.as_deref(), Some("-1"));
+
+        let xmp = parse(r#"<rdf:Description xmp:Rating="../../x"/>"#);
+        assert!(xmp.rating.is_none());
+    }
+
+    #[test]
+    fn parse_empty() {
+        let xmp = parse("<x:xmpmeta/>");
+        assert!(xmp.time_stamp.is_none());
+        assert!(xmp.rating.is_none());
+        assert!(xmp.keywords.is_empty());
+        assert!(!xmp.motion_photo);
+    }
+
+    #[test]
+    fn find_motion_photo_packet() {
+        let mut jpeg =
+            b"\xff\xd8\xff\xe1\x00\x00http://ns.adobe.com/xap/1.0/\0".to_vec();
+        jpeg.extend_from_slice(
+            br#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:Description GCamera:MicroVideo="1" GCamera:MicroVideoOffset="4242"/></x:xmpmeta>"#,
+        );
+        jpeg.extend_from_slice(b"\xff\xd9");
+
+        let xmp = parse(find_packet(&jpeg).unwrap());
+        assert!(xmp.motion_photo);
+        assert!(find_packet(b"\xff\xd8\xff\xd9").is_none());
+    }
+}